@@ -0,0 +1,90 @@
+use anyhow::Result;
+use rusql_alchemy::prelude::*;
+use sqlx::FromRow;
+
+#[derive(FromRow, Clone, Debug, Default, Model)]
+struct User {
+    #[model(primary_key = true, auto = true)]
+    id: Integer, // `auto` becomes auto_increment on mysql
+
+    #[model(size = 50, unique = true)]
+    name: String,
+
+    #[model(size = 255, unique = true)]
+    email: Option<String>,
+
+    #[model(size = 255)]
+    password: Password,
+
+    admin: Boolean,
+
+    age: Integer,
+
+    weight: Float,
+}
+
+#[derive(FromRow, Debug, Default, Model, Clone)]
+struct Product {
+    #[model(primary_key = true, auto = true)]
+    id: Integer,
+
+    #[model(size = 50)]
+    name: String,
+
+    price: Float,
+
+    description: Option<Text>,
+
+    #[model(default = true)]
+    is_sel: Boolean,
+
+    #[model(foreign_key = "User.id")]
+    owner: Integer,
+
+    #[model(default = "now")]
+    at: DateTime,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let conn = Database::new().await?.conn;
+
+    migrate!([User, Product], &conn);
+
+    User::create(
+        kwargs!(
+            name = "joe",
+            email = "24nomeniavo@gmail.com",
+            password = Password::from("strongpassword"),
+            age = 19,
+            weight = 80.1
+        ),
+        &conn,
+    )
+    .await;
+
+    let users = User::all(&conn).await;
+    println!("1: {:#?}", users);
+
+    // mysql binds the `?` placeholders in order
+    let user = User::get(kwargs!(email == "24nomeniavo@gmail.com"), &conn)
+        .await
+        .filter(|user| user.password.verify("strongpassword"));
+    println!("2: {:#?}", user);
+
+    Product::create(
+        kwargs!(
+            name = "tomato".to_string(),
+            price = 1000.0,
+            owner = user.clone().unwrap().id
+        ),
+        &conn,
+    )
+    .await;
+
+    let products = Product::filter(kwargs!(owner == user.unwrap().id), &conn).await;
+    println!("3: {:#?}", products);
+    products.delete(&conn).await;
+
+    Ok(())
+}
@@ -14,7 +14,7 @@ struct User_ {
     email: Option<String>,
 
     #[model(size = 255)]
-    password: String,
+    password: Password,
 
     #[model(default = "user")]
     role: String,
@@ -55,7 +55,7 @@ async fn main() -> Result<()> {
     User_ {
         name: "johnDoe@gmail.com".to_string(),
         email: Some("21john@gmail.com".to_string()),
-        password: "p455w0rd".to_string(),
+        password: "p455w0rd".into(),
         age: 18,
         weight: 60.0,
         ..Default::default()
@@ -70,7 +70,7 @@ async fn main() -> Result<()> {
         kwargs!(
             name = "joe",
             email = "24nomeniavo@gmail.com",
-            password = "strongpassword",
+            password = Password::from("strongpassword"),
             age = 19,
             weight = 80.1
         ),
@@ -81,20 +81,16 @@ async fn main() -> Result<()> {
     let users = User_::all(&conn).await;
     println!("1: {:#?}", users);
 
-    if let Some(mut user) = User_::get(
-        kwargs!(email == "24nomeniavo@gmail.com").and(kwargs!(password == "strongpassword")),
-        &conn,
-    )
-    .await
+    if let Some(mut user) = User_::get(kwargs!(email == "24nomeniavo@gmail.com"), &conn)
+        .await
+        .filter(|user| user.password.verify("strongpassword"))
     {
         user.role = "admin".into();
         user.update(&conn).await;
     }
-    let user = User_::get(
-        kwargs!(email == "24nomeniavo@gmail.com").and(kwargs!(password == "strongpassword")),
-        &conn,
-    )
-    .await;
+    let user = User_::get(kwargs!(email == "24nomeniavo@gmail.com"), &conn)
+        .await
+        .filter(|user| user.password.verify("strongpassword"));
 
     println!("2: {:#?}", user);
 
@@ -14,7 +14,7 @@ struct User {
     email: Option<String>,
 
     #[model(size = 255)]
-    password: String,
+    password: Password,
 
     admin: Boolean,
 
@@ -54,7 +54,7 @@ async fn main() -> Result<()> {
     User {
         name: "johnDoe@gmail.com".to_string(),
         email: Some("21john@gmail.com".to_string()),
-        password: "p455w0rd".to_string(),
+        password: "p455w0rd".into(),
         age: 18,
         weight: 60.0,
         ..Default::default()
@@ -69,7 +69,7 @@ async fn main() -> Result<()> {
         kwargs!(
             name = "joe",
             email = "24nomeniavo@gmail.com",
-            password = "strongpassword",
+            password = Password::from("strongpassword"),
             age = 19,
             weight = 80.1
         ),
@@ -80,20 +80,16 @@ async fn main() -> Result<()> {
     let users = User::all(&conn).await;
     println!("1: {:#?}", users);
 
-    if let Some(mut user) = User::get(
-        kwargs!(email == "24nomeniavo@gmail.com").and(kwargs!(password == "strongpassword")),
-        &conn,
-    )
-    .await
+    if let Some(mut user) = User::get(kwargs!(email == "24nomeniavo@gmail.com"), &conn)
+        .await
+        .filter(|user| user.password.verify("strongpassword"))
     {
         user.admin = Boolean::r#true();
         user.update(&conn).await;
     }
-    let user = User::get(
-        kwargs!(email == "24nomeniavo@gmail.com").and(kwargs!(password == "strongpassword")),
-        &conn,
-    )
-    .await;
+    let user = User::get(kwargs!(email == "24nomeniavo@gmail.com"), &conn)
+        .await
+        .filter(|user| user.password.verify("strongpassword"));
 
     println!("2: {:#?}", user);
 
@@ -0,0 +1,10 @@
+use anyhow::Result;
+use rusql_alchemy::cli::Cli;
+use rusql_alchemy::db::migration::Migrations;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::from_env()?;
+    let migrations = Migrations::from_dir(&cli.dir)?;
+    cli.run(&migrations).await
+}
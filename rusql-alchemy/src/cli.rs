@@ -0,0 +1,222 @@
+//! The `rusql` command line, managing migrations outside of the application binary.
+//!
+//! The `rusql` binary runs the SQL migrations of a directory (see `Migrations::from_dir`).
+//! To manage the models of an application too, build a small binary of your own that
+//! registers them and hands over to `run`:
+//!
+//! ```rust
+//! // src/bin/migrate.rs
+//! #[tokio::main]
+//! async fn main() -> anyhow::Result<()> {
+//!     let cli = Cli::from_env()?;
+//!     let migrations = Migrations::from_dir(&cli.dir)?
+//!         .with(Migration::model::<User>(1))
+//!         .model::<Product>();
+//!     cli.run(&migrations).await
+//! }
+//! ```
+//!
+//! The database is the one of `DATABASE_URL`, as for `Database::new`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+
+use crate::db::codegen::generate_models;
+use crate::db::migration::Migrations;
+use crate::{Database, DatabaseConfig};
+
+const USAGE: &str = "\
+usage: rusql [--dir <path>] [--env <name>] <command>
+
+commands:
+  migrate            apply the pending migrations
+  rollback [<n>]     revert the last n applied migrations (default 1)
+  status             list the migrations and whether they are applied
+  plan               print the statements `migrate` would execute
+  generate <name>    create empty up and down files for a new migration
+  models             print the models of the tables of the database
+
+options:
+  --dir <path>       the migrations directory (default $MIGRATIONS_DIR or `migrations`)
+  --env <name>       enable an environment, can be repeated";
+
+/// A command of the `rusql` command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Applies the pending migrations.
+    Migrate,
+    /// Reverts the given number of applied migrations.
+    Rollback(usize),
+    /// Lists the migrations and whether they are applied.
+    Status,
+    /// Prints the statements `Migrate` would execute.
+    Plan,
+    /// Creates the files of a new migration with the given name.
+    Generate(String),
+    /// Prints the model definitions of the tables of the database.
+    Models,
+    /// Prints the usage.
+    Help,
+}
+
+/// A parsed command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cli {
+    /// The command to run.
+    pub command: Command,
+    /// The migrations directory.
+    pub dir: PathBuf,
+    /// The enabled environments, see `DatabaseConfig::environment`.
+    pub environments: Vec<String>,
+}
+
+impl Cli {
+    /// Parses the arguments of the process.
+    pub fn from_env() -> Result<Self> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Parses the arguments, without the program name.
+    ///
+    /// # Example
+    /// ```
+    /// let cli = Cli::parse(["rollback", "2"].map(String::from))?;
+    /// assert_eq!(cli.command, Command::Rollback(2));
+    /// ```
+    pub fn parse<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut dir = std::env::var("MIGRATIONS_DIR").map(PathBuf::from).ok();
+        let mut environments = Vec::new();
+        let mut words = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--dir" => dir = Some(args.next().context("--dir expects a path")?.into()),
+                "--env" => environments.push(args.next().context("--env expects a name")?),
+                "-h" | "--help" => words.insert(0, "help".to_string()),
+                _ => words.push(arg),
+            }
+        }
+        let command = match words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            [] | ["help", ..] => Command::Help,
+            ["migrate"] => Command::Migrate,
+            ["rollback"] => Command::Rollback(1),
+            ["rollback", n] => Command::Rollback(
+                n.parse()
+                    .with_context(|| format!("{n} is not a number of migrations"))?,
+            ),
+            ["status"] => Command::Status,
+            ["plan"] => Command::Plan,
+            ["generate", name] => Command::Generate(name.to_string()),
+            ["models"] => Command::Models,
+            _ => bail!("unexpected arguments `{}`\n\n{USAGE}", words.join(" ")),
+        };
+        Ok(Self {
+            command,
+            dir: dir.unwrap_or_else(|| PathBuf::from("migrations")),
+            environments,
+        })
+    }
+
+    /// Runs the command against the given migrations.
+    pub async fn run(&self, migrations: &Migrations) -> Result<()> {
+        match &self.command {
+            Command::Help => println!("{USAGE}"),
+            Command::Generate(name) => {
+                for path in self.generate(name)? {
+                    println!("created {}", path.display());
+                }
+            }
+            Command::Migrate => {
+                let db = self.database().await?;
+                let applied = db.migrate(migrations).await?;
+                if applied.is_empty() {
+                    println!("no pending migration");
+                }
+                for version in applied {
+                    println!("applied {version}");
+                }
+            }
+            Command::Rollback(n) => {
+                let db = self.database().await?;
+                let reverted = db.rollback(*n).await?;
+                if reverted.is_empty() {
+                    println!("no applied migration");
+                }
+                for version in reverted {
+                    println!("reverted {version}");
+                }
+            }
+            Command::Status => {
+                let db = self.database().await?;
+                let applied = Migrations::applied(&db.conn).await?;
+                let enabled = migrations.for_environments(&self.environments);
+                for migration in migrations.iter() {
+                    let status = if applied.contains(&migration.version) {
+                        "applied"
+                    } else if enabled.iter().any(|m| m.version == migration.version) {
+                        "pending"
+                    } else {
+                        "skipped"
+                    };
+                    println!("{status:<8} {} {}", migration.version, migration.name);
+                }
+                for version in applied {
+                    if !migrations.iter().any(|m| m.version == version) {
+                        println!("{:<8} {version} (unknown migration)", "applied");
+                    }
+                }
+            }
+            Command::Models => {
+                let db = self.database().await?;
+                print!("{}", generate_models(&db.conn).await?);
+            }
+            Command::Plan => {
+                let db = self.database().await?;
+                for sql in db.plan(migrations).await? {
+                    println!("{sql}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn database(&self) -> Result<Database> {
+        let config = self
+            .environments
+            .iter()
+            .fold(DatabaseConfig::new(), |config, env| config.environment(env));
+        Database::with_config(config).await
+    }
+
+    /// Creates `<timestamp>_<name>.up.sql` and `<timestamp>_<name>.down.sql` in the migrations
+    /// directory and returns their paths.
+    fn generate(&self, name: &str) -> Result<Vec<PathBuf>> {
+        let name = name
+            .trim()
+            .to_lowercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        if name.is_empty() {
+            bail!("the migration name is empty");
+        }
+        let version = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        fs::create_dir_all(&self.dir)?;
+        let mut paths = Vec::new();
+        for (kind, comment) in [("up", "apply"), ("down", "revert")] {
+            let path = self.dir.join(format!("{version}_{name}.{kind}.sql"));
+            fs::write(&path, format!("-- {comment} {name}\n"))?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+/// Parses the arguments of the process and runs the command against `migrations`.
+pub async fn run(migrations: Migrations) -> Result<()> {
+    Cli::from_env()?.run(&migrations).await
+}
@@ -0,0 +1,152 @@
+//! Audit log of the models setting `Model::AUDITED`.
+//!
+//! The writes of an audited model made by `create`, `set`, `save_or_update`,
+//! `update_returning`, `delete_cascade`, `delete_where` and `update_where` are recorded in the
+//! `_audit_log` table, created by `Model::migrate` next to the table of the model, in the same
+//! transaction as the write. The derived `save`, `update` and `delete` are not recorded unless
+//! `Model::audit` is called after them. Each entry holds:
+//!
+//! - `model`, the table of the model
+//! - `pk`, the primary key of the row, when it is known
+//! - `action`, `create`, `update` or `delete`
+//! - `changes`, the written columns as a JSON object, with `***` for the `SENSITIVE` columns
+//! - `actor`, the actor set with `with_actor`, if any
+//! - `created_at`, the unix timestamp of the write, in seconds
+
+use std::future::Future;
+
+use sqlx::{Any, Executor};
+
+use super::backend::Backend;
+use super::models::{Arg, Condition, Model};
+use super::{instrument, QuerySettings};
+
+/// The table of the audit log.
+pub const AUDIT_TABLE: &str = "_audit_log";
+
+/// The value recorded in place of the values of the `SENSITIVE` columns.
+pub const REDACTED: &str = "***";
+
+tokio::task_local! {
+    static ACTOR: Option<String>;
+}
+
+/// A write recorded in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+
+impl AuditAction {
+    /// Returns the name stored in the `action` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+        }
+    }
+}
+
+/// Runs `future` recording `actor` as the author of the audited writes it makes.
+///
+/// # Example
+/// ```rust
+/// with_actor(session.user_id.to_string(), invoice.update(&conn)).await;
+/// ```
+pub async fn with_actor<F: Future>(actor: impl Into<String>, future: F) -> F::Output {
+    ACTOR.scope(Some(actor.into()), future).await
+}
+
+/// Returns the actor set with `with_actor` for the current task.
+pub fn actor() -> Option<String> {
+    ACTOR.try_with(Clone::clone).ok().flatten()
+}
+
+/// Returns the statement creating the audit log table.
+pub(crate) fn create_table() -> String {
+    format!(
+        "create table if not exists {AUDIT_TABLE} (
+            model varchar(255) not null,
+            pk text,
+            action varchar(16) not null,
+            changes text not null,
+            actor text,
+            created_at bigint not null
+        );"
+    )
+}
+
+/// Returns the values of the field conditions as a JSON object, with the values of the
+/// `SENSITIVE` columns of `M` redacted.
+pub(crate) fn changes<M: Model + ?Sized>(kw: &[Condition]) -> serde_json::Value {
+    kw.iter()
+        .filter_map(|condition| match condition {
+            Condition::FieldCondition { field, .. } if M::SENSITIVE.contains(&field.as_str()) => {
+                Some((field.clone(), REDACTED.into()))
+            }
+            Condition::FieldCondition { field, value, .. } => {
+                Some((field.clone(), value.clone().into()))
+            }
+            _ => None,
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// Records a write of the table `model` in the audit log, with the placeholders of `backend`
+/// and the `settings` of the pool of `executor`.
+///
+/// # Returns
+/// `true` if the entry was recorded, `false` otherwise.
+pub(crate) async fn record<'c, E>(
+    executor: E,
+    backend: Backend,
+    settings: &QuerySettings,
+    model: &str,
+    action: AuditAction,
+    pk: Option<Arg>,
+    changes: serde_json::Value,
+) -> bool
+where
+    E: Executor<'c, Database = Any>,
+{
+    let query = settings.tagged(format!(
+        "insert into {AUDIT_TABLE} (model, pk, action, changes, actor, created_at) values ({}, {}, {}, {}, {}, {});",
+        backend.placeholder(1),
+        backend.placeholder(2),
+        backend.placeholder(3),
+        backend.placeholder(4),
+        backend.placeholder(5),
+        backend.placeholder(6)
+    ));
+    // the key is stored as text, whatever the type of the column
+    let pk = pk.map(|pk| match pk {
+        Arg::Text(pk) => pk,
+        pk => serde_json::Value::from(pk).to_string(),
+    });
+    let created_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let args = vec![
+        Arg::from(model),
+        Arg::from(pk),
+        Arg::from(action.as_str()),
+        Arg::from(changes.to_string()),
+        Arg::from(actor()),
+        Arg::from(created_at),
+    ];
+    let mut stream = sqlx::query(&query);
+    binds!(args, stream);
+    instrument(
+        settings,
+        AUDIT_TABLE,
+        "record",
+        &query,
+        stream.execute(executor),
+    )
+    .await
+    .is_ok()
+}
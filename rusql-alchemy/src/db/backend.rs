@@ -0,0 +1,343 @@
+//! Detection of the database behind a connection and of the SQL features it supports.
+
+use std::fmt;
+
+use crate::Connection;
+
+/// The database engine a connection talks to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+    MySql,
+}
+
+impl Backend {
+    /// Detects the backend from the scheme of a database url.
+    ///
+    /// # Example
+    /// ```
+    /// assert_eq!(Backend::from_url("postgres://localhost/app"), Some(Backend::Postgres));
+    /// ```
+    pub fn from_url(url: &str) -> Option<Self> {
+        if url.starts_with("sqlite") {
+            Some(Backend::Sqlite)
+        } else if url.starts_with("postgres") {
+            Some(Backend::Postgres)
+        } else if url.starts_with("mysql") || url.starts_with("mariadb") {
+            Some(Backend::MySql)
+        } else {
+            None
+        }
+    }
+
+    /// Detects the backend of a connection pool from the url it was opened with.
+    ///
+    /// Defaults to `Sqlite`, the default feature of the crate, for unknown schemes.
+    pub fn of(conn: &Connection) -> Self {
+        Self::from_url(conn.connect_options().database_url.as_str()).unwrap_or(Backend::Sqlite)
+    }
+
+    /// Returns the placeholder of the parameter at `index`, counted from 1.
+    ///
+    /// MySQL does not number its placeholders, the parameters are bound in the order the
+    /// placeholders appear.
+    ///
+    /// # Example
+    /// ```
+    /// assert_eq!(Backend::Postgres.placeholder(2), "$2");
+    /// assert_eq!(Backend::MySql.placeholder(2), "?");
+    /// ```
+    pub fn placeholder(self, index: usize) -> String {
+        match self {
+            Backend::Sqlite => format!("?{index}"),
+            Backend::Postgres => format!("${index}"),
+            Backend::MySql => "?".to_string(),
+        }
+    }
+
+    /// Rewrites the `CREATE TABLE` statement of a model for the backend.
+    ///
+    /// The derive writes the statement for sqlite and postgres; on mysql `autoincrement`
+    /// becomes `auto_increment` and the columns of type `uuid` are stored as `char(36)`.
+    pub fn create_table(self, sql: &str) -> String {
+        match self {
+            Backend::MySql => {
+                let sql = replace_word(sql, "autoincrement", "auto_increment");
+                replace_type(&sql, "uuid", "char(36)")
+            }
+            _ => sql.to_string(),
+        }
+    }
+
+    /// Returns the statement creating a unique index if it does not exist.
+    ///
+    /// MySQL has no `create index if not exists`, see `ModelSchema::up` for how the unique
+    /// indexes of a new table are created there.
+    pub fn create_unique_index(self, table: &str, name: &str, columns: &[&str]) -> String {
+        let columns = columns.join(", ");
+        match self {
+            Backend::MySql => format!("alter table {table} add unique key {name} ({columns});"),
+            _ => format!("create unique index if not exists {name} on {table} ({columns});"),
+        }
+    }
+
+    /// Returns the function returning a random value, e.g. for `order by random()`.
+    pub fn random(self) -> &'static str {
+        match self {
+            Backend::MySql => "rand()",
+            _ => "random()",
+        }
+    }
+
+    /// Returns the SQL features supported by the backend.
+    pub fn capabilities(self) -> Capabilities {
+        match self {
+            Backend::Sqlite => Capabilities {
+                backend: self,
+                returning: true,
+                for_update: false,
+                skip_locked: false,
+                partial_indexes: true,
+                write_limit: false,
+                transactional_ddl: true,
+                listen_notify: false,
+            },
+            Backend::Postgres => Capabilities {
+                backend: self,
+                returning: true,
+                for_update: true,
+                skip_locked: true,
+                partial_indexes: true,
+                write_limit: false,
+                transactional_ddl: true,
+                listen_notify: true,
+            },
+            Backend::MySql => Capabilities {
+                backend: self,
+                returning: false,
+                for_update: true,
+                skip_locked: true,
+                partial_indexes: false,
+                write_limit: true,
+                transactional_ddl: false,
+                listen_notify: false,
+            },
+        }
+    }
+}
+
+/// Replaces the whole-word, case-insensitive occurrences of `word` outside of quotes.
+fn replace_word(sql: &str, word: &str, replacement: &str) -> String {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut replaced = String::with_capacity(sql.len());
+    let mut quote = None;
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        match (c, quote) {
+            ('\'' | '"' | '`', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {}
+        }
+        let preceded = replaced.chars().next_back().is_some_and(is_word);
+        let matches = quote.is_none()
+            && !preceded
+            && rest.len() >= word.len()
+            && rest.is_char_boundary(word.len())
+            && rest[..word.len()].eq_ignore_ascii_case(word)
+            && !rest[word.len()..].chars().next().is_some_and(is_word);
+        if matches {
+            replaced.push_str(replacement);
+            rest = &rest[word.len()..];
+        } else {
+            replaced.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    replaced
+}
+
+/// Replaces the column types equal to `ty`, case-insensitive, in a `CREATE TABLE` statement.
+///
+/// Only the word following the name of a column is a type, so a column named like the type
+/// keeps its name.
+fn replace_type(sql: &str, ty: &str, replacement: &str) -> String {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut replaced = String::with_capacity(sql.len());
+    let mut depth = 0;
+    // the words read so far in the current column definition
+    let mut words = 0;
+    let mut start = 0;
+    while let Some(c) = sql[start..].chars().next() {
+        let end = match c {
+            '\'' | '"' | '`' => sql[start + 1..]
+                .find(c)
+                .map_or(sql.len(), |i| start + 1 + i + 1),
+            c if is_word(c) => sql[start..]
+                .find(|c| !is_word(c))
+                .map_or(sql.len(), |i| start + i),
+            c => start + c.len_utf8(),
+        };
+        let token = &sql[start..end];
+        match c {
+            '(' => {
+                depth += 1;
+                if depth == 1 {
+                    words = 0;
+                }
+            }
+            ')' => depth -= 1,
+            ',' if depth == 1 => words = 0,
+            c if c.is_whitespace() => {}
+            _ if depth == 1 => {
+                words += 1;
+                if words == 2 && token.eq_ignore_ascii_case(ty) {
+                    replaced.push_str(replacement);
+                    start = end;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        replaced.push_str(token);
+        start = end;
+    }
+    replaced
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Backend::Sqlite => "sqlite",
+            Backend::Postgres => "postgres",
+            Backend::MySql => "mysql",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The SQL features supported by a backend, returned by `Database::capabilities`.
+///
+/// The `Display` implementation prints a one-line report, handy as a startup banner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The backend the capabilities belong to.
+    pub backend: Backend,
+    /// `INSERT`/`UPDATE`/`DELETE ... RETURNING`.
+    pub returning: bool,
+    /// `SELECT ... FOR UPDATE` / `FOR SHARE` row locks.
+    pub for_update: bool,
+    /// `SKIP LOCKED` on row locks.
+    pub skip_locked: bool,
+    /// `CREATE INDEX ... WHERE`.
+    pub partial_indexes: bool,
+    /// `LIMIT` on `DELETE` and `UPDATE`.
+    pub write_limit: bool,
+    /// DDL statements can be rolled back inside a transaction.
+    pub transactional_ddl: bool,
+    /// `LISTEN` / `NOTIFY`.
+    pub listen_notify: bool,
+}
+
+impl Capabilities {
+    /// Returns the capabilities of the backend of a connection pool.
+    pub fn of(conn: &Connection) -> Self {
+        Backend::of(conn).capabilities()
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let features = [
+            ("returning", self.returning),
+            ("for_update", self.for_update),
+            ("skip_locked", self.skip_locked),
+            ("partial_indexes", self.partial_indexes),
+            ("write_limit", self.write_limit),
+            ("transactional_ddl", self.transactional_ddl),
+            ("listen_notify", self.listen_notify),
+        ];
+        let supported = features
+            .iter()
+            .filter(|(_, supported)| *supported)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "{}: {supported}", self.backend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_backend_of_a_url() {
+        assert_eq!(Backend::from_url("sqlite://app.db"), Some(Backend::Sqlite));
+        assert_eq!(
+            Backend::from_url("postgresql://localhost/app"),
+            Some(Backend::Postgres)
+        );
+        assert_eq!(
+            Backend::from_url("mariadb://localhost/app"),
+            Some(Backend::MySql)
+        );
+        assert_eq!(Backend::from_url("mssql://localhost/app"), None);
+    }
+
+    #[test]
+    fn numbers_the_placeholders() {
+        assert_eq!(Backend::Sqlite.placeholder(3), "?3");
+        assert_eq!(Backend::Postgres.placeholder(3), "$3");
+        assert_eq!(Backend::MySql.placeholder(3), "?");
+    }
+
+    #[test]
+    fn replaces_whole_words_outside_of_quotes() {
+        assert_eq!(
+            replace_word(
+                "id integer primary key AUTOINCREMENT, note text default 'autoincrement'",
+                "autoincrement",
+                "auto_increment"
+            ),
+            "id integer primary key auto_increment, note text default 'autoincrement'"
+        );
+        assert_eq!(
+            replace_word("token uuid, uuids text, my_uuid uuid", "uuid", "char(36)"),
+            "token char(36), uuids text, my_uuid char(36)"
+        );
+        assert_eq!(replace_word("`uuid` uuid", "uuid", "x"), "`uuid` x");
+    }
+
+    #[test]
+    fn rewrites_the_create_table_for_mysql() {
+        let sql =
+            "create table if not exists user (id integer primary key autoincrement, token uuid);";
+        assert_eq!(Backend::Sqlite.create_table(sql), sql);
+        assert_eq!(
+            Backend::MySql.create_table(sql),
+            "create table if not exists user (id integer primary key auto_increment, token char(36));"
+        );
+    }
+
+    #[test]
+    fn rewrites_only_the_uuid_types() {
+        assert_eq!(
+            Backend::MySql.create_table(
+                "create table if not exists uuid (uuid uuid primary key, `uuid` UUID not null, \
+                 note varchar(36) default 'uuid', foreign key (uuid) references uuid(uuid));"
+            ),
+            "create table if not exists uuid (uuid char(36) primary key, `uuid` char(36) not null, \
+             note varchar(36) default 'uuid', foreign key (uuid) references uuid(uuid));"
+        );
+    }
+
+    #[test]
+    fn reports_the_capabilities() {
+        assert_eq!(
+            Backend::MySql.capabilities().to_string(),
+            "mysql: for_update, skip_locked, write_limit"
+        );
+        assert!(!Backend::Sqlite.capabilities().for_update);
+    }
+}
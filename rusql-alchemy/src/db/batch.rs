@@ -0,0 +1,407 @@
+//! Batched writes, coalescing the queued saves and deletes into multi-row statements.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::backend::Backend;
+use super::encryption;
+use super::models::{insert_values, primary_key_arg, Arg, Model};
+use super::{instrument, QuerySettings};
+use crate::{Connection, Error};
+
+/// A queued statement, merging the consecutive writes of the same kind on the same table.
+enum Write {
+    Insert {
+        table: &'static str,
+        /// The `ENCRYPTED` columns of the model, encrypted by `flush`.
+        encrypted: &'static [&'static str],
+        columns: Vec<String>,
+        /// The conflict columns of an upsert, `None` for a plain insert.
+        conflict: Option<Vec<String>>,
+        rows: Vec<Vec<Arg>>,
+    },
+    Delete {
+        table: &'static str,
+        pk: &'static str,
+        keys: Vec<Arg>,
+    },
+}
+
+/// Queues `save` and `delete` calls and writes them with as few statements as possible, in one
+/// transaction, e.g. for ingestion pipelines inserting thousands of rows per second.
+///
+/// The consecutive saves (or upserts) of a model become one `insert` with a row per instance,
+/// and the consecutive deletes one `delete ... where pk in (...)`, split so that no statement
+/// exceeds the parameter limit of the database. The writes of different models keep their order, so
+/// the rows referenced by foreign keys can be queued first.
+///
+/// The rows are written as serialized, without the audit log and the events of the
+/// `Model` methods. The `ENCRYPTED` columns are encrypted by `flush` with the cipher of the
+/// pool, and an instance that cannot be serialized or encrypted makes `flush` fail.
+///
+/// # Example
+/// ```rust
+/// let mut batch = WriteBatch::new();
+/// for line in lines {
+///     batch.save(&Reading::parse(line));
+/// }
+/// batch.delete(&stale);
+/// let written = batch.flush(&conn).await?;
+/// ```
+#[derive(Default)]
+pub struct WriteBatch {
+    writes: Vec<Write>,
+    /// The error of the first instance that could not be queued.
+    error: Option<anyhow::Error>,
+}
+
+impl WriteBatch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the insert of an instance.
+    ///
+    /// An unset primary key (`None`, `0` or an empty string) is left to the database, like
+    /// the computed columns and the unset `DB_GENERATED` columns.
+    pub fn save<M: Model + Serialize>(&mut self, instance: &M) -> &mut Self {
+        match row_values(instance) {
+            Ok((columns, values)) => self.push_insert(M::NAME, M::ENCRYPTED, columns, None, values),
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Queues the upsert of an instance: the row is inserted, or updated when a row with the
+    /// same `conflict` columns exists, e.g. to sync an external dataset.
+    ///
+    /// The `conflict` columns must be the primary key or a unique index. Mysql uses any unique
+    /// key of the table instead.
+    pub fn upsert<M: Model + Serialize>(&mut self, instance: &M, conflict: &[&str]) -> &mut Self {
+        match row_values(instance) {
+            Ok((columns, values)) => {
+                let conflict = conflict.iter().map(|column| column.to_string()).collect();
+                self.push_insert(M::NAME, M::ENCRYPTED, columns, Some(conflict), values)
+            }
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Keeps the error of an instance that could not be queued for `flush`.
+    fn fail(&mut self, err: anyhow::Error) -> &mut Self {
+        self.error.get_or_insert(err);
+        self
+    }
+
+    /// Queues the insert of a row of `table`, whose `encrypted` columns are encrypted by `flush`.
+    pub(crate) fn insert(
+        &mut self,
+        table: &'static str,
+        encrypted: &'static [&'static str],
+        columns: Vec<String>,
+        values: Vec<Arg>,
+    ) -> &mut Self {
+        self.push_insert(table, encrypted, columns, None, values)
+    }
+
+    fn push_insert(
+        &mut self,
+        table: &'static str,
+        encrypted: &'static [&'static str],
+        columns: Vec<String>,
+        conflict: Option<Vec<String>>,
+        values: Vec<Arg>,
+    ) -> &mut Self {
+        match self.writes.last_mut() {
+            Some(Write::Insert {
+                table: queued_table,
+                columns: queued,
+                conflict: queued_conflict,
+                rows,
+                ..
+            }) if *queued_table == table && *queued == columns && *queued_conflict == conflict => {
+                rows.push(values)
+            }
+            _ => self.writes.push(Write::Insert {
+                table,
+                encrypted,
+                columns,
+                conflict,
+                rows: vec![values],
+            }),
+        }
+        self
+    }
+
+    /// Queues the delete of an instance, ignored if its primary key is unset.
+    pub fn delete<M: Model + Serialize>(&mut self, instance: &M) -> &mut Self {
+        let Some(key) = primary_key_arg(instance) else {
+            return self;
+        };
+        match self.writes.last_mut() {
+            Some(Write::Delete { table, keys, .. }) if *table == M::NAME => keys.push(key),
+            _ => self.writes.push(Write::Delete {
+                table: M::NAME,
+                pk: M::PK,
+                keys: vec![key],
+            }),
+        }
+        self
+    }
+
+    /// Returns the number of queued writes.
+    pub fn len(&self) -> usize {
+        self.writes
+            .iter()
+            .map(|write| match write {
+                Write::Insert { rows, .. } => rows.len(),
+                Write::Delete { keys, .. } => keys.len(),
+            })
+            .sum()
+    }
+
+    /// Returns `true` if no write is queued.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Runs the queued writes in one transaction and empties the batch.
+    ///
+    /// # Returns
+    /// The number of rows written.
+    ///
+    /// # Errors
+    /// Returns the error of the first instance that could not be queued, in which case nothing
+    /// is written and the batch is emptied. Otherwise returns the error of the first row that
+    /// cannot be encrypted or the `Error` of the first failing statement, the transaction is
+    /// rolled back and the writes stay queued.
+    pub async fn flush(&mut self, conn: &Connection) -> Result<u64> {
+        if let Some(err) = self.error.take() {
+            self.writes.clear();
+            return Err(err);
+        }
+        if self.writes.is_empty() {
+            return Ok(0);
+        }
+        let backend = Backend::of(conn);
+        let settings = QuerySettings::of(conn);
+        let mut tx = conn.begin().await?;
+        let mut written = 0;
+        for write in &self.writes {
+            for (table, query, args) in statements(write, backend, &settings)? {
+                let query = settings.tagged(query);
+                let mut stream = sqlx::query(&query);
+                binds!(args.clone(), stream);
+                let result = instrument(
+                    &settings,
+                    table,
+                    "write_batch",
+                    &query,
+                    stream.execute(&mut *tx),
+                )
+                .await
+                .map_err(|err| Error::new(&settings, &query, args, err))?;
+                written += result.rows_affected();
+            }
+        }
+        tx.commit().await?;
+        self.writes.clear();
+        Ok(written)
+    }
+}
+
+/// Returns the columns and the values of the row of an instance, see `insert_values`.
+///
+/// # Errors
+/// Returns an error if the instance does not serialize to a row.
+pub(crate) fn row_values<M: Model + Serialize>(instance: &M) -> Result<(Vec<String>, Vec<Arg>)> {
+    insert_values(instance)
+        .ok_or_else(|| anyhow::anyhow!("a row of `{}` cannot be serialized", M::NAME))
+}
+
+/// Returns the clause turning the insert of `columns` into an upsert on the `conflict` columns,
+/// updating the other columns.
+fn upsert_clause(backend: Backend, columns: &[String], conflict: &[String]) -> String {
+    let updated = columns
+        .iter()
+        .filter(|column| !conflict.contains(column))
+        .collect::<Vec<_>>();
+    match backend {
+        Backend::MySql => {
+            // mysql has no `do nothing`, a column is set to itself instead
+            let set = if updated.is_empty() {
+                format!("{column} = {column}", column = columns[0])
+            } else {
+                updated
+                    .iter()
+                    .map(|column| format!("{column} = values({column})"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            format!(" on duplicate key update {set}")
+        }
+        _ if updated.is_empty() => format!(" on conflict ({}) do nothing", conflict.join(", ")),
+        _ => format!(
+            " on conflict ({}) do update set {}",
+            conflict.join(", "),
+            updated
+                .iter()
+                .map(|column| format!("{column} = excluded.{column}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Returns the largest number of parameters of a statement.
+fn max_params(backend: Backend) -> usize {
+    match backend {
+        Backend::Sqlite => 32766,
+        Backend::Postgres | Backend::MySql => 65535,
+    }
+}
+
+/// Returns the statements of a queued write, with their table and arguments.
+///
+/// # Errors
+/// Returns an error if a row cannot be encrypted.
+fn statements(
+    write: &Write,
+    backend: Backend,
+    settings: &QuerySettings,
+) -> Result<Vec<(&'static str, String, Vec<Arg>)>> {
+    let max_params = max_params(backend);
+    let statements = match write {
+        Write::Insert {
+            table,
+            columns,
+            rows,
+            ..
+        } if columns.is_empty() => rows
+            .iter()
+            .map(|_| {
+                // every column is filled by the database
+                let query = format!("insert into {table} default values;");
+                (*table, query, Vec::new())
+            })
+            .collect(),
+        Write::Insert {
+            table,
+            encrypted,
+            columns,
+            conflict,
+            rows,
+        } => {
+            let mut rows = rows.clone();
+            for row in &mut rows {
+                encryption::encrypt_row(settings, encrypted, columns, row)?;
+            }
+            let upsert = conflict
+                .as_ref()
+                .map(|conflict| upsert_clause(backend, columns, conflict))
+                .unwrap_or_default();
+            let chunk = (max_params / columns.len()).max(1);
+            rows.chunks(chunk)
+                .map(|rows| {
+                    let mut index = 0;
+                    let values = rows
+                        .iter()
+                        .map(|row| {
+                            let row = row
+                                .iter()
+                                .map(|_| {
+                                    index += 1;
+                                    backend.placeholder(index)
+                                })
+                                .collect::<Vec<_>>();
+                            format!("({})", row.join(", "))
+                        })
+                        .collect::<Vec<_>>();
+                    let query = format!(
+                        "insert into {table} ({}) values {}{upsert};",
+                        columns.join(", "),
+                        values.join(", ")
+                    );
+                    (*table, query, rows.concat())
+                })
+                .collect()
+        }
+        Write::Delete { table, pk, keys } => keys
+            .chunks(max_params)
+            .map(|keys| {
+                let placeholders = (1..=keys.len())
+                    .map(|index| backend.placeholder(index))
+                    .collect::<Vec<_>>();
+                let query = format!(
+                    "delete from {table} where {pk} in ({});",
+                    placeholders.join(", ")
+                );
+                (*table, query, keys.to_vec())
+            })
+            .collect(),
+    };
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{memory, EncryptedUser, TestCipher, User};
+    use crate::Database;
+
+    #[tokio::test]
+    async fn fails_the_flush_of_a_row_that_cannot_be_encrypted() {
+        let conn = memory().await;
+        let db = Database::from_pool(conn.clone());
+        assert!(EncryptedUser::migrate(&conn).await);
+        let joe = EncryptedUser {
+            name: "joe".into(),
+            ..EncryptedUser::default()
+        };
+        assert!(
+            EncryptedUser::bulk_upsert(std::slice::from_ref(&joe), &["id"], &conn)
+                .await
+                .is_err()
+        );
+        let mut batch = WriteBatch::new();
+        batch.save(&joe);
+        let err = batch.flush(&conn).await.unwrap_err();
+        assert!(err.to_string().contains("no cipher"), "{err}");
+        assert_eq!(batch.len(), 1);
+        assert!(EncryptedUser::all(&conn).await.is_empty());
+
+        db.set_cipher(TestCipher);
+        assert_eq!(batch.flush(&conn).await.unwrap(), 1);
+        let stored: (String,) = sqlx::query_as("select name from encrypted_user;")
+            .fetch_one(&conn)
+            .await
+            .unwrap();
+        assert_eq!(stored.0, "enc:eoj");
+        assert_eq!(EncryptedUser::all(&conn).await[0].name, "joe");
+    }
+
+    #[tokio::test]
+    async fn writes_the_queued_rows() {
+        let conn = memory().await;
+        assert!(User::migrate(&conn).await);
+        let mut batch = WriteBatch::new();
+        batch
+            .save(&User::new("joe", 19))
+            .save(&User::new("jane", 17))
+            .upsert(
+                &User {
+                    id: 1,
+                    ..User::new("joe", 20)
+                },
+                &["id"],
+            );
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch.flush(&conn).await.unwrap(), 3);
+        let ages = User::all(&conn)
+            .await
+            .iter()
+            .map(|user| user.age)
+            .collect::<Vec<_>>();
+        assert_eq!(ages, vec![20, 17]);
+    }
+}
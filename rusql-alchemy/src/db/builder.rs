@@ -0,0 +1,724 @@
+//! Builders for statements that need more options than the plain `Model` methods.
+
+use std::marker::PhantomData;
+
+use anyhow::Result;
+use sqlx::any::AnyRow;
+use sqlx::{Any, FromRow, Row};
+
+use super::audit::{self, AuditAction};
+use super::backend::Backend;
+use super::decode::DecodeError;
+use super::encryption;
+use super::models::{
+    decode_rows, decode_rows_lenient, select, selected_columns, writable, Arg, Condition, Model,
+};
+#[cfg(not(feature = "sea-query"))]
+use super::models::{select_columns_query, Query};
+#[cfg(feature = "sea-query")]
+use super::sea::{delete_statement, select_statement, update_statement};
+use super::{instrument, QuerySettings};
+use crate::{Connection, Error, Transaction};
+
+/// The rows a write applies to.
+pub(crate) enum Rows<'a> {
+    /// The rows matching the conditions, at most `limit` of them when set.
+    Matching(&'a Vec<Condition>, Option<usize>),
+    /// The rows of these primary keys.
+    Keys(&'a [Arg]),
+}
+
+/// Builds the `where` clause of the rows of a write, numbering its placeholders after `offset`.
+///
+/// Backends without `LIMIT` on writes get an id subquery instead:
+/// `where pk in (select pk from table where ... limit n)`.
+#[cfg(not(feature = "sea-query"))]
+fn write_filter<M: Model>(backend: Backend, rows: Rows, offset: usize) -> (String, Vec<Arg>) {
+    let (kw, limit) = match rows {
+        Rows::Matching(kw, limit) => (kw, limit),
+        Rows::Keys(keys) => {
+            let placeholders = (1..=keys.len())
+                .map(|index| backend.placeholder(offset + index))
+                .collect::<Vec<_>>();
+            return (
+                format!(" where {id} in ({})", placeholders.join(", "), id = M::PK),
+                keys.to_vec(),
+            );
+        }
+    };
+    let (fields, args) = kw.to_select_query_from(backend, offset);
+    let filter = if fields.is_empty() {
+        String::new()
+    } else {
+        format!(" where {fields}")
+    };
+    let filter = match limit {
+        None => filter,
+        Some(limit) if backend.capabilities().write_limit => format!("{filter} limit {limit}"),
+        Some(limit) => format!(
+            " where {id} in (select {id} from {table_name}{filter} limit {limit})",
+            id = M::PK,
+            table_name = M::NAME,
+        ),
+    };
+    (filter, args)
+}
+
+/// Renders the `select` of `columns` of the rows of `M` matching `kw`, without the final `;`,
+/// restricted to `M::DEFAULT_SCOPE` when `scoped` is set.
+///
+/// The row locks are left out on the backends without them.
+#[cfg(not(feature = "sea-query"))]
+fn select_statement<M: Model>(
+    backend: Backend,
+    columns: &str,
+    kw: &Vec<Condition>,
+    scoped: bool,
+    limit: Option<usize>,
+    lock: Option<&'static str>,
+    skip_locked: bool,
+) -> (String, Vec<Arg>) {
+    let capabilities = backend.capabilities();
+    let (mut query, args) = select_columns_query::<M>(backend, columns, kw, scoped);
+    if let Some(limit) = limit {
+        query = format!("{query} limit {limit}");
+    }
+    if let (Some(lock), true) = (lock, capabilities.for_update) {
+        query = format!("{query} {lock}");
+        if skip_locked && capabilities.skip_locked {
+            query = format!("{query} skip locked");
+        }
+    }
+    (query, args)
+}
+
+/// Renders the `delete` of the rows of `M`, without the final `;`.
+#[cfg(not(feature = "sea-query"))]
+fn delete_statement<M: Model>(backend: Backend, rows: Rows) -> (String, Vec<Arg>) {
+    let (filter, args) = write_filter::<M>(backend, rows, 0);
+    (
+        format!("delete from {table_name}{filter}", table_name = M::NAME),
+        args,
+    )
+}
+
+/// Renders the `update` of the rows of `M` to `values`, without the final `;`.
+#[cfg(not(feature = "sea-query"))]
+fn update_statement<M: Model>(
+    backend: Backend,
+    values: &Vec<Condition>,
+    rows: Rows,
+) -> (String, Vec<Arg>) {
+    let (placeholders, mut args) = values.to_update_query(backend);
+    let (filter, filter_args) = write_filter::<M>(backend, rows, args.len());
+    args.extend(filter_args);
+    (
+        format!(
+            "update {table_name} set {placeholders}{filter}",
+            table_name = M::NAME
+        ),
+        args,
+    )
+}
+
+/// The number of keys in the `where pk in (...)` of an audited bulk write.
+const AUDITED_CHUNK: usize = 1000;
+
+/// Runs a bulk write of an audited model in a transaction: the keys of the rows are selected
+/// first, then the statement rendered by `write` is run on those rows only and each of them is
+/// recorded in the audit log with `changes`.
+///
+/// # Returns
+/// The number of written rows, `None` if a statement failed and the transaction was rolled
+/// back.
+async fn audited_write<M: Model>(
+    conn: &Connection,
+    rows: Rows<'_>,
+    write: impl Fn(Rows) -> (String, Vec<Arg>),
+    action: AuditAction,
+    changes: serde_json::Value,
+) -> Option<u64> {
+    let operation = match action {
+        AuditAction::Update => "update_where",
+        _ => "delete_where",
+    };
+    let backend = Backend::of(conn);
+    let settings = QuerySettings::of(conn);
+    let mut tx = conn.begin().await.ok()?;
+    let keys = match rows {
+        Rows::Keys(keys) => keys.to_vec(),
+        Rows::Matching(kw, limit) => {
+            let (query, args) =
+                select_statement::<M>(backend, M::PK, kw, false, limit, None, false);
+            let query = settings.tagged(format!("{query};"));
+            let mut stream = sqlx::query(&query);
+            binds!(args, stream);
+            instrument(
+                &settings,
+                M::NAME,
+                operation,
+                &query,
+                stream.fetch_all(&mut *tx),
+            )
+            .await
+            .ok()?
+            .iter()
+            .map(|row| {
+                row.try_get::<i64, _>(0)
+                    .map(Arg::from)
+                    .or_else(|_| row.try_get::<String, _>(0).map(Arg::from))
+            })
+            .collect::<sqlx::Result<Vec<_>>>()
+            .ok()?
+        }
+    };
+
+    let mut written = 0;
+    for chunk in keys.chunks(AUDITED_CHUNK) {
+        let (query, args) = write(Rows::Keys(chunk));
+        let query = settings.tagged(format!("{query};"));
+        let mut stream = sqlx::query(&query);
+        binds!(args, stream);
+        written += instrument(
+            &settings,
+            M::NAME,
+            operation,
+            &query,
+            stream.execute(&mut *tx),
+        )
+        .await
+        .ok()?
+        .rows_affected();
+    }
+    for key in keys {
+        if !audit::record(
+            &mut *tx,
+            backend,
+            &settings,
+            M::NAME,
+            action,
+            Some(key),
+            changes.clone(),
+        )
+        .await
+        {
+            return None;
+        }
+    }
+    tx.commit().await.ok()?;
+    Some(written)
+}
+
+/// A `DELETE` statement created by `Model::delete_where`.
+///
+/// The rows deleted from an audited model are recorded one by one in the audit log, in the
+/// transaction of the delete.
+///
+/// # Example
+/// ```
+/// let deleted = Log::delete_where(kwargs!(level == "debug"))
+///     .limit(1000)
+///     .execute_in_batches(&conn)
+///     .await;
+/// println!("Deleted {deleted} rows");
+/// ```
+pub struct DeleteBuilder<M> {
+    kw: Vec<Condition>,
+    limit: Option<usize>,
+    _model: PhantomData<M>,
+}
+
+impl<M: Model> DeleteBuilder<M> {
+    pub(crate) fn new(kw: Vec<Condition>) -> Self {
+        Self {
+            kw,
+            limit: None,
+            _model: PhantomData,
+        }
+    }
+
+    /// Restricts the statement to at most `limit` rows.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Executes the statement once.
+    ///
+    /// # Returns
+    /// The number of deleted rows, `0` if the statement failed.
+    pub async fn execute(&self, conn: &Connection) -> u64 {
+        let backend = Backend::of(conn);
+        if M::AUDITED {
+            let changes = serde_json::Value::Object(Default::default());
+            return audited_write::<M>(
+                conn,
+                Rows::Matching(&self.kw, self.limit),
+                |rows| delete_statement::<M>(backend, rows),
+                AuditAction::Delete,
+                changes,
+            )
+            .await
+            .unwrap_or(0);
+        }
+        let (query, args) = delete_statement::<M>(backend, Rows::Matching(&self.kw, self.limit));
+        let settings = QuerySettings::of(conn);
+        let query = settings.tagged(format!("{query};"));
+        let mut stream = sqlx::query(&query);
+        binds!(args, stream);
+        instrument(
+            &settings,
+            M::NAME,
+            "delete_where",
+            &query,
+            stream.execute(conn),
+        )
+        .await
+        .map_or(0, |result| result.rows_affected())
+    }
+
+    /// Executes the statement repeatedly until fewer than `limit` rows are deleted, so each
+    /// statement only holds its locks for one batch.
+    ///
+    /// Without a limit this is the same as `execute`.
+    ///
+    /// # Returns
+    /// The total number of deleted rows.
+    pub async fn execute_in_batches(&self, conn: &Connection) -> u64 {
+        let mut total = 0;
+        loop {
+            let deleted = self.execute(conn).await;
+            total += deleted;
+            match self.limit {
+                Some(limit) if deleted >= limit as u64 && deleted > 0 => continue,
+                _ => return total,
+            }
+        }
+    }
+}
+
+/// An `UPDATE` statement created by `Model::update_where`.
+///
+/// The rows updated in an audited model are recorded one by one in the audit log with the set
+/// values, in the transaction of the update.
+///
+/// # Example
+/// ```
+/// let updated = User::update_where(kwargs!(age < 18), kwargs!(role = "minor"))
+///     .limit(500)
+///     .execute(&conn)
+///     .await;
+/// println!("Updated {updated} rows");
+/// ```
+pub struct UpdateBuilder<M> {
+    kw: Vec<Condition>,
+    values: Vec<Condition>,
+    limit: Option<usize>,
+    _model: PhantomData<M>,
+}
+
+impl<M: Model> UpdateBuilder<M> {
+    pub(crate) fn new(kw: Vec<Condition>, values: Vec<Condition>) -> Self {
+        Self {
+            kw,
+            values,
+            limit: None,
+            _model: PhantomData,
+        }
+    }
+
+    /// Restricts the statement to at most `limit` rows.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Executes the statement. The values of the `COMPUTED` columns are ignored, only the
+    /// database writes them.
+    ///
+    /// # Returns
+    /// The number of updated rows, `0` if the statement failed or there is nothing to set.
+    pub async fn execute(&self, conn: &Connection) -> u64 {
+        let values = writable::<M>(self.values.clone());
+        if values.is_empty() {
+            return 0;
+        }
+        let Ok(values) = encryption::encrypt::<M>(&QuerySettings::of(conn), values) else {
+            return 0;
+        };
+        let backend = Backend::of(conn);
+        if M::AUDITED {
+            return audited_write::<M>(
+                conn,
+                Rows::Matching(&self.kw, self.limit),
+                |rows| update_statement::<M>(backend, &values, rows),
+                AuditAction::Update,
+                audit::changes::<M>(&values),
+            )
+            .await
+            .unwrap_or(0);
+        }
+        let (query, args) =
+            update_statement::<M>(backend, &values, Rows::Matching(&self.kw, self.limit));
+        let settings = QuerySettings::of(conn);
+        let query = settings.tagged(format!("{query};"));
+        let mut stream = sqlx::query(&query);
+        binds!(args, stream);
+        instrument(
+            &settings,
+            M::NAME,
+            "update_where",
+            &query,
+            stream.execute(conn),
+        )
+        .await
+        .map_or(0, |result| result.rows_affected())
+    }
+}
+
+/// A `SELECT` statement created by `Model::select`, with an optional limit, row locks and
+/// comment.
+///
+/// The row locks are held until the end of the transaction, so a locked select is run with
+/// `fetch_in`. They are left out on sqlite, which does not support them and serializes the
+/// write transactions anyway.
+///
+/// # Example
+/// ```
+/// db.transaction(|tx| {
+///     Box::pin(async move {
+///         let items = Item::select(kwargs!(id == 7)).for_update().fetch_in(tx).await?;
+///         tx.execute("update item set stock = stock - 1 where id = ?", args!(7)).await
+///     })
+/// })
+/// .await?;
+/// ```
+pub struct SelectBuilder<M> {
+    kw: Vec<Condition>,
+    limit: Option<usize>,
+    lock: Option<&'static str>,
+    skip_locked: bool,
+    deferred: bool,
+    comment: Option<String>,
+    _model: PhantomData<M>,
+}
+
+impl<M: Model> SelectBuilder<M> {
+    pub(crate) fn new(kw: Vec<Condition>) -> Self {
+        Self {
+            kw,
+            limit: None,
+            lock: None,
+            skip_locked: false,
+            deferred: false,
+            comment: None,
+            _model: PhantomData,
+        }
+    }
+
+    /// Restricts the statement to at most `limit` rows.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Locks the selected rows for writing, `FOR UPDATE`.
+    pub fn for_update(mut self) -> Self {
+        self.lock = Some("for update");
+        self
+    }
+
+    /// Locks the selected rows against writes of the other transactions, `FOR SHARE`.
+    pub fn for_share(mut self) -> Self {
+        self.lock = Some("for share");
+        self
+    }
+
+    /// Skips the rows locked by other transactions instead of waiting for them, e.g. so
+    /// several workers can pick jobs from the same queue.
+    pub fn skip_locked(mut self) -> Self {
+        self.skip_locked = true;
+        self
+    }
+
+    /// Selects the `DEFERRED` columns too, see `Model::with_deferred`.
+    pub fn with_deferred(mut self) -> Self {
+        self.deferred = true;
+        self
+    }
+
+    /// Prefixes the statement with `/* comment */`, after the query tag, so it can be traced in
+    /// `pg_stat_statements` and the slow-query logs.
+    ///
+    /// # Example
+    /// ```
+    /// let orders = Order::select(kwargs!(status == "open"))
+    ///     .comment("dashboard:recent_orders")
+    ///     .limit(20)
+    ///     .fetch(&conn)
+    ///     .await;
+    /// ```
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    fn query(&self, backend: Backend, settings: &QuerySettings) -> (String, Vec<Arg>) {
+        self.query_columns(&selected_columns::<M>(self.deferred), backend, settings)
+    }
+
+    fn query_columns(
+        &self,
+        columns: &str,
+        backend: Backend,
+        settings: &QuerySettings,
+    ) -> (String, Vec<Arg>) {
+        let (query, args) = select_statement::<M>(
+            backend,
+            columns,
+            &self.kw,
+            true,
+            self.limit,
+            self.lock,
+            self.skip_locked,
+        );
+        (
+            settings.commented(format!("{query};"), self.comment.as_deref()),
+            args,
+        )
+    }
+
+    /// Executes the statement with `expression` as its only column, e.g. an aggregate, and
+    /// decodes the value of the first row.
+    ///
+    /// A `NULL`, e.g. the `sum` of no rows, decodes as `None` when `T` is an `Option`, and fails
+    /// otherwise. Comparisons are integers on sqlite and mysql, so they are read as `Boolean`.
+    ///
+    /// # Errors
+    /// Returns an `Error` if the statement fails, returns no row, or its value does not
+    /// decode as `T`.
+    ///
+    /// # Example
+    /// ```
+    /// let adults: i64 = User::select(kwargs!(age >= 18)).fetch_scalar("count(*)", &conn).await?;
+    /// let total: Option<f64> = Order::select(kwargs!(status == "paid"))
+    ///     .fetch_scalar("sum(total)", &conn)
+    ///     .await?;
+    /// let any_admin: Boolean = User::select(kwargs!(role == "admin"))
+    ///     .fetch_scalar("count(*) > 0", &conn)
+    ///     .await?;
+    /// ```
+    pub async fn fetch_scalar<T>(&self, expression: &str, conn: &Connection) -> Result<T>
+    where
+        T: for<'r> sqlx::Decode<'r, Any> + sqlx::Type<Any>,
+    {
+        let settings = QuerySettings::of(conn);
+        let (query, args) = self.query_columns(expression, Backend::of(conn), &settings);
+        let mut stream = sqlx::query(&query);
+        binds!(args.clone(), stream);
+        instrument(
+            &settings,
+            M::NAME,
+            "fetch_scalar",
+            &query,
+            stream.fetch_one(conn),
+        )
+        .await
+        .and_then(|row| row.try_get::<T, _>(0))
+        .map_err(|err| Error::new(&settings, &query, args, err).into())
+    }
+}
+
+impl<M> SelectBuilder<M>
+where
+    M: Model + Unpin + for<'r> FromRow<'r, AnyRow>,
+{
+    /// Executes the statement on the pool, where the row locks are released at once.
+    ///
+    /// # Returns
+    /// The selected rows, empty if the statement failed.
+    pub async fn fetch(&self, conn: &Connection) -> Vec<M> {
+        let settings = QuerySettings::of(conn);
+        let (query, args) = self.query(Backend::of(conn), &settings);
+        let mut stream = sqlx::query(&query);
+        binds!(args, stream);
+        instrument(&settings, M::NAME, "select", &query, stream.fetch_all(conn))
+            .await
+            .and_then(|rows| decode_rows::<M>(&settings, rows))
+            .unwrap_or_default()
+    }
+
+    /// Executes the statement in a transaction, which holds the row locks until it ends.
+    ///
+    /// # Errors
+    /// Returns an `Error` if the statement fails.
+    pub async fn fetch_in(&self, tx: &mut Transaction) -> Result<Vec<M>> {
+        let settings = tx.settings();
+        let (query, args) = self.query(tx.backend(), &settings);
+        let mut stream = sqlx::query(&query);
+        binds!(args.clone(), stream);
+        instrument(
+            &settings,
+            M::NAME,
+            "select",
+            &query,
+            stream.fetch_all(tx.conn()),
+        )
+        .await
+        .and_then(|rows| decode_rows::<M>(&settings, rows))
+        .map_err(|err| Error::new(&settings, &query, args, err).into())
+    }
+
+    /// Executes the statement on the pool, skipping the rows that cannot be decoded instead of
+    /// failing, e.g. to read a table holding some malformed legacy rows.
+    ///
+    /// # Returns
+    /// The decoded rows, and the errors of the skipped rows with their index and column.
+    ///
+    /// # Errors
+    /// Returns an `Error` if the statement fails.
+    ///
+    /// # Example
+    /// ```
+    /// let (users, skipped) = User::select(vec![]).fetch_lenient(&conn).await?;
+    /// for err in skipped {
+    ///     eprintln!("{err}");
+    /// }
+    /// ```
+    pub async fn fetch_lenient(&self, conn: &Connection) -> Result<(Vec<M>, Vec<DecodeError>)> {
+        let settings = QuerySettings::of(conn);
+        let (query, args) = self.query(Backend::of(conn), &settings);
+        let mut stream = sqlx::query(&query);
+        binds!(args.clone(), stream);
+        let rows = instrument(&settings, M::NAME, "select", &query, stream.fetch_all(conn))
+            .await
+            .map_err(|err| Error::new(&settings, &query, args, err))?;
+        Ok(decode_rows_lenient(&settings, rows))
+    }
+}
+
+/// The queries of a model ignoring its `DEFAULT_SCOPE`, created by `Model::unscoped`.
+///
+/// # Example
+/// ```
+/// let posts = Post::unscoped().all(&conn).await;
+/// ```
+pub struct Unscoped<M> {
+    _model: PhantomData<M>,
+}
+
+impl<M> Unscoped<M> {
+    pub(crate) fn new() -> Self {
+        Self {
+            _model: PhantomData,
+        }
+    }
+}
+
+impl<M> Unscoped<M>
+where
+    M: Model + Unpin + for<'r> FromRow<'r, AnyRow>,
+{
+    /// Retrieves every row, see `Model::all`.
+    pub async fn all(&self, conn: &Connection) -> Vec<M> {
+        select(Vec::new(), false, false, "all", conn).await
+    }
+
+    /// Retrieves the rows matching the conditions, see `Model::filter`.
+    pub async fn filter(&self, kw: Vec<Condition>, conn: &Connection) -> Vec<M> {
+        select(kw, false, false, "filter", conn).await
+    }
+
+    /// Retrieves the first row matching the conditions, see `Model::get`.
+    pub async fn get(&self, kw: Vec<Condition>, conn: &Connection) -> Option<M> {
+        self.filter(kw, conn).await.into_iter().next()
+    }
+}
+
+/// The queries of a model selecting its `DEFERRED` columns too, created by
+/// `Model::with_deferred`.
+///
+/// # Example
+/// ```
+/// let articles = Article::with_deferred().all(&conn).await;
+/// ```
+pub struct WithDeferred<M> {
+    _model: PhantomData<M>,
+}
+
+impl<M> WithDeferred<M> {
+    pub(crate) fn new() -> Self {
+        Self {
+            _model: PhantomData,
+        }
+    }
+}
+
+impl<M> WithDeferred<M>
+where
+    M: Model + Unpin + for<'r> FromRow<'r, AnyRow>,
+{
+    /// Retrieves every row, see `Model::all`.
+    pub async fn all(&self, conn: &Connection) -> Vec<M> {
+        select(Vec::new(), true, true, "all", conn).await
+    }
+
+    /// Retrieves the rows matching the conditions, see `Model::filter`.
+    pub async fn filter(&self, kw: Vec<Condition>, conn: &Connection) -> Vec<M> {
+        select(kw, true, true, "filter", conn).await
+    }
+
+    /// Retrieves the first row matching the conditions, see `Model::get`.
+    pub async fn get(&self, kw: Vec<Condition>, conn: &Connection) -> Option<M> {
+        self.filter(kw, conn).await.into_iter().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::models::{any_of_each, And, Or};
+    use crate::db::testing::{memory, User};
+
+    use super::*;
+
+    fn field(field: &str, comparison_operator: &str, value: impl Into<Arg>) -> Vec<Condition> {
+        vec![Condition::field(field, comparison_operator, value)]
+    }
+
+    // the builders are rendered by `sea-query` with the feature, `Model::filter` never is
+    #[tokio::test]
+    async fn selects_the_rows_of_filter() {
+        let conn = memory().await;
+        assert!(User::migrate(&conn).await);
+        let users = vec![
+            User::new("joe", 19),
+            User::new("jane", 17),
+            User::new("z", 10),
+        ];
+        assert!(User::seed(users, &conn).await);
+
+        let cases = vec![
+            [field("age", "=", 10), field("name", "=", "z")].concat(),
+            [field("age", "=", 10), field("name", "=", "joe")].concat(),
+            [field("age", ">", 9), field("age", "<", 18)]
+                .concat()
+                .or(field("name", "=", "joe")),
+            field("name", "=", "joe").and(field("age", "=", 19)),
+            any_of_each(&[
+                [field("name", "=", "z"), field("name", "=", "jane")].concat(),
+                field("age", ">=", 17),
+            ]),
+            field("name", "!=", None::<&str>),
+        ];
+        for kw in cases {
+            let filtered = User::filter(kw.clone(), &conn).await;
+            assert_eq!(
+                User::select(kw.clone()).fetch(&conn).await,
+                filtered,
+                "{kw:?}"
+            );
+        }
+        let kw = [field("age", "=", 10), field("name", "=", "z")].concat();
+        assert_eq!(User::select(kw).fetch(&conn).await.len(), 1);
+    }
+}
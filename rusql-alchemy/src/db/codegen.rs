@@ -0,0 +1,339 @@
+//! Generation of model definitions from an existing database.
+//!
+//! The tables of the connected database are introspected (columns, primary keys, unique
+//! columns and foreign keys) and rendered as `#[derive(Model)]` structs, to start using the
+//! crate on a schema that was not created by it. The output is meant to be reviewed: default
+//! values, checks and multi-column constraints are not carried over.
+
+use anyhow::Result;
+use sqlx::Row;
+
+use super::backend::Backend;
+use super::migration::MIGRATIONS_TABLE;
+use super::schema::{fetch, type_family};
+use super::{instrument, QuerySettings};
+use crate::{Connection, Error};
+
+/// A column of an introspected table.
+struct Column {
+    name: String,
+    data_type: String,
+    nullable: bool,
+    size: Option<i64>,
+    primary_key: bool,
+    auto: bool,
+    unique: bool,
+    foreign_key: Option<(String, String)>,
+}
+
+/// Returns the model definitions of every table of the connected database.
+///
+/// Each struct is named after its table with the first letter capitalized, so that the
+/// derived table name matches the existing one.
+///
+/// # Example
+/// ```
+/// let source = generate_models(&db.conn).await?;
+/// std::fs::write("src/models.rs", source)?;
+/// ```
+pub async fn generate_models(conn: &Connection) -> Result<String> {
+    let mut source = String::from("use rusql_alchemy::prelude::*;\n");
+    for table in tables(conn).await? {
+        source.push('\n');
+        source.push_str(&generate_model(conn, &table).await?);
+    }
+    Ok(source)
+}
+
+/// Returns the model definition of a table.
+pub async fn generate_model(conn: &Connection, table: &str) -> Result<String> {
+    let columns = introspect(conn, table).await?;
+    let mut fields = Vec::new();
+    for column in &columns {
+        let mut attributes = Vec::new();
+        if column.primary_key {
+            attributes.push("primary_key = true".to_string());
+        }
+        if column.auto && Backend::of(conn) != Backend::Postgres {
+            attributes.push("auto = true".to_string());
+        }
+        if let Some(size) = column.size {
+            attributes.push(format!("size = {size}"));
+        }
+        if column.unique {
+            attributes.push("unique = true".to_string());
+        }
+        if let Some((table, column)) = &column.foreign_key {
+            attributes.push(format!("foreign_key = \"{}.{column}\"", struct_name(table)));
+        }
+
+        let mut field = String::new();
+        if !attributes.is_empty() {
+            field.push_str(&format!("    #[model({})]\n", attributes.join(", ")));
+        }
+        let rust_type = rust_type(column, Backend::of(conn));
+        let rust_type = if column.nullable && !column.primary_key {
+            format!("Option<{rust_type}>")
+        } else {
+            rust_type
+        };
+        field.push_str(&format!("    {}: {rust_type},\n", field_name(&column.name)));
+        fields.push(field);
+    }
+    Ok(format!(
+        "// generated from the `{table}` table\n\
+         #[derive(FromRow, Clone, Debug, Default, Model)]\n\
+         struct {} {{\n{}}}\n",
+        struct_name(table),
+        fields.join("\n")
+    ))
+}
+
+/// Returns the tables of the connected database, without the tables of the crate.
+async fn tables(conn: &Connection) -> Result<Vec<String>> {
+    let query = match Backend::of(conn) {
+        Backend::Sqlite => "select name from sqlite_master \
+             where type = 'table' and name not like 'sqlite_%' order by name;"
+            .to_string(),
+        Backend::Postgres => "select table_name::text from information_schema.tables \
+             where table_schema = current_schema() and table_type = 'BASE TABLE' \
+             order by table_name;"
+            .to_string(),
+        Backend::MySql => "select table_name from information_schema.tables \
+             where table_schema = database() and table_type = 'BASE TABLE' \
+             order by table_name;"
+            .to_string(),
+    };
+    let rows = instrument(
+        &QuerySettings::of(conn),
+        "schema",
+        "introspect",
+        &query,
+        sqlx::query(&query).fetch_all(conn),
+    )
+    .await
+    .map_err(|err| Error::new(&QuerySettings::of(conn), &query, Vec::new(), err))?;
+    Ok(rows
+        .iter()
+        .map(|row| row.get::<String, _>(0))
+        .filter(|table| !table.starts_with(MIGRATIONS_TABLE))
+        .collect())
+}
+
+async fn introspect(conn: &Connection, table: &str) -> Result<Vec<Column>> {
+    match Backend::of(conn) {
+        Backend::Sqlite => introspect_sqlite(conn, table).await,
+        backend => introspect_information_schema(conn, table, backend).await,
+    }
+}
+
+async fn introspect_sqlite(conn: &Connection, table: &str) -> Result<Vec<Column>> {
+    let rows = fetch(
+        conn,
+        "select name, type, \"notnull\", pk from pragma_table_info(?1) order by cid;",
+        table,
+    )
+    .await?;
+    let primary_keys = rows.iter().filter(|row| row.get::<i64, _>(3) > 0).count();
+    let mut columns = rows
+        .iter()
+        .map(|row| {
+            let data_type: String = row.get(1);
+            let primary_key = row.get::<i64, _>(3) > 0;
+            Column {
+                name: row.get(0),
+                size: type_size(&data_type),
+                // an `integer primary key` is an alias of the rowid
+                auto: primary_key && primary_keys == 1 && data_type.eq_ignore_ascii_case("integer"),
+                nullable: row.get::<i64, _>(2) == 0,
+                data_type,
+                primary_key,
+                unique: false,
+                foreign_key: None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let unique = fetch(
+        conn,
+        "select min(ii.name) from pragma_index_list(?1) il \
+         join pragma_index_info(il.name) ii \
+         where il.\"unique\" = 1 and il.origin <> 'pk' \
+         group by il.name having count(*) = 1;",
+        table,
+    )
+    .await?;
+    for row in unique {
+        let name: String = row.get(0);
+        if let Some(column) = columns.iter_mut().find(|c| c.name == name) {
+            column.unique = true;
+        }
+    }
+
+    let foreign_keys = fetch(
+        conn,
+        "select \"from\", \"table\", \"to\" from pragma_foreign_key_list(?1);",
+        table,
+    )
+    .await?;
+    for row in foreign_keys {
+        let name: String = row.get(0);
+        let referenced: String = row.get(1);
+        // the referenced column is omitted when it is the primary key
+        let to = row
+            .get::<Option<String>, _>(2)
+            .unwrap_or_else(|| "id".to_string());
+        if let Some(column) = columns.iter_mut().find(|c| c.name == name) {
+            column.foreign_key = Some((referenced, to));
+        }
+    }
+    Ok(columns)
+}
+
+async fn introspect_information_schema(
+    conn: &Connection,
+    table: &str,
+    backend: Backend,
+) -> Result<Vec<Column>> {
+    let (columns_query, constraints_query) = if backend == Backend::Postgres {
+        (
+            "select column_name::text, data_type::text, is_nullable::text, \
+             character_maximum_length::bigint, coalesce(column_default, is_identity)::text \
+             from information_schema.columns \
+             where table_name = $1 and table_schema = current_schema() \
+             order by ordinal_position;",
+            "select tc.constraint_name::text, tc.constraint_type::text, kcu.column_name::text, \
+             ccu.table_name::text, ccu.column_name::text \
+             from information_schema.table_constraints tc \
+             join information_schema.key_column_usage kcu \
+             on kcu.constraint_name = tc.constraint_name and kcu.table_schema = tc.table_schema \
+             left join information_schema.constraint_column_usage ccu \
+             on tc.constraint_type = 'FOREIGN KEY' and ccu.constraint_name = tc.constraint_name \
+             and ccu.table_schema = tc.table_schema \
+             where tc.table_name = $1 and tc.table_schema = current_schema();",
+        )
+    } else {
+        (
+            "select column_name, data_type, is_nullable, \
+             cast(character_maximum_length as signed), extra \
+             from information_schema.columns \
+             where table_name = ? and table_schema = database() \
+             order by ordinal_position;",
+            "select tc.constraint_name, tc.constraint_type, kcu.column_name, \
+             kcu.referenced_table_name, kcu.referenced_column_name \
+             from information_schema.table_constraints tc \
+             join information_schema.key_column_usage kcu \
+             on kcu.constraint_name = tc.constraint_name and kcu.table_schema = tc.table_schema \
+             and kcu.table_name = tc.table_name \
+             where tc.table_name = ? and tc.table_schema = database();",
+        )
+    };
+
+    let mut columns = fetch(conn, columns_query, table)
+        .await?
+        .iter()
+        .map(|row| {
+            let data_type: String = row.get(1);
+            let extra = row.get::<Option<String>, _>(4).unwrap_or_default();
+            Column {
+                name: row.get(0),
+                nullable: row.get::<String, _>(2) == "YES",
+                // text types without a length, e.g. `text`, report no size
+                size: row
+                    .get::<Option<i64>, _>(3)
+                    .filter(|_| data_type.to_lowercase().contains("char")),
+                primary_key: false,
+                auto: extra.starts_with("nextval(")
+                    || extra == "YES"
+                    || extra.contains("auto_increment"),
+                unique: false,
+                foreign_key: None,
+                data_type,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let constraints = fetch(conn, constraints_query, table).await?;
+    let count = |name: &str| {
+        constraints
+            .iter()
+            .filter(|row| row.get::<String, _>(0) == name)
+            .count()
+    };
+    for row in &constraints {
+        let name: String = row.get(0);
+        let kind: String = row.get(1);
+        let column_name: String = row.get(2);
+        let Some(column) = columns.iter_mut().find(|c| c.name == column_name) else {
+            continue;
+        };
+        match kind.as_str() {
+            "PRIMARY KEY" => column.primary_key = true,
+            "UNIQUE" if count(&name) == 1 => column.unique = true,
+            "FOREIGN KEY" => {
+                if let (Some(table), Some(to)) = (
+                    row.get::<Option<String>, _>(3),
+                    row.get::<Option<String>, _>(4),
+                ) {
+                    column.foreign_key = Some((table, to));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(columns)
+}
+
+/// Returns the length of a `varchar(n)` or `char(n)` type.
+fn type_size(data_type: &str) -> Option<i64> {
+    let lower = data_type.to_lowercase();
+    if !lower.contains("char") {
+        return None;
+    }
+    let (_, size) = lower.split_once('(')?;
+    size.trim_end_matches(')').trim().parse().ok()
+}
+
+fn rust_type(column: &Column, backend: Backend) -> String {
+    let lower = column.data_type.to_lowercase();
+    let rust_type = match type_family(&column.data_type) {
+        Some("integer") if column.auto && backend == Backend::Postgres => "Serial",
+        Some("integer") if lower.contains("big") => "i64",
+        Some("integer") => "Integer",
+        Some("boolean") => "Boolean",
+        Some("text") if column.size.is_some() => "String",
+        Some("text") => "Text",
+        Some("real") => "Float",
+        Some("blob") => "Vec<u8>",
+        Some("datetime") if lower.starts_with("date") && !lower.contains("time") => "Date",
+        Some("datetime") if lower.starts_with("time") && !lower.contains("stamp") => "Time",
+        Some("datetime") => "DateTime",
+        Some("json") => "Json<serde_json::Value>",
+        Some("uuid") => "Uuid",
+        _ => "String",
+    };
+    rust_type.to_string()
+}
+
+/// Capitalizes the first letter of a table name, the derive lowercases it back.
+fn struct_name(table: &str) -> String {
+    let mut chars = table.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn field_name(column: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "as", "async", "await", "box", "break", "const", "continue", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "static", "struct", "trait", "true", "type", "unsafe",
+        "use", "where", "while", "yield",
+    ];
+    if KEYWORDS.contains(&column) {
+        format!("r#{column}")
+    } else {
+        column.to_string()
+    }
+}
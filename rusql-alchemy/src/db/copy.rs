@@ -0,0 +1,108 @@
+//! Bulk loading of postgres tables with `COPY ... FROM STDIN`, see `Model::copy_in`.
+//!
+//! The rows are sent as CSV on a connection of their own, outside of the pool, since the `Any`
+//! driver does not support `COPY`. The consecutive rows with the same columns share a `COPY`,
+//! and every `COPY` runs in one transaction.
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::postgres::PgConnection;
+use sqlx::Connection as _;
+
+use super::backend::Backend;
+use super::batch::row_values;
+use super::encryption;
+use super::models::{Arg, Model};
+use super::{instrument, QuerySettings};
+use crate::{Connection, Error};
+
+/// The size of the chunks of CSV sent to the server, in bytes.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Copies the instances into the table of `M`.
+///
+/// # Returns
+/// The number of copied rows.
+pub(crate) async fn copy_in<M: Model + Serialize>(rows: &[M], conn: &Connection) -> Result<u64> {
+    if Backend::of(conn) != Backend::Postgres {
+        anyhow::bail!("COPY is only supported by postgres");
+    }
+    // an unset primary key is left to the database, so the rows may not have the same columns
+    let mut groups: Vec<(Vec<String>, Vec<Vec<Arg>>)> = Vec::new();
+    for row in rows {
+        let (columns, mut values) = row_values(row)?;
+        encryption::encrypt_row(
+            &QuerySettings::of(conn),
+            M::ENCRYPTED,
+            &columns,
+            &mut values,
+        )?;
+        match groups.last_mut() {
+            Some((queued, rows)) if *queued == columns => rows.push(values),
+            _ => groups.push((columns, vec![values])),
+        }
+    }
+    if groups.is_empty() {
+        return Ok(0);
+    }
+
+    let url = &conn.connect_options().database_url;
+    let mut pg = PgConnection::connect(url.as_str()).await?;
+    let mut tx = pg.begin().await?;
+    let mut copied = 0;
+    for (columns, rows) in &groups {
+        let query = format!(
+            "copy {table} ({columns}) from stdin with (format csv)",
+            table = M::NAME,
+            columns = columns.join(", ")
+        );
+        copied += instrument(
+            &QuerySettings::of(conn),
+            M::NAME,
+            "copy_in",
+            &query,
+            copy(&mut tx, &query, rows),
+        )
+        .await
+        .map_err(|err| Error::new(&QuerySettings::of(conn), &query, Vec::new(), err))?;
+    }
+    tx.commit().await?;
+    Ok(copied)
+}
+
+/// Runs a `COPY ... FROM STDIN` statement, sending `rows` as CSV.
+async fn copy(conn: &mut PgConnection, query: &str, rows: &[Vec<Arg>]) -> sqlx::Result<u64> {
+    let mut copy = conn.copy_in_raw(query).await?;
+    let mut buffer = String::new();
+    for row in rows {
+        let fields = row.iter().map(csv_field).collect::<Vec<_>>();
+        buffer.push_str(&fields.join(","));
+        buffer.push('\n');
+        if buffer.len() >= CHUNK_SIZE {
+            copy.send(std::mem::take(&mut buffer).into_bytes()).await?;
+        }
+    }
+    if !buffer.is_empty() {
+        copy.send(buffer.into_bytes()).await?;
+    }
+    copy.finish().await
+}
+
+/// Renders a value as a field of the CSV format of `COPY`, where an unquoted empty field is
+/// `NULL` and a quoted one an empty string.
+fn csv_field(value: &Arg) -> String {
+    match value {
+        Arg::Null => String::new(),
+        Arg::Bool(value) => value.to_string(),
+        Arg::Int(value) => value.to_string(),
+        Arg::Float(value) => value.to_string(),
+        Arg::Text(text) => format!("\"{}\"", text.replace('"', "\"\"")),
+        Arg::Bytes(bytes) => {
+            let hex = bytes
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>();
+            format!("\\x{hex}")
+        }
+    }
+}
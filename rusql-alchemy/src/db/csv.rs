@@ -0,0 +1,172 @@
+//! Reading and writing of CSV records, see `Model::export_csv` and `Model::import_csv`.
+//!
+//! The files follow RFC 4180: fields are separated by commas, and the fields containing a
+//! comma, a quote or a line break are quoted, with their quotes doubled.
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, Result};
+
+use super::models::Arg;
+use super::schema::type_family;
+
+/// Writes a record, terminated by `\r\n`.
+pub(crate) fn write_record<W: Write>(writer: &mut W, fields: &[String]) -> Result<()> {
+    let record = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    write!(writer, "{record}\r\n")?;
+    Ok(())
+}
+
+/// Returns a JSON value as a field, `null` becoming an empty field.
+pub(crate) fn field(value: Option<&serde_json::Value>) -> String {
+    match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// Reads every record, the empty lines are skipped.
+pub(crate) fn read_records<R: Read>(mut reader: R) -> Result<Vec<Vec<String>>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|field| !field.is_empty()) || record.len() > 1 {
+                    records.push(std::mem::take(&mut record));
+                } else {
+                    record.clear();
+                }
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        bail!("unterminated quoted field");
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Converts a field to the argument bound to a column of type `sql_type`, an empty field
+/// becoming `NULL`.
+pub(crate) fn arg(field: &str, sql_type: &str) -> Result<Arg> {
+    if field.is_empty() {
+        return Ok(Arg::Null);
+    }
+    let arg = match type_family(sql_type) {
+        // sqlite stores the booleans in integer columns
+        Some("integer") => match boolean(field) {
+            Some(b) => Arg::Bool(b),
+            None => Arg::Int(field.trim().parse()?),
+        },
+        Some("real") => Arg::Float(field.trim().parse()?),
+        Some("boolean") => match (boolean(field), field.trim()) {
+            (Some(b), _) => Arg::Bool(b),
+            (None, "1") => Arg::Bool(true),
+            (None, "0") => Arg::Bool(false),
+            _ => bail!("invalid boolean `{field}`"),
+        },
+        _ => Arg::Text(field.to_string()),
+    };
+    Ok(arg)
+}
+
+/// Parses `true`, `false` and their one-letter forms.
+fn boolean(field: &str) -> Option<bool> {
+    match field.trim().to_lowercase().as_str() {
+        "true" | "t" => Some(true),
+        "false" | "f" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_the_fields_that_need_it() {
+        let mut out = Vec::new();
+        let fields = ["joe", "a,b", "say \"hi\"", "two\nlines", ""].map(String::from);
+        write_record(&mut out, &fields).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "joe,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\r\n"
+        );
+    }
+
+    #[test]
+    fn reads_back_the_written_records() {
+        let records = vec![
+            vec!["id".to_string(), "name".to_string()],
+            vec!["1".to_string(), "a,\"b\"\r\nc".to_string()],
+            vec!["2".to_string(), String::new()],
+        ];
+        let mut out = Vec::new();
+        for record in &records {
+            write_record(&mut out, record).unwrap();
+        }
+        assert_eq!(read_records(out.as_slice()).unwrap(), records);
+    }
+
+    #[test]
+    fn skips_the_empty_lines() {
+        let records = read_records("a,b\n\n1,2\n\n".as_bytes()).unwrap();
+        assert_eq!(records, vec![vec!["a", "b"], vec!["1", "2"]]);
+        assert_eq!(read_records(",\n".as_bytes()).unwrap(), vec![vec!["", ""]]);
+    }
+
+    #[test]
+    fn rejects_an_unterminated_quote() {
+        assert!(read_records("a,\"b\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn converts_the_fields_to_the_column_type() {
+        assert_eq!(arg("", "integer").unwrap(), Arg::Null);
+        assert_eq!(arg(" 42 ", "integer").unwrap(), Arg::Int(42));
+        assert_eq!(arg("true", "integer").unwrap(), Arg::Bool(true));
+        assert_eq!(arg("1.5", "real").unwrap(), Arg::Float(1.5));
+        assert_eq!(arg("0", "boolean").unwrap(), Arg::Bool(false));
+        assert_eq!(arg("joe", "varchar(50)").unwrap(), Arg::Text("joe".into()));
+        assert!(arg("x", "integer").is_err());
+        assert!(arg("maybe", "boolean").is_err());
+    }
+
+    #[test]
+    fn writes_null_as_an_empty_field() {
+        assert_eq!(field(None), "");
+        assert_eq!(field(Some(&serde_json::Value::Null)), "");
+        assert_eq!(field(Some(&serde_json::json!("joe"))), "joe");
+        assert_eq!(field(Some(&serde_json::json!(1.5))), "1.5");
+    }
+}
@@ -0,0 +1,226 @@
+//! Decoding of result sets into models.
+
+use std::fmt;
+
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use sqlx::{any::AnyRow, Column, Row, TypeInfo, ValueRef};
+
+/// The ordinals of a list of columns in a result set.
+///
+/// Every row of a statement has the same columns, so the ordinals can be resolved once from
+/// the first row and reused to decode the others, instead of looking each column up by name
+/// for every row.
+///
+/// # Example
+/// ```
+/// impl Model for User {
+///     // ...
+///     fn from_rows(rows: &[AnyRow]) -> sqlx::Result<Vec<Self>> {
+///         let Some(first) = rows.first() else {
+///             return Ok(Vec::new());
+///         };
+///         let ordinals = ColumnOrdinals::resolve(first, &["id", "name"])?;
+///         rows.iter()
+///             .map(|row| {
+///                 Ok(User {
+///                     id: row.try_get(ordinals[0])?,
+///                     name: row.try_get(ordinals[1])?,
+///                 })
+///             })
+///             .collect()
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ColumnOrdinals(Vec<usize>);
+
+impl ColumnOrdinals {
+    /// Resolves the ordinals of `columns` from a row of the result set.
+    ///
+    /// # Errors
+    /// Returns `sqlx::Error::ColumnNotFound` if a column is missing from the row.
+    pub fn resolve(row: &AnyRow, columns: &[&str]) -> sqlx::Result<Self> {
+        columns
+            .iter()
+            .map(|name| row.try_column(*name).map(|column| column.ordinal()))
+            .collect::<sqlx::Result<Vec<_>>>()
+            .map(Self)
+    }
+}
+
+impl std::ops::Deref for ColumnOrdinals {
+    type Target = [usize];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Returns the fields of the struct `T` deserializes, e.g. to select only the columns of a DTO
+/// with `Model::filter_as`.
+///
+/// The names are the serialized ones, after `#[serde(rename)]`. Returns `None` if `T` is not
+/// deserialized as a struct, e.g. when it has a `#[serde(flatten)]` field.
+///
+/// # Example
+/// ```
+/// #[derive(Deserialize)]
+/// struct UserSummary {
+///     id: i32,
+///     name: String,
+/// }
+///
+/// assert_eq!(field_names::<UserSummary>(), Some(&["id", "name"][..]));
+/// ```
+pub fn field_names<T: DeserializeOwned>() -> Option<&'static [&'static str]> {
+    let mut fields = None;
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// A deserializer recording the fields of the struct asked for, and failing.
+struct FieldNames<'a>(&'a mut Option<&'static [&'static str]>);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = Some(fields);
+        Err(de::Error::custom("the fields are recorded"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+/// A row of a result set that cannot be decoded into a model, found as the source of the
+/// `sqlx::Error::Decode` of an `Error::Query`, see `Error::decode_error`.
+///
+/// # Example
+/// ```rust
+/// if let Err(err) = User::select(kwargs!(age >= 18)).fetch_in(tx).await {
+///     if let Some(err) = err.downcast_ref::<Error>().and_then(Error::decode_error) {
+///         eprintln!("row {} of {}: column {:?}", err.row, err.model, err.column);
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct DecodeError {
+    /// The table of the model.
+    pub model: &'static str,
+    /// The index of the row in the result set, from 0.
+    pub row: usize,
+    /// The column at fault, when the driver reports it.
+    pub column: Option<String>,
+    /// The SQL type of the value found in the column.
+    pub sql_type: Option<String>,
+    /// The Rust type the column was decoded as, when the types do not match.
+    pub expected: Option<String>,
+    /// The error reported by the driver.
+    pub source: sqlx::Error,
+}
+
+impl DecodeError {
+    /// Describes the failure to decode `row`, the row at `index` in the result set of `model`.
+    pub(crate) fn new(
+        model: &'static str,
+        index: usize,
+        row: &AnyRow,
+        source: sqlx::Error,
+    ) -> Self {
+        let column = match &source {
+            // the index of the column is reported as its `Debug` form
+            sqlx::Error::ColumnDecode { index, .. } => {
+                let index = index.trim_matches('"');
+                match index.parse::<usize>() {
+                    Ok(ordinal) => row.columns().get(ordinal).map(|c| c.name().to_string()),
+                    Err(_) => Some(index.to_string()),
+                }
+            }
+            sqlx::Error::ColumnNotFound(name) => Some(name.clone()),
+            _ => None,
+        };
+        let sql_type = column
+            .as_deref()
+            .and_then(|name| row.try_get_raw(name).ok())
+            .map(|value| value.type_info().name().to_string());
+        // the mismatch errors of sqlx read "mismatched types; Rust type `i32` (as SQL type ...)"
+        let expected = match &source {
+            sqlx::Error::ColumnDecode { source, .. } => source
+                .to_string()
+                .split_once("Rust type `")
+                .and_then(|(_, rest)| rest.split_once('`'))
+                .map(|(rust_type, _)| rust_type.to_string()),
+            _ => None,
+        };
+        Self {
+            model,
+            row: index,
+            column,
+            sql_type,
+            expected,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot decode row {} of `{}`", self.row, self.model)?;
+        if let Some(column) = &self.column {
+            write!(f, ", column `{column}`")?;
+        }
+        match (&self.sql_type, &self.expected) {
+            (Some(sql_type), Some(expected)) => write!(f, " ({sql_type}, expected `{expected}`)")?,
+            (Some(sql_type), None) => write!(f, " ({sql_type})")?,
+            (None, Some(expected)) => write!(f, " (expected `{expected}`)")?,
+            (None, None) => {}
+        }
+        write!(f, ": {}", self.source)
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<DecodeError> for sqlx::Error {
+    fn from(err: DecodeError) -> Self {
+        sqlx::Error::Decode(Box::new(err))
+    }
+}
+
+/// Replaces the error of decoding a result set of `model` with the `DecodeError` of the first
+/// row failing `decode`, or returns it as is when every row decodes on its own.
+pub(crate) fn locate<T>(
+    model: &'static str,
+    rows: &[AnyRow],
+    err: sqlx::Error,
+    decode: impl Fn(&AnyRow) -> sqlx::Result<T>,
+) -> sqlx::Error {
+    let failed = rows
+        .iter()
+        .enumerate()
+        .find_map(|(index, row)| decode(row).err().map(|err| (index, row, err)));
+    let Some((index, row, source)) = failed else {
+        return err;
+    };
+    let err = DecodeError::new(model, index, row, source);
+    #[cfg(feature = "tracing")]
+    tracing::error!(db.model = model, error = %err, "decode failed");
+    err.into()
+}
@@ -0,0 +1,264 @@
+//! Encryption of the `Model::ENCRYPTED` columns.
+//!
+//! The values of the `ENCRYPTED` columns of a model are encrypted with the cipher set by
+//! `Database::set_cipher` before they are written by `create`, `save`, `set`, `update`, the
+//! update builders and the bulk writes, and decrypted before the rows are decoded by `all`,
+//! `filter`, `get`, `refresh` and the other reads of the model. Like the query tag, the cipher
+//! belongs to the pools of the database it is set on. Writing or reading an encrypted column
+//! without a cipher fails, so a plaintext value is never stored by mistake.
+//!
+//! The encrypted columns must be text columns. `AesGcmCipher` draws a random nonce for every
+//! value, so the same plaintext is stored differently each time and the encrypted columns
+//! cannot be filtered, ordered or indexed.
+
+use std::borrow::Cow;
+
+use anyhow::Result;
+use sqlx::any::AnyRow;
+use sqlx::{Column, Row};
+// the values of a row are only reachable through the driver crate
+use sqlx_core::any::AnyValueKind;
+
+use super::models::{Arg, Condition, Model};
+use super::QuerySettings;
+
+/// Encrypts and decrypts the values of the encrypted columns, set with `Database::set_cipher`.
+///
+/// # Example
+/// ```rust
+/// struct Rot13;
+///
+/// impl Cipher for Rot13 {
+///     fn encrypt(&self, plaintext: &str) -> anyhow::Result<String> {
+///         Ok(rot13(plaintext))
+///     }
+///
+///     fn decrypt(&self, ciphertext: &str) -> anyhow::Result<String> {
+///         Ok(rot13(ciphertext))
+///     }
+/// }
+/// ```
+pub trait Cipher: Send + Sync {
+    /// Returns the stored form of `plaintext`.
+    fn encrypt(&self, plaintext: &str) -> Result<String>;
+
+    /// Returns the plaintext of a value returned by `encrypt`.
+    ///
+    /// # Errors
+    /// Returns an error if the value was not encrypted with this cipher, or was altered.
+    fn decrypt(&self, ciphertext: &str) -> Result<String>;
+}
+
+/// Encrypts the values given to the `ENCRYPTED` columns of `M`, with the cipher of `settings`.
+///
+/// # Errors
+/// Returns an error if there is no cipher or a value is not text.
+pub(crate) fn encrypt<M: Model + ?Sized>(
+    settings: &QuerySettings,
+    kw: Vec<Condition>,
+) -> Result<Vec<Condition>> {
+    if M::ENCRYPTED.is_empty() {
+        return Ok(kw);
+    }
+    kw.into_iter()
+        .map(|condition| match condition {
+            Condition::FieldCondition {
+                field,
+                value,
+                comparison_operator,
+            } if M::ENCRYPTED.contains(&field.as_str()) => Ok(Condition::FieldCondition {
+                value: encrypt_value(settings, &field, value)?,
+                field,
+                comparison_operator,
+            }),
+            condition => Ok(condition),
+        })
+        .collect()
+}
+
+/// Encrypts the values of the `encrypted` columns in the values of a row, in place.
+///
+/// # Errors
+/// Returns an error if there is no cipher or a value is not text.
+pub(crate) fn encrypt_row(
+    settings: &QuerySettings,
+    encrypted: &[&str],
+    columns: &[String],
+    values: &mut [Arg],
+) -> Result<()> {
+    for (column, value) in columns.iter().zip(values) {
+        if encrypted.contains(&column.as_str()) {
+            *value = encrypt_value(settings, column, std::mem::replace(value, Arg::Null))?;
+        }
+    }
+    Ok(())
+}
+
+/// Encrypts the value of the encrypted column `column`, `NULL` staying as is.
+fn encrypt_value(settings: &QuerySettings, column: &str, value: Arg) -> Result<Arg> {
+    match value {
+        Arg::Null => Ok(Arg::Null),
+        Arg::Text(text) => Ok(Arg::Text(settings.cipher()?.encrypt(&text)?)),
+        _ => anyhow::bail!("the encrypted column `{column}` must be text"),
+    }
+}
+
+/// Decrypts the `ENCRYPTED` columns of `M` in the rows of a result set, in place, with the
+/// cipher of `settings`.
+///
+/// # Errors
+/// Returns `sqlx::Error::Decode` if there is no cipher or a value cannot be decrypted.
+pub(crate) fn decrypt<M: Model + ?Sized>(
+    settings: &QuerySettings,
+    rows: &mut [AnyRow],
+) -> sqlx::Result<()> {
+    if M::ENCRYPTED.is_empty() || rows.is_empty() {
+        return Ok(());
+    }
+    let ordinals = rows[0]
+        .columns()
+        .iter()
+        .filter(|column| M::ENCRYPTED.contains(&column.name()))
+        .map(|column| column.ordinal())
+        .collect::<Vec<_>>();
+    if ordinals.is_empty() {
+        return Ok(());
+    }
+    let cipher = settings
+        .cipher()
+        .map_err(|err| sqlx::Error::Decode(err.into()))?;
+    for row in rows {
+        for &ordinal in &ordinals {
+            let value = &mut row.values[ordinal].kind;
+            let plaintext = match value {
+                AnyValueKind::Text(text) => cipher.decrypt(text),
+                AnyValueKind::Blob(bytes) => std::str::from_utf8(bytes)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|text| cipher.decrypt(text)),
+                _ => continue,
+            };
+            let plaintext = plaintext.map_err(|err| sqlx::Error::Decode(err.into()))?;
+            *value = AnyValueKind::Text(Cow::Owned(plaintext));
+        }
+    }
+    Ok(())
+}
+
+/// A `Cipher` encrypting with AES-256-GCM, storing the values as the base64 of a random 96-bit
+/// nonce followed by the ciphertext and its tag.
+///
+/// # Example
+/// ```rust
+/// let key: [u8; 32] = secrets.get("encryption_key")?;
+/// db.set_cipher(AesGcmCipher::new(key));
+/// ```
+#[cfg(feature = "encryption")]
+pub struct AesGcmCipher {
+    cipher: aes_gcm::Aes256Gcm,
+}
+
+#[cfg(feature = "encryption")]
+impl AesGcmCipher {
+    /// Creates a cipher with a 256-bit key.
+    pub fn new(key: [u8; 32]) -> Self {
+        use aes_gcm::KeyInit;
+
+        Self {
+            cipher: aes_gcm::Aes256Gcm::new(&key.into()),
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Cipher for AesGcmCipher {
+    fn encrypt(&self, plaintext: &str) -> Result<String> {
+        use aes_gcm::aead::{Aead, AeadCore, OsRng};
+        use base64::Engine;
+
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow::anyhow!("the value cannot be encrypted"))?;
+        let mut stored = nonce.to_vec();
+        stored.extend(ciphertext);
+        Ok(base64::engine::general_purpose::STANDARD.encode(stored))
+    }
+
+    fn decrypt(&self, ciphertext: &str) -> Result<String> {
+        use aes_gcm::aead::Aead;
+        use base64::Engine;
+
+        let stored = base64::engine::general_purpose::STANDARD.decode(ciphertext)?;
+        if stored.len() < 12 {
+            anyhow::bail!("the value is not encrypted");
+        }
+        let (nonce, ciphertext) = stored.split_at(12);
+        let plaintext = self
+            .cipher
+            .decrypt(aes_gcm::Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("the value cannot be decrypted"))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{memory, EncryptedUser, TestCipher};
+    use crate::Database;
+
+    fn user(name: &str) -> Vec<Condition> {
+        vec![
+            Condition::field("name", "=", name),
+            Condition::field("age", "=", 19),
+        ]
+    }
+
+    #[tokio::test]
+    async fn encrypts_the_writes_and_decrypts_the_reads() {
+        let conn = memory().await;
+        Database::from_pool(conn.clone()).set_cipher(TestCipher);
+        assert!(EncryptedUser::migrate(&conn).await);
+        assert!(EncryptedUser::create(user("joe"), &conn).await);
+        assert!(EncryptedUser::create(user("ann"), &conn).await);
+        assert!(EncryptedUser::set(2, vec![Condition::field("name", "=", "jim")], &conn).await);
+
+        let stored: Vec<(String,)> = sqlx::query_as("select name from encrypted_user order by id;")
+            .fetch_all(&conn)
+            .await
+            .unwrap();
+        assert_eq!(stored, [("enc:eoj".into(),), ("enc:mij".into(),)]);
+        let names = EncryptedUser::all(&conn)
+            .await
+            .into_iter()
+            .map(|user| user.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["joe", "jim"]);
+    }
+
+    #[tokio::test]
+    async fn fails_without_a_cipher() {
+        // the cipher of another database does not apply to this pool
+        Database::from_pool(memory().await).set_cipher(TestCipher);
+        let conn = memory().await;
+        assert!(EncryptedUser::migrate(&conn).await);
+        assert!(!EncryptedUser::create(user("joe"), &conn).await);
+        assert!(!EncryptedUser::set(1, user("joe"), &conn).await);
+
+        sqlx::query("insert into encrypted_user (name, age) values ('enc:eoj', 19);")
+            .execute(&conn)
+            .await
+            .unwrap();
+        let (users, skipped) = EncryptedUser::select(Vec::new())
+            .fetch_lenient(&conn)
+            .await
+            .unwrap();
+        assert!(users.is_empty());
+        assert!(
+            skipped[0].to_string().contains("no cipher"),
+            "{}",
+            skipped[0]
+        );
+    }
+}
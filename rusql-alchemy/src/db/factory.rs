@@ -0,0 +1,134 @@
+//! Factories creating model instances for tests.
+//!
+//! A `Factory` holds a value, or a generator fed with a sequence number, for the fields of a
+//! model, so tests only spell out the fields they care about. Instances are persisted with
+//! `Model::create`.
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sqlx::any::AnyRow;
+use sqlx::FromRow;
+
+use super::models::{Arg, Condition, Model};
+use crate::Connection;
+
+type Generator = Box<dyn Fn(u64) -> Arg + Send + Sync>;
+
+/// Creates instances of a model with default and sequence-based values.
+///
+/// # Example
+/// ```
+/// let users = Factory::<User>::new()
+///     .sequence("name", |n| format!("user_{n}"))
+///     .sequence("email", |n| format!("user_{n}@example.com"))
+///     .field("age", 18);
+///
+/// let joe = users.create_with(kwargs!(name = "joe"), &conn).await.unwrap();
+/// let others = users.create_many(3, &conn).await;
+/// ```
+pub struct Factory<T> {
+    fields: Vec<(String, Generator)>,
+    sequence: AtomicU64,
+    _model: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for Factory<T> {
+    fn default() -> Self {
+        Self {
+            fields: Vec::new(),
+            sequence: AtomicU64::new(0),
+            _model: PhantomData,
+        }
+    }
+}
+
+impl<T: Model> Factory<T> {
+    /// Creates a factory without field values, the other fields keep their database default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives the same value to a field for every instance.
+    pub fn field<V>(self, name: &str, value: V) -> Self
+    where
+        V: Into<Arg> + Clone + Send + Sync + 'static,
+    {
+        self.sequence(name, move |_| value.clone())
+    }
+
+    /// Gives a field the value generated from the sequence number of each instance, starting
+    /// at 1, e.g. to fill unique columns.
+    pub fn sequence<V, F>(mut self, name: &str, generate: F) -> Self
+    where
+        V: Into<Arg>,
+        F: Fn(u64) -> V + Send + Sync + 'static,
+    {
+        self.fields.retain(|(field, _)| field != name);
+        self.fields
+            .push((name.to_string(), Box::new(move |n| generate(n).into())));
+        self
+    }
+
+    /// Returns the `kwargs` of the next instance, with `overrides` replacing the values of the
+    /// factory.
+    pub fn build_with(&self, overrides: Vec<Condition>) -> Vec<Condition> {
+        let n = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        let overridden = |name: &str| {
+            overrides.iter().any(
+                |condition| matches!(condition, Condition::FieldCondition { field, .. } if field == name),
+            )
+        };
+        let mut kw = self
+            .fields
+            .iter()
+            .filter(|(name, _)| !overridden(name))
+            .map(|(name, generate)| Condition::field(name, "=", generate(n)))
+            .collect::<Vec<_>>();
+        kw.extend(overrides);
+        kw
+    }
+
+    /// Returns the `kwargs` of the next instance.
+    pub fn build(&self) -> Vec<Condition> {
+        self.build_with(Vec::new())
+    }
+
+    /// Creates the next instance, with `overrides` replacing the values of the factory.
+    ///
+    /// # Returns
+    /// The created instance, read back with its values, or `None` if it could not be created.
+    pub async fn create_with(&self, overrides: Vec<Condition>, conn: &Connection) -> Option<T>
+    where
+        T: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone + Send,
+    {
+        let kw = self.build_with(overrides);
+        if !T::create(kw.clone(), conn).await {
+            return None;
+        }
+        T::get(kw, conn).await
+    }
+
+    /// Creates the next instance.
+    pub async fn create(&self, conn: &Connection) -> Option<T>
+    where
+        T: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone + Send,
+    {
+        self.create_with(Vec::new(), conn).await
+    }
+
+    /// Creates `n` instances, stopping at the first failure.
+    pub async fn create_many(&self, n: usize, conn: &Connection) -> Vec<T>
+    where
+        T: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone + Send,
+    {
+        let mut instances = Vec::with_capacity(n);
+        for _ in 0..n {
+            match self.create(conn).await {
+                Some(instance) => instances.push(instance),
+                None => break,
+            }
+        }
+        instances
+    }
+}
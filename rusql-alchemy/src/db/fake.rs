@@ -0,0 +1,419 @@
+//! In-memory test double for models.
+//!
+//! `MemoryStore` mirrors the CRUD methods of the `Model` trait but keeps the rows in a
+//! `HashMap` keyed by primary key instead of sending them to a database, so service logic can
+//! be unit tested without any database nor driver. It is only available with the `fake`
+//! feature.
+//!
+//! The store is not a `Connection`: the `Model` methods keep sending their queries to a
+//! database, so only code written against `MemoryStore` itself can run on it.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use super::models::{Arg, Condition, Model};
+
+type Row = Map<String, Value>;
+
+/// The rows of a store, with the order they were inserted in.
+#[derive(Default)]
+struct Table {
+    rows: HashMap<String, (u64, Row)>,
+    inserted: u64,
+    last_id: i64,
+}
+
+/// An in-memory store that can stand in for the database of a model `T`.
+///
+/// Rows are kept as JSON objects, so `T` has to implement `Serialize` and `DeserializeOwned`
+/// on top of the usual model derives. The conditions are evaluated in memory: the comparisons
+/// of `kwargs!` (`=`, `!=`, `<`, `<=`, `>`, `>=`), combined with `and()`, `or()` and
+/// `any_of_each`, are supported; the other operators, such as `similar_to`, the column
+/// comparisons and the conditions written per backend never match.
+///
+/// # Example
+/// ```
+/// let store = MemoryStore::<User>::new();
+/// store.create(kwargs!(name = "joe", age = 19)).await;
+///
+/// let users = store.filter(kwargs!(age >= 18)).await;
+/// assert_eq!(users.len(), 1);
+/// ```
+pub struct MemoryStore<T> {
+    table: Mutex<Table>,
+    _model: PhantomData<T>,
+}
+
+impl<T> Default for MemoryStore<T> {
+    fn default() -> Self {
+        Self {
+            table: Mutex::default(),
+            _model: PhantomData,
+        }
+    }
+}
+
+impl<T> MemoryStore<T>
+where
+    T: Model + Serialize + DeserializeOwned + Default,
+{
+    /// Creates a new, empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts the instance into the store.
+    ///
+    /// If the primary key is unset (`None`, `0` or an empty string), the next id is assigned
+    /// the same way an auto-increment column would.
+    ///
+    /// # Returns
+    /// `true` if the instance could be stored, `false` if its primary key is already used.
+    pub async fn save(&self, instance: &T) -> bool {
+        match to_row(instance) {
+            Some(row) => self.insert(row),
+            None => false,
+        }
+    }
+
+    /// Creates a new row from the key-value arguments, the other fields keep their `Default`
+    /// value.
+    ///
+    /// # Returns
+    /// `true` if creation is successful, `false` if a field is not a column of the model or the
+    /// primary key is already used.
+    pub async fn create(&self, kw: Vec<Condition>) -> bool {
+        let Some(mut row) = to_row(&T::default()) else {
+            return false;
+        };
+        apply(&mut row, kw) && self.insert(row)
+    }
+
+    /// Replaces the stored row that has the same primary key as the instance.
+    ///
+    /// # Returns
+    /// `true` if a row was updated, `false` otherwise.
+    pub async fn update(&self, instance: &T) -> bool {
+        let Some(row) = to_row(instance) else {
+            return false;
+        };
+        let key = key(row.get(T::PK));
+        match self.table.lock().unwrap().rows.get_mut(&key) {
+            Some((_, stored)) => {
+                *stored = row;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Updates the row identified by its primary key with the given parameters.
+    ///
+    /// # Returns
+    /// `true` if a row was updated, `false` if there is none or a field is not a column of the
+    /// model.
+    pub async fn set<V: Into<Arg> + Send>(&self, id_value: V, kw: Vec<Condition>) -> bool {
+        let key = key(Some(&id_value.into().into()));
+        let mut table = self.table.lock().unwrap();
+        let Some((_, row)) = table.rows.get_mut(&key) else {
+            return false;
+        };
+        let mut updated = row.clone();
+        if !apply(&mut updated, kw) {
+            return false;
+        }
+        // the row is moved when its primary key changes
+        let new_key = self::key(updated.get(T::PK));
+        if new_key == key {
+            *row = updated;
+            return true;
+        }
+        if table.rows.contains_key(&new_key) {
+            return false;
+        }
+        let (order, _) = table.rows.remove(&key).unwrap();
+        table.rows.insert(new_key, (order, updated));
+        true
+    }
+
+    /// Removes the stored row that has the same primary key as the instance.
+    ///
+    /// # Returns
+    /// `true` if a row was removed, `false` otherwise.
+    pub async fn delete(&self, instance: &T) -> bool {
+        let Some(row) = to_row(instance) else {
+            return false;
+        };
+        let key = key(row.get(T::PK));
+        self.table.lock().unwrap().rows.remove(&key).is_some()
+    }
+
+    /// Retrieves all instances in insertion order.
+    pub async fn all(&self) -> Vec<T> {
+        self.select(&[])
+    }
+
+    /// Filters instances with the same `kwargs!` conditions accepted by `Model::filter`.
+    pub async fn filter(&self, kw: Vec<Condition>) -> Vec<T> {
+        self.select(&kw)
+    }
+
+    /// Retrieves the first instance matching the conditions.
+    pub async fn get(&self, kw: Vec<Condition>) -> Option<T> {
+        self.select(&kw).into_iter().next()
+    }
+
+    /// Counts the stored instances.
+    pub async fn count(&self) -> i64 {
+        self.table.lock().unwrap().rows.len() as i64
+    }
+
+    /// Removes every stored instance.
+    pub async fn clear(&self) {
+        self.table.lock().unwrap().rows.clear();
+    }
+
+    fn insert(&self, mut row: Row) -> bool {
+        let mut table = self.table.lock().unwrap();
+        let unset = match row.get(T::PK) {
+            None | Some(Value::Null) => true,
+            Some(Value::Number(n)) => n.as_f64() == Some(0.0),
+            Some(Value::String(s)) => s.is_empty(),
+            _ => false,
+        };
+        if unset {
+            table.last_id += 1;
+            row.insert(T::PK.to_string(), Value::from(table.last_id));
+        } else if let Some(id) = row.get(T::PK).and_then(Value::as_i64) {
+            table.last_id = table.last_id.max(id);
+        }
+        let key = key(row.get(T::PK));
+        if table.rows.contains_key(&key) {
+            return false;
+        }
+        table.inserted += 1;
+        let order = table.inserted;
+        table.rows.insert(key, (order, row));
+        true
+    }
+
+    fn select(&self, kw: &[Condition]) -> Vec<T> {
+        let table = self.table.lock().unwrap();
+        let mut rows = table
+            .rows
+            .values()
+            .filter(|(_, row)| matches(row, kw))
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|(order, _)| *order);
+        rows.into_iter()
+            .filter_map(|(_, row)| serde_json::from_value(Value::Object(row.clone())).ok())
+            .collect()
+    }
+}
+
+fn to_row<T: Serialize>(instance: &T) -> Option<Row> {
+    match serde_json::to_value(instance) {
+        Ok(Value::Object(row)) => Some(row),
+        _ => None,
+    }
+}
+
+/// Returns the key of a primary key value, the same for `1` and `1.0`.
+fn key(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::Number(n)) => match n.as_f64() {
+            Some(n) if n.fract() == 0.0 => format!("{}", n as i64),
+            _ => n.to_string(),
+        },
+        Some(value) => value.to_string(),
+        None => Value::Null.to_string(),
+    }
+}
+
+/// Sets the fields of the conditions in a row.
+///
+/// # Returns
+/// `false` if a field is not a column of the row.
+fn apply(row: &mut Row, kw: Vec<Condition>) -> bool {
+    for condition in kw {
+        if let Condition::FieldCondition { field, value, .. } = condition {
+            match row.get_mut(&field) {
+                Some(stored) => *stored = value.into(),
+                None => return false,
+            }
+        }
+    }
+    true
+}
+
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    // booleans compare with 0/1, as stored in the integer columns of sqlite
+    let number = |v: &Value| match v {
+        Value::Bool(b) => Some(*b as i64 as f64),
+        Value::Number(n) => n.as_f64(),
+        _ => None,
+    };
+    match (left, right) {
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        (l, r) => number(l)?.partial_cmp(&number(r)?),
+    }
+}
+
+fn matches_condition(row: &Row, condition: &Condition) -> bool {
+    match condition {
+        Condition::FieldCondition {
+            field,
+            value,
+            comparison_operator,
+        } => {
+            let stored = row.get(field).unwrap_or(&Value::Null);
+            let value = Value::from(value.clone());
+            // `None` values are compared with `is null`, as in `Model::filter`
+            if value.is_null() {
+                return match comparison_operator.as_str() {
+                    "=" | "==" => stored.is_null(),
+                    "!=" => !stored.is_null(),
+                    _ => false,
+                };
+            }
+            match (comparison_operator.as_str(), compare(stored, &value)) {
+                ("=" | "==", Some(o)) => o == Ordering::Equal,
+                ("!=", Some(o)) => o != Ordering::Equal,
+                ("<", Some(o)) => o == Ordering::Less,
+                ("<=", Some(o)) => o != Ordering::Greater,
+                (">", Some(o)) => o == Ordering::Greater,
+                (">=", Some(o)) => o != Ordering::Less,
+                _ => false,
+            }
+        }
+        Condition::Group(conditions) => matches(row, conditions),
+        Condition::ColumnCondition { .. }
+        | Condition::ByBackend { .. }
+        | Condition::LogicalOperator { .. } => false,
+    }
+}
+
+// `and` binds tighter than `or`, as in SQL
+fn matches(row: &Row, kw: &[Condition]) -> bool {
+    let mut groups = vec![true];
+    for condition in kw {
+        match condition {
+            Condition::LogicalOperator { operator } if operator.eq_ignore_ascii_case("or") => {
+                groups.push(true)
+            }
+            Condition::LogicalOperator { .. } => {}
+            condition => {
+                let matched = matches_condition(row, condition);
+                let group = groups.last_mut().unwrap();
+                *group = *group && matched;
+            }
+        }
+    }
+    groups.into_iter().any(|group| group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::{any_of_each, And, Or};
+    use crate::db::testing::User;
+
+    fn field(field: &str, comparison_operator: &str, value: impl Into<Arg>) -> Vec<Condition> {
+        vec![Condition::field(field, comparison_operator, value)]
+    }
+
+    async fn store() -> MemoryStore<User> {
+        let store = MemoryStore::new();
+        assert!(store.save(&User::new("joe", 19)).await);
+        assert!(
+            store
+                .create(field("name", "=", "jane").and(field("age", "=", 17)))
+                .await
+        );
+        store
+    }
+
+    #[tokio::test]
+    async fn assigns_the_primary_keys() {
+        let store = store().await;
+        let ids = store
+            .all()
+            .await
+            .iter()
+            .map(|user| user.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+
+        let kim = User {
+            id: 10,
+            ..User::new("kim", 30)
+        };
+        assert!(store.save(&kim).await);
+        assert!(!store.save(&kim).await);
+        assert!(store.save(&User::new("bob", 40)).await);
+        assert!(store.get(field("id", "=", 11)).await.is_some());
+        assert_eq!(store.count().await, 4);
+    }
+
+    #[tokio::test]
+    async fn filters_like_the_database() {
+        let store = store().await;
+        let names = |users: Vec<User>| users.into_iter().map(|user| user.name).collect::<Vec<_>>();
+        assert_eq!(names(store.filter(field("age", ">=", 18)).await), ["joe"]);
+        assert_eq!(names(store.filter(field("age", "<", 18)).await), ["jane"]);
+        assert_eq!(
+            names(
+                store
+                    .filter(field("name", "=", "joe").or(field("age", "=", 17)))
+                    .await
+            ),
+            ["joe", "jane"]
+        );
+        assert!(store
+            .filter(field("name", "=", "joe").and(field("age", "=", 17)))
+            .await
+            .is_empty());
+        let kw = any_of_each(&[
+            [field("name", "=", "joe"), field("name", "=", "jane")].concat(),
+            field("age", ">", 18),
+        ]);
+        assert_eq!(names(store.filter(kw).await), ["joe"]);
+        assert!(store
+            .filter(field("name", "=", None::<&str>))
+            .await
+            .is_empty());
+        assert!(store
+            .filter(field("name", "similar_to", "jo"))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn updates_and_deletes_rows() {
+        let store = store().await;
+        let mut joe = store.get(field("name", "=", "joe")).await.unwrap();
+        joe.age = 20;
+        assert!(store.update(&joe).await);
+        assert!(store.set(2, field("age", "=", 18)).await);
+        assert!(!store.set(3, field("age", "=", 18)).await);
+        assert!(!store.set(2, field("missing", "=", 1)).await);
+        let ages = store
+            .all()
+            .await
+            .iter()
+            .map(|user| user.age)
+            .collect::<Vec<_>>();
+        assert_eq!(ages, vec![20, 18]);
+
+        assert!(store.delete(&joe).await);
+        assert!(!store.delete(&joe).await);
+        assert!(!store.update(&joe).await);
+        store.clear().await;
+        assert_eq!(store.count().await, 0);
+    }
+}
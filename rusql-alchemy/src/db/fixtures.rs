@@ -0,0 +1,254 @@
+//! Loading of fixture files.
+//!
+//! A fixture file maps table names to the rows to insert. A value may reference a row of
+//! another table by its natural key with a `$ref` object, which is replaced by the `id` of
+//! the matching row (or by the column named with `$column`):
+//!
+//! ```json
+//! {
+//!     "user": [{ "name": "joe", "age": 19 }],
+//!     "product": [{ "name": "bike", "owner": { "$ref": "user", "name": "joe" } }]
+//! }
+//! ```
+//!
+//! Tables are filled after the tables they reference, and all the files are loaded in one
+//! transaction. JSON files are always supported, YAML files with the `yaml` feature.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use sqlx::{Any, Executor, Row};
+
+use super::backend::Backend;
+use super::models::Arg;
+use super::{instrument, QuerySettings};
+use crate::{Connection, Error};
+
+type Rows = Vec<Map<String, Value>>;
+
+/// Inserts the rows of a fixture file, or of every fixture file of a directory in name order.
+///
+/// # Returns
+/// The number of inserted rows.
+pub async fn load(conn: &Connection, path: impl AsRef<Path>) -> Result<u64> {
+    let mut tables: Vec<(String, Rows)> = Vec::new();
+    for file in files(path.as_ref())? {
+        for (table, rows) in parse(&file)? {
+            match tables.iter_mut().find(|(name, _)| *name == table) {
+                Some((_, existing)) => existing.extend(rows),
+                None => tables.push((table, rows)),
+            }
+        }
+    }
+
+    let backend = Backend::of(conn);
+    let settings = QuerySettings::of(conn);
+    let mut tx = conn.begin().await?;
+    let mut inserted = 0;
+    for (table, rows) in ordered(tables)? {
+        for row in rows {
+            insert(&mut tx, backend, &settings, &table, row).await?;
+            inserted += 1;
+        }
+    }
+    tx.commit().await?;
+    Ok(inserted)
+}
+
+fn files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("json" | "yaml" | "yml")
+        ) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn parse(file: &Path) -> Result<Vec<(String, Rows)>> {
+    let content =
+        fs::read_to_string(file).with_context(|| format!("cannot read {}", file.display()))?;
+    let value: Value = match file.extension().and_then(|ext| ext.to_str()) {
+        #[cfg(feature = "yaml")]
+        Some("yaml" | "yml") => serde_yaml::from_str(&content)
+            .with_context(|| format!("invalid fixture file {}", file.display()))?,
+        #[cfg(not(feature = "yaml"))]
+        Some("yaml" | "yml") => bail!(
+            "cannot load {}, YAML fixtures need the `yaml` feature",
+            file.display()
+        ),
+        _ => serde_json::from_str(&content)
+            .with_context(|| format!("invalid fixture file {}", file.display()))?,
+    };
+    let Value::Object(tables) = value else {
+        bail!("{} does not map table names to rows", file.display());
+    };
+    let mut parsed = Vec::new();
+    for (table, rows) in tables {
+        let Value::Array(rows) = rows else {
+            bail!("the rows of {table} in {} are not a list", file.display());
+        };
+        let rows = rows
+            .into_iter()
+            .map(|row| match row {
+                Value::Object(row) => Ok(row),
+                _ => bail!("a row of {table} in {} is not an object", file.display()),
+            })
+            .collect::<Result<Rows>>()?;
+        parsed.push((table, rows));
+    }
+    Ok(parsed)
+}
+
+/// Orders the tables so that every table comes after the tables its rows reference.
+fn ordered(tables: Vec<(String, Rows)>) -> Result<Vec<(String, Rows)>> {
+    let mut remaining = tables
+        .into_iter()
+        .map(|(table, rows)| {
+            let mut references = Vec::new();
+            for value in rows.iter().flat_map(|row| row.values()) {
+                if let Some(Value::String(referenced)) = value.get("$ref") {
+                    if *referenced != table && !references.contains(referenced) {
+                        references.push(referenced.clone());
+                    }
+                }
+            }
+            (table, rows, references)
+        })
+        .collect::<Vec<_>>();
+
+    let mut ordered: Vec<(String, Rows)> = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let ready = remaining.iter().position(|(_, _, references)| {
+            references.iter().all(|referenced| {
+                ordered.iter().any(|(table, _)| table == referenced)
+                    || !remaining.iter().any(|(table, _, _)| table == referenced)
+            })
+        });
+        match ready {
+            Some(index) => {
+                let (table, rows, _) = remaining.remove(index);
+                ordered.push((table, rows));
+            }
+            None => bail!(
+                "circular references between the fixtures of {}",
+                remaining
+                    .iter()
+                    .map(|(table, _, _)| table.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+    Ok(ordered)
+}
+
+async fn insert(
+    tx: &mut sqlx::Transaction<'_, Any>,
+    backend: Backend,
+    settings: &QuerySettings,
+    table: &str,
+    row: Map<String, Value>,
+) -> Result<()> {
+    let mut columns = Vec::new();
+    let mut args = Vec::new();
+    for (column, value) in row {
+        let arg = match value {
+            Value::Object(reference) if reference.contains_key("$ref") => {
+                resolve(&mut **tx, backend, settings, reference).await?
+            }
+            value => Arg::from(value),
+        };
+        columns.push(column);
+        args.push(arg);
+    }
+    let query = format!(
+        "insert into {table} ({}) values ({});",
+        columns.join(", "),
+        (1..=args.len())
+            .map(|index| backend.placeholder(index))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let mut stream = sqlx::query(&query);
+    binds!(args.clone(), stream);
+    instrument(
+        settings,
+        "fixtures",
+        "insert",
+        &query,
+        stream.execute(&mut **tx),
+    )
+    .await
+    .map_err(|err| Error::new(settings, &query, args, err))?;
+    Ok(())
+}
+
+/// Returns the key of the row matching a `$ref` object.
+async fn resolve<'c, E>(
+    executor: E,
+    backend: Backend,
+    settings: &QuerySettings,
+    mut reference: Map<String, Value>,
+) -> Result<Arg>
+where
+    E: Executor<'c, Database = Any>,
+{
+    let Some(Value::String(table)) = reference.remove("$ref") else {
+        bail!("`$ref` must be a table name");
+    };
+    let column = match reference.remove("$column") {
+        Some(Value::String(column)) => column,
+        _ => "id".to_string(),
+    };
+    if reference.is_empty() {
+        bail!("the reference to {table} has no natural key");
+    }
+    let mut keys = Vec::new();
+    let mut conditions = Vec::new();
+    let mut args = Vec::new();
+    for (i, (key, value)) in reference.into_iter().enumerate() {
+        conditions.push(format!("{key} = {}", backend.placeholder(i + 1)));
+        args.push(Arg::from(value));
+        keys.push(key);
+    }
+    let query = format!(
+        "select {column} from {table} where {} limit 2;",
+        conditions.join(" and ")
+    );
+    let mut stream = sqlx::query(&query);
+    binds!(args.clone(), stream);
+    let rows = instrument(
+        settings,
+        "fixtures",
+        "resolve",
+        &query,
+        stream.fetch_all(executor),
+    )
+    .await
+    .map_err(|err| Error::new(settings, &query, args.clone(), err))?;
+    let [row] = rows.as_slice() else {
+        let key = keys
+            .iter()
+            .zip(&args)
+            .map(|(key, arg)| format!("{key} = {arg}"))
+            .collect::<Vec<_>>()
+            .join(" and ");
+        let found = if rows.is_empty() { "no" } else { "several" };
+        bail!("{found} rows of {table} match the reference where {key}");
+    };
+    Ok(match row.try_get::<i64, _>(0) {
+        Ok(id) => Arg::Int(id),
+        Err(_) => Arg::Text(row.try_get::<String, _>(0)?),
+    })
+}
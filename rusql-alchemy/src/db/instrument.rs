@@ -0,0 +1,548 @@
+//! Instrumentation of the statements sent to the database.
+//!
+//! Every statement run by the crate goes through `instrument`, with the `QuerySettings` of the
+//! pool it is sent to. With the `tracing` feature it is wrapped in a `query` span and followed
+//! by an event with its duration and row count. Statements slower than the threshold set with
+//! `Database::log_slow_queries` are passed to the slow-query callback of the database. With the
+//! `metrics` feature, the number of statements and their duration are recorded per model and
+//! operation:
+//!
+//! - `rusql_alchemy_queries_total` (counter, labels `model`, `operation`, `status`)
+//! - `rusql_alchemy_query_duration_seconds` (histogram, labels `model`, `operation`)
+//!
+//! Raw statements run with `Database::execute` and `Database::fetch` use the `raw` model.
+//!
+//! Statements running longer than the statement timeout of the database are abandoned and fail
+//! with `Error::Timeout`, see `Database::set_statement_timeout` and `with_timeout`. The pools
+//! opened by `Database::with_config` also set the timeout on their postgres and mysql
+//! connections, so the server cancels the statements as well.
+//!
+//! The statements generated by the crate can be prefixed with a `/* ... */` comment, see
+//! `Database::set_query_tag` and `SelectBuilder::comment`, so they are traceable in
+//! `pg_stat_statements` and the slow-query logs of the server. Like the slow-query log, the tag
+//! belongs to the settings of the pool.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+use sqlx::any::{AnyConnectOptions, AnyQueryResult, AnyRow};
+
+use super::backend::Backend;
+use super::encryption::Cipher;
+use crate::{Connection, DatabaseConfig};
+
+/// A callback receiving the SQL and the elapsed time of a slow statement.
+pub type SlowQueryCallback = Arc<dyn Fn(&str, Duration) + Send + Sync>;
+
+struct SlowQueryLog {
+    threshold: Duration,
+    callback: SlowQueryCallback,
+}
+
+/// The instrumentation settings of a `Database`.
+///
+/// They are attached to its pools, so the `Model` methods, which are given a pool, follow the
+/// settings of the database it belongs to.
+#[derive(Default)]
+pub(crate) struct QuerySettings {
+    slow_query_log: RwLock<Option<SlowQueryLog>>,
+    query_tag: RwLock<Option<String>>,
+    statement_timeout: RwLock<Option<Duration>>,
+    redact_params: bool,
+    /// The cipher of the `ENCRYPTED` columns, see `Database::set_cipher`.
+    cipher: RwLock<Option<Arc<dyn Cipher>>>,
+    /// The server timeouts set on the postgres and mysql connections of the pools.
+    connection_timeouts: Mutex<Vec<ConnectionTimeout>>,
+}
+
+/// The server timeout set on a connection of a pool.
+///
+/// sqlx gives the hooks of a pool no identity of the connection, so it is found from when it
+/// was opened, which the hooks compute from the age of the connection.
+struct ConnectionTimeout {
+    opened: Instant,
+    timeout: Option<Duration>,
+    seen: Instant,
+}
+
+/// How far apart the opening instants computed for the same connection can be.
+const SAME_CONNECTION: Duration = Duration::from_millis(1);
+
+/// How long the timeout of a connection is remembered after it was last acquired, the default
+/// idle timeout of the pools.
+const FORGET_CONNECTION: Duration = Duration::from_secs(600);
+
+lazy_static! {
+    /// The settings of the pools, found from their connect options, which the clones of a pool
+    /// share.
+    static ref POOL_SETTINGS: RwLock<Vec<(Weak<AnyConnectOptions>, Arc<QuerySettings>)>> =
+        RwLock::new(Vec::new());
+    /// The settings of the pools opened without a `Database`.
+    static ref DEFAULT_SETTINGS: Arc<QuerySettings> = Arc::default();
+}
+
+tokio::task_local! {
+    static TIMEOUT_OVERRIDE: Option<Duration>;
+}
+
+/// The source of the `sqlx::Error::Io` of a statement abandoned after the statement timeout,
+/// turned into `Error::Timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StatementTimeout(pub(crate) Duration);
+
+impl fmt::Display for StatementTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "statement timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for StatementTimeout {}
+
+/// Runs `future` with another statement timeout for the statements it sends, `None` disabling
+/// the timeout.
+///
+/// This task-local wrapper is the only way to override the timeout of the database for some
+/// calls: the `Model` methods take no per-call options. On postgres and mysql, the connections
+/// acquired by `future` from a pool opened with `Database::with_config` get the timeout on the
+/// server too, and get the one of their next task back when they are acquired again. A
+/// transaction keeps the one of its connection when it began.
+///
+/// # Example
+/// ```rust
+/// // this report is allowed to take longer than the usual statements
+/// let rows = with_timeout(Some(Duration::from_secs(30)), User::filter(kw, &conn)).await;
+/// ```
+pub async fn with_timeout<F: Future>(timeout: Option<Duration>, future: F) -> F::Output {
+    TIMEOUT_OVERRIDE.scope(timeout, future).await
+}
+
+impl QuerySettings {
+    /// Returns the settings of a database opened with `config`.
+    pub(crate) fn from_config(config: &DatabaseConfig) -> Arc<QuerySettings> {
+        let settings = QuerySettings {
+            redact_params: config.redact_params,
+            ..QuerySettings::default()
+        };
+        if config.tag_queries {
+            settings.set_query_tag(config.application_name.clone());
+        }
+        *settings.statement_timeout.write().unwrap() = config.statement_timeout;
+        Arc::new(settings)
+    }
+
+    /// Returns the settings attached to a pool, the defaults when it was not opened by a
+    /// `Database`.
+    pub(crate) fn of(conn: &Connection) -> Arc<QuerySettings> {
+        let options = conn.connect_options();
+        POOL_SETTINGS
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(pool, _)| std::ptr::eq(pool.as_ptr(), Arc::as_ptr(&options)))
+            .map_or_else(
+                || DEFAULT_SETTINGS.clone(),
+                |(_, settings)| settings.clone(),
+            )
+    }
+
+    /// Attaches settings to a pool, replacing the ones attached before.
+    pub(crate) fn attach(conn: &Connection, settings: Arc<QuerySettings>) {
+        let options = conn.connect_options();
+        let mut pools = POOL_SETTINGS.write().unwrap();
+        // the entries of the dropped pools are removed on the way
+        pools.retain(|(pool, _)| {
+            pool.strong_count() > 0 && !std::ptr::eq(pool.as_ptr(), Arc::as_ptr(&options))
+        });
+        pools.push((Arc::downgrade(&options), settings));
+    }
+
+    /// Whether the parameters are left out of the errors, see `DatabaseConfig::redact_params`.
+    pub(crate) fn redact_params(&self) -> bool {
+        self.redact_params
+    }
+
+    /// Sets the cipher of the encrypted columns, or removes it when it is `None`.
+    pub(crate) fn set_cipher(&self, cipher: Option<Arc<dyn Cipher>>) {
+        *self.cipher.write().unwrap() = cipher;
+    }
+
+    /// Returns the cipher of the encrypted columns.
+    ///
+    /// # Errors
+    /// Returns an error if no cipher is set.
+    pub(crate) fn cipher(&self) -> anyhow::Result<Arc<dyn Cipher>> {
+        self.cipher
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("no cipher is set, see `Database::set_cipher`"))
+    }
+
+    /// Abandons the statements running longer than `timeout`, or never when it is `None`.
+    pub(crate) fn set_statement_timeout(&self, timeout: Option<Duration>) {
+        *self.statement_timeout.write().unwrap() = timeout;
+    }
+
+    /// Returns the timeout of the statements sent by the current task: the one of `with_timeout`,
+    /// or the default of the database.
+    pub(crate) fn statement_timeout(&self) -> Option<Duration> {
+        TIMEOUT_OVERRIDE
+            .try_with(|timeout| *timeout)
+            .unwrap_or_else(|_| *self.statement_timeout.read().unwrap())
+    }
+
+    /// Returns the statement setting `timeout` on a connection of `backend` opened at `opened`,
+    /// `None` when the connection already has it.
+    ///
+    /// A new connection, given `None`, has no timeout. A connection taken back from the pool
+    /// has the timeout last set on it, or an unknown one when it was opened too close to
+    /// another connection to tell them apart.
+    pub(crate) fn server_timeout(
+        &self,
+        backend: Backend,
+        opened: Option<Instant>,
+        timeout: Option<Duration>,
+    ) -> Option<String> {
+        let current = match opened {
+            None => Some(None),
+            Some(opened) => {
+                let mut connections = self.connection_timeouts.lock().unwrap();
+                let mut found = connections
+                    .iter_mut()
+                    .filter(|connection| apart(connection.opened, opened) <= SAME_CONNECTION);
+                match (found.next(), found.next()) {
+                    (Some(connection), None) => {
+                        connection.seen = Instant::now();
+                        Some(connection.timeout)
+                    }
+                    _ => None,
+                }
+            }
+        };
+        if current == Some(timeout) {
+            return None;
+        }
+        // 0 disables the timeout on both servers
+        let millis = timeout.map_or(0, |timeout| timeout.as_millis());
+        match backend {
+            Backend::Postgres => Some(format!("set statement_timeout = {millis};")),
+            Backend::MySql => Some(format!("set session max_execution_time = {millis};")),
+            Backend::Sqlite => None,
+        }
+    }
+
+    /// Remembers that the connection opened at `opened` has `timeout`, forgetting the
+    /// connections not acquired for a while, which the pool has likely closed.
+    pub(crate) fn set_server_timeout(&self, opened: Instant, timeout: Option<Duration>) {
+        let mut connections = self.connection_timeouts.lock().unwrap();
+        connections.retain(|connection| {
+            connection.seen.elapsed() < FORGET_CONNECTION
+                && apart(connection.opened, opened) > SAME_CONNECTION
+        });
+        connections.push(ConnectionTimeout {
+            opened,
+            timeout,
+            seen: Instant::now(),
+        });
+    }
+
+    /// Prefixes the statements generated by the crate with `/* tag */`, or with nothing when it
+    /// is `None`.
+    pub(crate) fn set_query_tag(&self, tag: Option<String>) {
+        *self.query_tag.write().unwrap() = tag;
+    }
+
+    /// Prefixes a generated statement with the query tag.
+    pub(crate) fn tagged(&self, query: String) -> String {
+        self.commented(query, None)
+    }
+
+    /// Prefixes a generated statement with the query tag and `comment`.
+    pub(crate) fn commented(&self, query: String, comment: Option<&str>) -> String {
+        let tag = self.query_tag.read().unwrap();
+        let comments = tag
+            .as_deref()
+            .into_iter()
+            .chain(comment)
+            .map(|text| {
+                // a comment must not end early, nor open a nested comment on postgres
+                let text = text.replace("*/", "* /").replace("/*", "/ *");
+                format!("/* {text} */ ")
+            })
+            .collect::<String>();
+        format!("{comments}{query}")
+    }
+
+    /// Reports statements slower than `threshold` to `callback`, or to the default logger when
+    /// `callback` is `None`.
+    pub(crate) fn set_slow_query_log(
+        &self,
+        threshold: Duration,
+        callback: Option<SlowQueryCallback>,
+    ) {
+        let mut log = self.slow_query_log.write().unwrap();
+        let callback = callback
+            .or_else(|| log.as_ref().map(|log| log.callback.clone()))
+            .unwrap_or_else(|| Arc::new(log_slow_query));
+        *log = Some(SlowQueryLog {
+            threshold,
+            callback,
+        });
+    }
+
+    /// Replaces the slow-query callback, keeping the threshold. Nothing is reported until a
+    /// threshold is set.
+    pub(crate) fn set_slow_query_callback(&self, callback: SlowQueryCallback) {
+        let mut log = self.slow_query_log.write().unwrap();
+        let threshold = log.as_ref().map_or(Duration::MAX, |log| log.threshold);
+        *log = Some(SlowQueryLog {
+            threshold,
+            callback,
+        });
+    }
+}
+
+fn log_slow_query(sql: &str, elapsed: Duration) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        db.statement = sql,
+        elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+        "slow query"
+    );
+    #[cfg(not(feature = "tracing"))]
+    eprintln!("slow query ({elapsed:?}): {sql}");
+}
+
+/// The number of rows returned or affected by a statement.
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+pub(crate) trait RowCount {
+    fn row_count(&self) -> u64;
+}
+
+impl RowCount for AnyQueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+impl RowCount for AnyRow {
+    fn row_count(&self) -> u64 {
+        1
+    }
+}
+
+impl RowCount for u64 {
+    fn row_count(&self) -> u64 {
+        *self
+    }
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl<T> RowCount for Option<T> {
+    fn row_count(&self) -> u64 {
+        self.is_some() as u64
+    }
+}
+
+fn timed_out(timeout: Duration) -> sqlx::Error {
+    sqlx::Error::Io(io::Error::new(
+        io::ErrorKind::TimedOut,
+        StatementTimeout(timeout),
+    ))
+}
+
+/// Returns the time between two instants, in either order.
+fn apart(a: Instant, b: Instant) -> Duration {
+    a.saturating_duration_since(b)
+        .max(b.saturating_duration_since(a))
+}
+
+/// Whether the server cancelled a statement on its own timeout, `statement_timeout` on
+/// postgres and `max_execution_time` on mysql.
+fn cancelled_by_server(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(err) => {
+            (err.code().as_deref() == Some("57014") && err.message().contains("statement timeout"))
+                || err
+                    .message()
+                    .contains("maximum statement execution time exceeded")
+        }
+        _ => false,
+    }
+}
+
+/// Runs a statement of `model` issued by the `operation` method and reports it with the
+/// settings of the pool it is sent to.
+pub(crate) async fn instrument<T, F>(
+    settings: &QuerySettings,
+    model: &'static str,
+    operation: &'static str,
+    sql: &str,
+    statement: F,
+) -> Result<T, sqlx::Error>
+where
+    T: RowCount,
+    F: Future<Output = Result<T, sqlx::Error>>,
+{
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!(
+        "query",
+        db.model = model,
+        db.operation = operation,
+        db.statement = sql
+    );
+    #[cfg(feature = "tracing")]
+    let statement = tracing::Instrument::instrument(statement, span.clone());
+
+    let timeout = settings.statement_timeout();
+
+    let start = Instant::now();
+    let result = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, statement).await {
+            // the server may cancel the statement before the client gives up on it
+            Ok(Err(err)) if cancelled_by_server(&err) => Err(timed_out(timeout)),
+            Ok(result) => result,
+            Err(_) => Err(timed_out(timeout)),
+        },
+        None => statement.await,
+    };
+    let elapsed = start.elapsed();
+
+    #[cfg(feature = "tracing")]
+    span.in_scope(|| match &result {
+        Ok(rows) => tracing::debug!(
+            elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+            rows = rows.row_count(),
+            "query succeeded"
+        ),
+        Err(err) => tracing::error!(
+            elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+            error = %err,
+            "query failed"
+        ),
+    });
+
+    #[cfg(feature = "metrics")]
+    {
+        let status = if result.is_ok() { "ok" } else { "error" };
+        metrics::counter!(
+            "rusql_alchemy_queries_total",
+            "model" => model,
+            "operation" => operation,
+            "status" => status
+        )
+        .increment(1);
+        metrics::histogram!(
+            "rusql_alchemy_query_duration_seconds",
+            "model" => model,
+            "operation" => operation
+        )
+        .record(elapsed.as_secs_f64());
+    }
+    #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+    let _ = (model, operation);
+
+    let slow = settings
+        .slow_query_log
+        .read()
+        .unwrap()
+        .as_ref()
+        .filter(|log| elapsed >= log.threshold)
+        .map(|log| log.callback.clone());
+    if let Some(callback) = slow {
+        callback(sql, elapsed);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SET_5S: &str = "set statement_timeout = 5000;";
+
+    #[test]
+    fn sets_the_timeout_of_new_connections() {
+        let settings = QuerySettings::default();
+        let timeout = Some(Duration::from_secs(5));
+        assert_eq!(settings.server_timeout(Backend::Postgres, None, None), None);
+        assert_eq!(
+            settings.server_timeout(Backend::Postgres, None, timeout),
+            Some(SET_5S.to_string())
+        );
+        assert_eq!(
+            settings.server_timeout(Backend::MySql, None, timeout),
+            Some("set session max_execution_time = 5000;".to_string())
+        );
+        assert_eq!(
+            settings.server_timeout(Backend::Sqlite, None, timeout),
+            None
+        );
+    }
+
+    #[test]
+    fn sets_the_timeout_of_a_connection_only_when_it_differs() {
+        let settings = QuerySettings::default();
+        let timeout = Some(Duration::from_secs(5));
+        let opened = Instant::now();
+        // an unknown connection may hold any timeout
+        assert_eq!(
+            settings.server_timeout(Backend::Postgres, Some(opened), timeout),
+            Some(SET_5S.to_string())
+        );
+        settings.set_server_timeout(opened, timeout);
+        let later = opened + SAME_CONNECTION / 2;
+        assert_eq!(
+            settings.server_timeout(Backend::Postgres, Some(later), timeout),
+            None
+        );
+        assert_eq!(
+            settings.server_timeout(Backend::Postgres, Some(later), None),
+            Some("set statement_timeout = 0;".to_string())
+        );
+        settings.set_server_timeout(later, None);
+        assert_eq!(
+            settings.server_timeout(Backend::Postgres, Some(opened), None),
+            None
+        );
+    }
+
+    #[test]
+    fn sets_the_timeout_of_connections_opened_together() {
+        let settings = QuerySettings::default();
+        let timeout = Some(Duration::from_secs(5));
+        let opened = Instant::now();
+        settings.set_server_timeout(opened, timeout);
+        let other = opened + Duration::from_secs(1);
+        settings.set_server_timeout(other, timeout);
+        assert_eq!(
+            settings.server_timeout(Backend::Postgres, Some(other), timeout),
+            None
+        );
+        let close = opened + SAME_CONNECTION / 2;
+        settings
+            .connection_timeouts
+            .lock()
+            .unwrap()
+            .push(ConnectionTimeout {
+                opened: close,
+                timeout,
+                seen: Instant::now(),
+            });
+        assert_eq!(
+            settings.server_timeout(Backend::Postgres, Some(opened), timeout),
+            Some(SET_5S.to_string())
+        );
+    }
+}
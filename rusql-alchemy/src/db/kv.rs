@@ -0,0 +1,141 @@
+//! A key-value store in the `_rusql_kv` table, e.g. for feature flags and application settings.
+//!
+//! The values are stored as JSON, so any `Serialize` type can be written and read back with
+//! `get::<T>`:
+//!
+//! - `name`, the key
+//! - `value`, the JSON of the value
+//! - `updated_at`, the unix timestamp of the last write, in seconds
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::Row;
+
+use super::backend::Backend;
+use super::models::Arg;
+use super::{instrument, QuerySettings};
+use crate::{Connection, Error};
+
+/// The table of the key-value store.
+pub const KV_TABLE: &str = "_rusql_kv";
+
+/// The key-value store of a database, returned by `Database::kv`.
+///
+/// # Example
+/// ```rust
+/// let kv = db.kv();
+/// kv.migrate().await?;
+/// kv.set("signup_enabled", &true).await?;
+/// if kv.get::<bool>("signup_enabled").await?.unwrap_or(false) {
+///     // ...
+/// }
+/// ```
+pub struct KvStore<'a> {
+    conn: &'a Connection,
+}
+
+impl<'a> KvStore<'a> {
+    pub(crate) fn new(conn: &'a Connection) -> Self {
+        Self { conn }
+    }
+
+    /// Creates the table of the store if it does not exist.
+    pub async fn migrate(&self) -> Result<()> {
+        let query = format!(
+            "create table if not exists {KV_TABLE} (
+                name varchar(255) primary key,
+                value text not null,
+                updated_at bigint not null
+            );"
+        );
+        self.execute("migrate", &query, Vec::new())
+            .await
+            .map(|_| ())
+    }
+
+    /// Returns the value of `key`, or `None` if it is not set.
+    ///
+    /// # Errors
+    /// Returns an error if the statement fails or the value does not match `T`.
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let query = format!(
+            "select value from {KV_TABLE} where name = {};",
+            self.placeholder(1)
+        );
+        let args = vec![Arg::from(key)];
+        let row = instrument(
+            &QuerySettings::of(self.conn),
+            KV_TABLE,
+            "get",
+            &query,
+            sqlx::query(&query).bind(key).fetch_optional(self.conn),
+        )
+        .await
+        .map_err(|err| Error::new(&QuerySettings::of(self.conn), &query, args, err))?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let value: String = row.try_get(0)?;
+        Ok(Some(serde_json::from_str(&value)?))
+    }
+
+    /// Sets the value of `key`, replacing the previous one.
+    pub async fn set<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<()> {
+        let upsert = match Backend::of(self.conn) {
+            Backend::MySql => {
+                "on duplicate key update value = values(value), updated_at = values(updated_at)"
+            }
+            _ => {
+                "on conflict (name) do update set value = excluded.value, \
+                 updated_at = excluded.updated_at"
+            }
+        };
+        let query = format!(
+            "insert into {KV_TABLE} (name, value, updated_at) values ({}, {}, {}) {upsert};",
+            self.placeholder(1),
+            self.placeholder(2),
+            self.placeholder(3)
+        );
+        let updated_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        let args = vec![
+            Arg::from(key),
+            Arg::from(serde_json::to_string(value)?),
+            Arg::from(updated_at),
+        ];
+        self.execute("set", &query, args).await.map(|_| ())
+    }
+
+    /// Removes `key`.
+    ///
+    /// # Returns
+    /// `true` if the key was set.
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        let query = format!(
+            "delete from {KV_TABLE} where name = {};",
+            self.placeholder(1)
+        );
+        let deleted = self.execute("delete", &query, vec![Arg::from(key)]).await?;
+        Ok(deleted > 0)
+    }
+
+    async fn execute(&self, op: &'static str, query: &str, args: Vec<Arg>) -> Result<u64> {
+        let mut stream = sqlx::query(query);
+        binds!(args.clone(), stream);
+        let result = instrument(
+            &QuerySettings::of(self.conn),
+            KV_TABLE,
+            op,
+            query,
+            stream.execute(self.conn),
+        )
+        .await
+        .map_err(|err| Error::new(&QuerySettings::of(self.conn), query, args, err))?;
+        Ok(result.rows_affected())
+    }
+
+    fn placeholder(&self, index: usize) -> String {
+        Backend::of(self.conn).placeholder(index)
+    }
+}
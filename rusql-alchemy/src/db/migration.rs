@@ -0,0 +1,1047 @@
+//! Versioned migrations.
+//!
+//! A `Migration` is an ordered unit of schema changes identified by a version number.
+//! The versions already applied to a database are recorded in the `_rusql_migrations`
+//! table, so running the same `Migrations` again only applies the pending ones.
+//!
+//! Each migration is applied in a transaction on the backends supporting transactional DDL
+//! (sqlite and postgres), and recorded as applied only once it is committed.
+//!
+//! Concurrent runs, e.g. from several replicas starting together, are serialized by a lock
+//! (an advisory lock on postgres and mysql, a lock row on sqlite).
+//!
+//! Models can also be registered without a version: their tables are compared with the
+//! live database on every run and the missing tables, columns and unique indexes are added.
+//! The monthly partitions of the partitioned tables are created ahead in the same way.
+
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use sqlx::{pool::PoolConnection, Any, Executor, Row};
+
+use super::backend::{Backend, Capabilities};
+use super::models::{Arg, Model, DEFAULT_DATABASE};
+use super::schema::{self, ModelSchema};
+use super::sequence;
+use super::{instrument, QuerySettings};
+use crate::{Connection, Error};
+
+/// The table recording the applied migrations.
+pub const MIGRATIONS_TABLE: &str = "_rusql_migrations";
+
+type DataFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// A data migration step: async Rust code run against the connection.
+#[derive(Clone)]
+pub struct DataStep(Arc<dyn Fn(Connection) -> DataFuture + Send + Sync>);
+
+impl fmt::Debug for DataStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DataStep")
+    }
+}
+
+impl PartialEq for DataStep {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// The statements creating the table of a model, rendered in the dialect of the database the
+/// migration is applied to, see `Model::up`.
+#[derive(Clone, Copy)]
+pub struct ModelStep(fn(Backend) -> Vec<String>);
+
+impl fmt::Debug for ModelStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ModelStep")
+    }
+}
+
+impl PartialEq for ModelStep {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::fn_addr_eq(self.0, other.0)
+    }
+}
+
+/// A unit of schema changes, applied once per database.
+///
+/// # Example
+/// ```
+/// let migration = Migration::new(2, "add user index")
+///     .up("create index user_email_idx on user (email);")
+///     .down("drop index user_email_idx;");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    /// The version of the migration, migrations are applied in increasing order.
+    pub version: i64,
+    /// A short description of the migration.
+    pub name: String,
+    /// The statements creating the table of a model, run before the `up` statements.
+    pub model: Option<ModelStep>,
+    /// The statements applying the migration.
+    pub up: Vec<String>,
+    /// The statements reverting the migration.
+    pub down: Vec<String>,
+    /// The data migration steps, run in order after the `up` statements.
+    pub data: Vec<DataStep>,
+    /// The environments the migration is restricted to, empty when it applies everywhere.
+    pub env: Vec<String>,
+    /// The name of the database the migration applies to in a `DatabaseRegistry`.
+    pub database: String,
+}
+
+impl Migration {
+    /// Creates an empty migration.
+    pub fn new(version: i64, name: impl Into<String>) -> Self {
+        Self {
+            version,
+            name: name.into(),
+            model: None,
+            up: Vec::new(),
+            down: Vec::new(),
+            data: Vec::new(),
+            env: Vec::new(),
+            database: DEFAULT_DATABASE.to_string(),
+        }
+    }
+
+    /// Creates the migration creating the table of a model and its unique indexes, in the
+    /// dialect of the database it is applied to.
+    ///
+    /// # Example
+    /// ```
+    /// let migration = Migration::model::<User>(1);
+    /// ```
+    pub fn model<M: Model>(version: i64) -> Self {
+        let mut migration = Self::new(version, format!("create {}", M::NAME));
+        migration.model = Some(ModelStep(M::up));
+        migration.env = M::ENV.iter().map(|env| env.to_string()).collect();
+        migration.database = M::DATABASE.to_string();
+        migration.down(format!("drop table if exists {};", M::NAME))
+    }
+
+    /// Returns the statements applying the migration in the dialect of `backend`.
+    pub fn statements(&self, backend: Backend) -> Vec<String> {
+        let mut statements = self.model.map(|step| (step.0)(backend)).unwrap_or_default();
+        statements.extend(self.up.iter().cloned());
+        statements
+    }
+
+    /// Adds a statement applying the migration.
+    pub fn up(mut self, sql: impl Into<String>) -> Self {
+        self.up.push(sql.into());
+        self
+    }
+
+    /// Adds a statement reverting the migration.
+    pub fn down(mut self, sql: impl Into<String>) -> Self {
+        self.down.push(sql.into());
+        self
+    }
+
+    /// Restricts the migration to an environment, see `DatabaseConfig::environment`.
+    ///
+    /// A migration restricted to several environments is applied when one of them is enabled.
+    ///
+    /// # Example
+    /// ```
+    /// let migration = Migration::new(4, "seed test accounts")
+    ///     .up("insert into user (name) values ('test');")
+    ///     .env("test");
+    /// ```
+    pub fn env(mut self, env: impl Into<String>) -> Self {
+        self.env.push(env.into());
+        self
+    }
+
+    /// Applies the migration to a named database of a `DatabaseRegistry` instead of the
+    /// default one.
+    pub fn database(mut self, name: impl Into<String>) -> Self {
+        self.database = name.into();
+        self
+    }
+
+    /// Adds a data migration step, run after the `up` statements with the same versioning as
+    /// the schema changes, e.g. to backfill a new column.
+    ///
+    /// # Example
+    /// ```
+    /// let migration = Migration::new(3, "backfill user roles")
+    ///     .up("alter table user add column role varchar(20);")
+    ///     .run(|conn| async move {
+    ///         for user in User::all(&conn).await {
+    ///             let role = if user.admin.is_true() { "admin" } else { "user" };
+    ///             User::set(user.id, kwargs!(role = role), &conn).await;
+    ///         }
+    ///         Ok(())
+    ///     });
+    /// ```
+    pub fn run<F, Fut>(mut self, step: F) -> Self
+    where
+        F: Fn(Connection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.data
+            .push(DataStep(Arc::new(move |conn| Box::pin(step(conn)))));
+        self
+    }
+}
+
+/// An ordered set of migrations.
+///
+/// # Example
+/// ```
+/// let migrations = Migrations::new()
+///     .with(Migration::model::<User>(1))
+///     .with(Migration::model::<Product>(2));
+///
+/// let applied = db.migrate(&migrations).await?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Migrations {
+    migrations: Vec<Migration>,
+    models: Vec<ModelSchema>,
+    partitions: Vec<(ModelSchema, u32)>,
+}
+
+impl Migrations {
+    /// Creates an empty set of migrations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a migration, keeping the set ordered by version.
+    pub fn with(mut self, migration: Migration) -> Self {
+        let index = self
+            .migrations
+            .partition_point(|m| m.version <= migration.version);
+        self.migrations.insert(index, migration);
+        self
+    }
+
+    /// Registers a model whose table is kept in line with its schema: on every run the table
+    /// and its sequences are created if they are missing, and the missing columns and unique
+    /// indexes are added.
+    ///
+    /// Tables are created after the tables referenced by their foreign keys, whatever the
+    /// registration order.
+    ///
+    /// Columns are never dropped or altered. Keep in mind that sqlite cannot add a `not null`
+    /// column without a default value to a table.
+    ///
+    /// # Example
+    /// ```
+    /// let migrations = Migrations::new().model::<User>().model::<Product>();
+    /// ```
+    pub fn model<M: Model>(mut self) -> Self {
+        self.models.push(ModelSchema::of::<M>());
+        self
+    }
+
+    /// Registers a table with a `Model::PARTITION_BY` such as `"range(column)"` whose monthly
+    /// partitions are created on every run, from the current month to `ahead` months later,
+    /// e.g. for append-heavy event tables.
+    ///
+    /// Postgres rejects the rows outside of the existing partitions, so `ahead` should cover
+    /// the time between two runs. Only postgres tables are partitioned, the other databases
+    /// ignore it. The model itself is registered with `model`.
+    ///
+    /// # Example
+    /// ```
+    /// impl Model for Event {
+    ///     // the primary key of a partitioned table includes the partition column
+    ///     const SCHEMA: &'static str = "create table if not exists event (\
+    ///         id serial, at timestamp not null, kind text not null, primary key (id, at));";
+    ///     const PARTITION_BY: &'static str = "range(at)";
+    ///     // NAME, PK, save, update and delete, see `Model`
+    /// }
+    ///
+    /// let migrations = Migrations::new().model::<Event>().monthly_partitions::<Event>(3);
+    /// ```
+    pub fn monthly_partitions<M: Model>(mut self, ahead: u32) -> Self {
+        self.partitions.push((ModelSchema::of::<M>(), ahead));
+        self
+    }
+
+    /// Loads the SQL migrations of a directory.
+    ///
+    /// Each migration is a `<version>_<name>.up.sql` file, run as a single script, with an
+    /// optional `<version>_<name>.down.sql` file reverting it, as created by `rusql generate`. A missing directory is an empty
+    /// set of migrations.
+    ///
+    /// # Example
+    /// ```
+    /// // migrations/1_create_user.up.sql, migrations/1_create_user.down.sql
+    /// let migrations = Migrations::from_dir("migrations")?.model::<Product>();
+    /// ```
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut migrations = Self::new();
+        if !dir.exists() {
+            return Ok(migrations);
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(stem) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".up.sql"))
+            else {
+                continue;
+            };
+            let Some((version, name)) = stem
+                .split_once('_')
+                .and_then(|(version, name)| Some((version.parse::<i64>().ok()?, name)))
+            else {
+                bail!("{} is not named <version>_<name>.up.sql", path.display());
+            };
+            let mut migration =
+                Migration::new(version, name.replace('_', " ")).up(fs::read_to_string(&path)?);
+            let down = path.with_file_name(format!("{stem}.down.sql"));
+            if down.exists() {
+                let sql = fs::read_to_string(down)?;
+                // a down file holding only comments cannot revert anything
+                if sql
+                    .lines()
+                    .any(|line| !line.trim().is_empty() && !line.trim().starts_with("--"))
+                {
+                    migration = migration.down(sql);
+                }
+            }
+            migrations = migrations.with(migration);
+        }
+        Ok(migrations)
+    }
+
+    /// Returns the migrations and models that apply in the given environments: those without
+    /// environment and those restricted to one of them.
+    pub fn for_environments(&self, enabled: &[String]) -> Self {
+        Self {
+            migrations: self
+                .migrations
+                .iter()
+                .filter(|migration| {
+                    migration.env.is_empty()
+                        || migration.env.iter().any(|env| enabled.contains(env))
+                })
+                .cloned()
+                .collect(),
+            models: self
+                .models
+                .iter()
+                .filter(|model| {
+                    model.env.is_empty()
+                        || model.env.iter().any(|env| enabled.iter().any(|e| e == env))
+                })
+                .copied()
+                .collect(),
+            partitions: self
+                .partitions
+                .iter()
+                .filter(|(model, _)| {
+                    model.env.is_empty()
+                        || model.env.iter().any(|env| enabled.iter().any(|e| e == env))
+                })
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Returns the migrations and models of a named database of a `DatabaseRegistry`.
+    pub fn for_database(&self, name: &str) -> Self {
+        Self {
+            migrations: self
+                .migrations
+                .iter()
+                .filter(|migration| migration.database == name)
+                .cloned()
+                .collect(),
+            models: self
+                .models
+                .iter()
+                .filter(|model| model.database == name)
+                .copied()
+                .collect(),
+            partitions: self
+                .partitions
+                .iter()
+                .filter(|(model, _)| model.database == name)
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Returns the schemas of the registered models.
+    pub fn models(&self) -> impl Iterator<Item = &ModelSchema> {
+        self.models.iter()
+    }
+
+    /// Returns the migrations ordered by version.
+    pub fn iter(&self) -> impl Iterator<Item = &Migration> {
+        self.migrations.iter()
+    }
+
+    /// Returns the versions recorded as applied, in increasing order.
+    pub async fn applied(conn: &Connection) -> Result<Vec<i64>> {
+        Ok(recorded(conn)
+            .await?
+            .into_iter()
+            .map(|(version, _)| version)
+            .collect())
+    }
+
+    /// Applies the pending migrations in order and records them.
+    ///
+    /// # Returns
+    /// The versions that were applied.
+    ///
+    /// # Errors
+    /// Fails on duplicated versions, and stops at the first failing statement.
+    pub async fn run(&self, conn: &Connection) -> Result<Vec<i64>> {
+        let lock = MigrationLock::acquire(conn).await?;
+        let result = self.apply_pending(conn).await;
+        lock.release().await?;
+        result
+    }
+
+    async fn apply_pending(&self, conn: &Connection) -> Result<Vec<i64>> {
+        let mut versions = Vec::new();
+        for migration in self.pending(conn).await? {
+            apply(conn, migration).await?;
+            versions.push(migration.version);
+        }
+        let transactional = Capabilities::of(conn).transactional_ddl;
+        for model in self.ordered_models()? {
+            for (_, name) in model.sequences {
+                sequence::create(conn, name).await?;
+            }
+            let changes = schema::diff(conn, model).await?;
+            if transactional && !changes.is_empty() {
+                let mut tx = conn.begin().await?;
+                for change in changes {
+                    execute(&mut *tx, &QuerySettings::of(conn), &change.to_string()).await?;
+                }
+                tx.commit().await?;
+            } else {
+                for change in changes {
+                    execute(conn, &QuerySettings::of(conn), &change.to_string()).await?;
+                }
+            }
+        }
+        for sql in self.missing_partitions(conn).await? {
+            execute(conn, &QuerySettings::of(conn), &sql).await?;
+        }
+        Ok(versions)
+    }
+
+    /// Returns the statements creating the monthly partitions missing from the database.
+    async fn missing_partitions(&self, conn: &Connection) -> Result<Vec<String>> {
+        if Backend::of(conn) != Backend::Postgres {
+            return Ok(Vec::new());
+        }
+        let (year, month) = current_month();
+        let query = format!(
+            "select count(*) from pg_class where relname = {};",
+            Backend::Postgres.placeholder(1)
+        );
+        let mut statements = Vec::new();
+        for (model, ahead) in &self.partitions {
+            for (name, sql) in model.partitions(year, month, ahead + 1) {
+                let args = vec![Arg::from(name)];
+                let mut stream = sqlx::query(&query);
+                binds!(args.clone(), stream);
+                let exists: i64 = instrument(
+                    &QuerySettings::of(conn),
+                    MIGRATIONS_TABLE,
+                    "partitions",
+                    &query,
+                    stream.fetch_one(conn),
+                )
+                .await
+                .and_then(|row| row.try_get(0))
+                .map_err(|err| Error::new(&QuerySettings::of(conn), &query, args, err))?;
+                if exists == 0 {
+                    statements.push(sql);
+                }
+            }
+        }
+        Ok(statements)
+    }
+
+    /// Returns the statements `run` would execute, without executing them.
+    ///
+    /// The model changes are computed against the current database, so changes depending on
+    /// pending versioned migrations may be listed too.
+    ///
+    /// # Example
+    /// ```
+    /// for sql in migrations.plan(&conn).await? {
+    ///     println!("{sql}");
+    /// }
+    /// ```
+    pub async fn plan(&self, conn: &Connection) -> Result<Vec<String>> {
+        let mut statements = Vec::new();
+        for migration in self.pending(conn).await? {
+            statements.extend(migration.statements(Backend::of(conn)));
+            for index in 1..=migration.data.len() {
+                statements.push(format!(
+                    "-- data migration step {index} of version {}",
+                    migration.version
+                ));
+            }
+        }
+        for model in self.ordered_models()? {
+            for change in schema::diff(conn, model).await? {
+                statements.push(change.to_string());
+            }
+        }
+        statements.extend(self.missing_partitions(conn).await?);
+        Ok(statements)
+    }
+
+    /// Returns the script creating the whole schema from an empty database of `backend`: the
+    /// `up` statements of every migration in version order, then the tables and unique indexes
+    /// of the registered models.
+    ///
+    /// Data migration steps cannot be exported and are left as comments.
+    pub fn script(&self, backend: Backend) -> Result<String> {
+        let mut sections = Vec::new();
+        for migration in self.iter() {
+            let mut section = vec![format!("-- {} {}", migration.version, migration.name)];
+            section.extend(
+                migration
+                    .statements(backend)
+                    .iter()
+                    .map(|sql| statement(sql)),
+            );
+            for index in 1..=migration.data.len() {
+                section.push(format!("-- data migration step {index} not exported"));
+            }
+            sections.push(section.join("\n"));
+        }
+        for model in self.ordered_models()? {
+            let mut section = vec![format!("-- model {}", model.name)];
+            section.extend(model.up(backend).iter().map(|sql| statement(sql)));
+            sections.push(section.join("\n"));
+        }
+        if backend == Backend::Postgres {
+            let (year, month) = current_month();
+            for (model, ahead) in &self.partitions {
+                let mut section = vec![format!("-- partitions of {}", model.name)];
+                section.extend(model.monthly_partitions(year, month, ahead + 1));
+                sections.push(section.join("\n"));
+            }
+        }
+        Ok(sections.join("\n\n") + "\n")
+    }
+
+    /// Orders the registered models so that every table comes after the tables its foreign
+    /// keys reference, keeping the registration order otherwise.
+    fn ordered_models(&self) -> Result<Vec<&ModelSchema>> {
+        let mut remaining = self
+            .models
+            .iter()
+            .map(|model| (model, model.references()))
+            .collect::<Vec<_>>();
+        let mut ordered: Vec<&ModelSchema> = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            // a model is ready once every registered table it references is created
+            let ready = remaining.iter().position(|(model, references)| {
+                references.iter().all(|table| {
+                    table.eq_ignore_ascii_case(model.name)
+                        || ordered.iter().any(|m| m.name.eq_ignore_ascii_case(table))
+                        || !self
+                            .models
+                            .iter()
+                            .any(|m| m.name.eq_ignore_ascii_case(table))
+                })
+            });
+            match ready {
+                Some(index) => ordered.push(remaining.remove(index).0),
+                None => bail!(
+                    "circular foreign keys between the tables {}",
+                    remaining
+                        .iter()
+                        .map(|(model, _)| model.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+        }
+        Ok(ordered)
+    }
+
+    async fn pending(&self, conn: &Connection) -> Result<Vec<&Migration>> {
+        for pair in self.migrations.windows(2) {
+            if pair[0].version == pair[1].version {
+                bail!("duplicated migration version {}", pair[0].version);
+            }
+        }
+        let applied = Self::applied(conn).await?;
+        Ok(self
+            .iter()
+            .filter(|m| !applied.contains(&m.version))
+            .collect())
+    }
+
+    /// Reverts the last `n` applied migrations, newest first, with the down statements
+    /// recorded when they were applied.
+    ///
+    /// # Returns
+    /// The versions that were reverted.
+    ///
+    /// # Errors
+    /// Fails without reverting anything if one of the migrations has no down statements.
+    pub async fn rollback(conn: &Connection, n: usize) -> Result<Vec<i64>> {
+        let lock = MigrationLock::acquire(conn).await?;
+        let result = Self::revert_last(conn, n).await;
+        lock.release().await?;
+        result
+    }
+
+    async fn revert_last(conn: &Connection, n: usize) -> Result<Vec<i64>> {
+        let recorded = recorded(conn).await?;
+        let last = recorded.iter().rev().take(n).collect::<Vec<_>>();
+        if let Some((version, _)) = last.iter().find(|(_, down)| down.is_empty()) {
+            bail!("migration {version} has no down statements and cannot be rolled back");
+        }
+        let mut versions = Vec::new();
+        for (version, down) in last {
+            revert(conn, *version, down).await?;
+            versions.push(*version);
+        }
+        Ok(versions)
+    }
+
+    /// Drops the tables of the registered models, referencing tables first, and reverts every
+    /// recorded migration with its recorded down statements, newest first, then applies all the
+    /// migrations and creates the tables of the models again.
+    ///
+    /// This drops the data of the migrated tables, `run` never does.
+    ///
+    /// # Returns
+    /// The versions that were applied.
+    pub async fn reset(&self, conn: &Connection) -> Result<Vec<i64>> {
+        let lock = MigrationLock::acquire(conn).await?;
+        let result = async {
+            for model in self.ordered_models()?.into_iter().rev() {
+                let query = format!("drop table if exists {};", model.name);
+                execute(conn, &QuerySettings::of(conn), &query).await?;
+            }
+            for (version, down) in recorded(conn).await?.into_iter().rev() {
+                revert(conn, version, &down).await?;
+            }
+            self.apply_pending(conn).await
+        }
+        .await;
+        lock.release().await?;
+        result
+    }
+}
+
+/// The table holding the migration lock on sqlite.
+const LOCK_TABLE: &str = "_rusql_migrations_lock";
+
+/// The key of the postgres advisory lock, `rusql_mg` in ascii.
+const ADVISORY_LOCK_KEY: i64 = 0x7275_7371_6c5f_6d67;
+
+/// A sqlite lock row older than this is considered left behind by a crashed process.
+const STALE_LOCK: Duration = Duration::from_secs(600);
+
+/// How often the instance holding the sqlite lock row refreshes it, well before it turns stale.
+const REFRESH_LOCK: Duration = Duration::from_secs(150);
+
+/// A lock held while migrations are applied, so that only one of several instances starting
+/// at the same time migrates the database.
+///
+/// It is a session advisory lock on postgres (`pg_advisory_lock`) and mysql (`GET_LOCK`),
+/// held by a dedicated connection of the pool, and a row of `_rusql_migrations_lock` on sqlite,
+/// refreshed while it is held so long migrations do not lose it.
+enum MigrationLock {
+    Session(PoolConnection<Any>, Backend, Arc<QuerySettings>),
+    Row(Connection, RefreshTask),
+}
+
+/// The task refreshing the lock row, stopped when the lock is released or dropped.
+struct RefreshTask(tokio::task::JoinHandle<()>);
+
+impl Drop for RefreshTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl MigrationLock {
+    /// Waits until the lock is acquired.
+    async fn acquire(conn: &Connection) -> Result<Self> {
+        let backend = Backend::of(conn);
+        let query = match backend {
+            Backend::Postgres => format!("select pg_advisory_lock({ADVISORY_LOCK_KEY});"),
+            Backend::MySql => format!("select get_lock('{MIGRATIONS_TABLE}', -1);"),
+            Backend::Sqlite => return Self::acquire_row(conn).await,
+        };
+        let settings = QuerySettings::of(conn);
+        let mut session = conn.acquire().await?;
+        execute(&mut *session, &settings, &query).await?;
+        Ok(MigrationLock::Session(session, backend, settings))
+    }
+
+    async fn acquire_row(conn: &Connection) -> Result<Self> {
+        let settings = QuerySettings::of(conn);
+        let placeholder = Backend::of(conn).placeholder(1);
+        let query = format!(
+            "create table if not exists {LOCK_TABLE} (id integer primary key, locked_at bigint not null);"
+        );
+        execute(conn, &QuerySettings::of(conn), &query).await?;
+        let insert = format!("insert into {LOCK_TABLE} (id, locked_at) values (1, {placeholder});");
+        let delete_stale =
+            format!("delete from {LOCK_TABLE} where id = 1 and locked_at < {placeholder};");
+        loop {
+            let now = now();
+            let mut stream = sqlx::query(&insert);
+            binds!([Arg::from(now)], stream);
+            match instrument(&settings, LOCK_TABLE, "lock", &insert, stream.execute(conn)).await {
+                Ok(_) => {
+                    let refresh = tokio::spawn(refresh_row(conn.clone()));
+                    return Ok(MigrationLock::Row(conn.clone(), RefreshTask(refresh)));
+                }
+                // the row of another instance holds the lock
+                Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {}
+                Err(err) => {
+                    return Err(Error::new(&settings, &insert, vec![Arg::from(now)], err).into());
+                }
+            }
+            let mut stream = sqlx::query(&delete_stale);
+            binds!([Arg::from(now - STALE_LOCK.as_secs() as i64)], stream);
+            instrument(
+                &settings,
+                LOCK_TABLE,
+                "lock",
+                &delete_stale,
+                stream.execute(conn),
+            )
+            .await
+            .map_err(|err| Error::new(&settings, &delete_stale, Vec::new(), err))?;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    /// Releases the lock.
+    async fn release(self) -> Result<()> {
+        match self {
+            MigrationLock::Session(mut session, backend, settings) => {
+                let query = match backend {
+                    Backend::MySql => format!("select release_lock('{MIGRATIONS_TABLE}');"),
+                    _ => format!("select pg_advisory_unlock({ADVISORY_LOCK_KEY});"),
+                };
+                execute(&mut *session, &settings, &query).await
+            }
+            MigrationLock::Row(conn, refresh) => {
+                drop(refresh);
+                let query = format!("delete from {LOCK_TABLE} where id = 1;");
+                execute(&conn, &QuerySettings::of(&conn), &query).await
+            }
+        }
+    }
+}
+
+/// Refreshes the lock row every `REFRESH_LOCK`, so another instance does not take it as
+/// stale while the migrations run.
+async fn refresh_row(conn: Connection) {
+    let settings = QuerySettings::of(&conn);
+    let query = format!(
+        "update {LOCK_TABLE} set locked_at = {} where id = 1;",
+        Backend::of(&conn).placeholder(1)
+    );
+    loop {
+        tokio::time::sleep(REFRESH_LOCK).await;
+        let mut stream = sqlx::query(&query);
+        binds!([Arg::from(now())], stream);
+        // a failed refresh is retried on the next one, before the lock turns stale
+        let _ = instrument(&settings, LOCK_TABLE, "lock", &query, stream.execute(&conn)).await;
+    }
+}
+
+/// Returns the current unix time in seconds.
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// Applies a migration and records it.
+///
+/// When the backend supports transactional DDL, the statements and the record are committed
+/// together, so a failing migration leaves neither a partial schema nor a record behind.
+async fn apply(conn: &Connection, migration: &Migration) -> Result<()> {
+    let backend = Backend::of(conn);
+    if !Capabilities::of(conn).transactional_ddl {
+        for sql in &migration.statements(backend) {
+            execute(conn, &QuerySettings::of(conn), sql).await?;
+        }
+        for step in &migration.data {
+            (step.0)(conn.clone()).await?;
+        }
+        return record(conn, backend, &QuerySettings::of(conn), migration).await;
+    }
+
+    let mut tx = conn.begin().await?;
+    for sql in &migration.statements(backend) {
+        execute(&mut *tx, &QuerySettings::of(conn), sql).await?;
+    }
+    if migration.data.is_empty() {
+        record(&mut *tx, backend, &QuerySettings::of(conn), migration).await?;
+        tx.commit().await?;
+        return Ok(());
+    }
+    // data steps run on the pool, so they cannot share the transaction (on sqlite they
+    // would wait for its lock): the schema changes are committed first, and the migration
+    // is recorded once the steps succeed
+    tx.commit().await?;
+    for step in &migration.data {
+        (step.0)(conn.clone()).await?;
+    }
+    record(conn, backend, &QuerySettings::of(conn), migration).await
+}
+
+/// Runs the down statements of a recorded migration and removes its record, in one
+/// transaction when the backend supports transactional DDL.
+async fn revert(conn: &Connection, version: i64, down: &str) -> Result<()> {
+    let backend = Backend::of(conn);
+    if !Capabilities::of(conn).transactional_ddl {
+        if !down.is_empty() {
+            execute(conn, &QuerySettings::of(conn), down).await?;
+        }
+        return forget(conn, backend, &QuerySettings::of(conn), version).await;
+    }
+    let mut tx = conn.begin().await?;
+    if !down.is_empty() {
+        execute(&mut *tx, &QuerySettings::of(conn), down).await?;
+    }
+    forget(&mut *tx, backend, &QuerySettings::of(conn), version).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Returns the recorded versions and their down statements, in increasing version order.
+async fn recorded(conn: &Connection) -> Result<Vec<(i64, String)>> {
+    create_migrations_table(conn).await?;
+    let query = format!("select version, down from {MIGRATIONS_TABLE} order by version;");
+    let rows = instrument(
+        &QuerySettings::of(conn),
+        MIGRATIONS_TABLE,
+        "recorded",
+        &query,
+        sqlx::query(&query).fetch_all(conn),
+    )
+    .await
+    .map_err(|err| Error::new(&QuerySettings::of(conn), &query, Vec::new(), err))?;
+    Ok(rows
+        .iter()
+        .map(|row| (row.get::<i64, _>(0), row.get::<String, _>(1)))
+        .collect())
+}
+
+async fn forget<'c, E>(
+    executor: E,
+    backend: Backend,
+    settings: &QuerySettings,
+    version: i64,
+) -> Result<()>
+where
+    E: Executor<'c, Database = Any>,
+{
+    let query = format!(
+        "delete from {MIGRATIONS_TABLE} where version = {};",
+        backend.placeholder(1)
+    );
+    let args = vec![Arg::from(version)];
+    let mut stream = sqlx::query(&query);
+    binds!(args.clone(), stream);
+    instrument(
+        settings,
+        MIGRATIONS_TABLE,
+        "forget",
+        &query,
+        stream.execute(executor),
+    )
+    .await
+    .map_err(|err| Error::new(settings, &query, args, err))?;
+    Ok(())
+}
+
+async fn create_migrations_table(conn: &Connection) -> Result<()> {
+    let query = format!(
+        "create table if not exists {MIGRATIONS_TABLE} (
+            version bigint primary key,
+            name varchar(255) not null,
+            down text not null
+        );"
+    );
+    execute(conn, &QuerySettings::of(conn), &query).await
+}
+
+async fn record<'c, E>(
+    executor: E,
+    backend: Backend,
+    settings: &QuerySettings,
+    migration: &Migration,
+) -> Result<()>
+where
+    E: Executor<'c, Database = Any>,
+{
+    let query = format!(
+        "insert into {MIGRATIONS_TABLE} (version, name, down) values ({}, {}, {});",
+        backend.placeholder(1),
+        backend.placeholder(2),
+        backend.placeholder(3)
+    );
+    let args = vec![
+        Arg::from(migration.version),
+        Arg::from(migration.name.as_str()),
+        Arg::from(
+            migration
+                .down
+                .iter()
+                .map(|sql| statement(sql))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+    ];
+    let mut stream = sqlx::query(&query);
+    binds!(args.clone(), stream);
+    instrument(
+        settings,
+        MIGRATIONS_TABLE,
+        "record",
+        &query,
+        stream.execute(executor),
+    )
+    .await
+    .map_err(|err| Error::new(settings, &query, args, err))?;
+    Ok(())
+}
+
+/// Returns the current year and month, in UTC.
+fn current_month() -> (i32, u32) {
+    let today = chrono::Utc::now().date_naive();
+    (
+        chrono::Datelike::year(&today),
+        chrono::Datelike::month(&today),
+    )
+}
+
+/// Terminates a statement with a single `;`.
+fn statement(sql: &str) -> String {
+    format!("{};", sql.trim_end().trim_end_matches(';'))
+}
+
+/// Executes a statement that may contain several `;`-separated statements.
+async fn execute<'c, E>(executor: E, settings: &QuerySettings, sql: &'c str) -> Result<()>
+where
+    E: Executor<'c, Database = Any>,
+{
+    instrument(
+        settings,
+        MIGRATIONS_TABLE,
+        "migrate",
+        sql,
+        sqlx::raw_sql(sql).execute(executor),
+    )
+    .await
+    .map_err(|err| Error::new(settings, sql, Vec::new(), err))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{memory, User};
+
+    async fn lock_row(conn: &Connection) -> Option<i64> {
+        sqlx::query_scalar(&format!("select locked_at from {LOCK_TABLE} where id = 1;"))
+            .fetch_optional(conn)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn takes_and_releases_the_lock_row() {
+        let conn = memory().await;
+        let lock = MigrationLock::acquire(&conn).await.unwrap();
+        assert!(lock_row(&conn).await.is_some());
+        lock.release().await.unwrap();
+        assert!(lock_row(&conn).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn takes_over_a_stale_lock_row() {
+        let conn = memory().await;
+        MigrationLock::acquire(&conn).await.unwrap();
+        let stale = now() - STALE_LOCK.as_secs() as i64 - 1;
+        sqlx::query(&format!("update {LOCK_TABLE} set locked_at = {stale};"))
+            .execute(&conn)
+            .await
+            .unwrap();
+        let lock = MigrationLock::acquire(&conn).await.unwrap();
+        assert!(lock_row(&conn).await.unwrap() > stale);
+        lock.release().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_lock_row_cannot_be_written() {
+        let conn = memory().await;
+        sqlx::query(&format!(
+            "create table {LOCK_TABLE} (id integer primary key, locked_at bigint not null \
+             check (locked_at < 0));"
+        ))
+        .execute(&conn)
+        .await
+        .unwrap();
+        let acquired = tokio::time::timeout(Duration::from_secs(5), MigrationLock::acquire(&conn));
+        assert!(acquired.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn drops_the_tables_of_the_models_on_reset() {
+        let conn = memory().await;
+        let migrations = Migrations::new()
+            .with(
+                Migration::new(1, "create tag")
+                    .up("create table tag (name text);")
+                    .down("drop table tag;"),
+            )
+            .model::<User>();
+        assert_eq!(migrations.run(&conn).await.unwrap(), [1]);
+        assert!(User::new("joe", 19).save(&conn).await);
+
+        assert_eq!(migrations.reset(&conn).await.unwrap(), [1]);
+        assert!(User::all(&conn).await.is_empty());
+        assert_eq!(Migrations::applied(&conn).await.unwrap(), [1]);
+    }
+
+    #[tokio::test]
+    async fn creates_the_sequences_of_the_models() {
+        let conn = memory().await;
+        let schema = ModelSchema {
+            sequences: &[("id", "user_seq")],
+            ..ModelSchema::of::<User>()
+        };
+        let migrations = Migrations {
+            models: vec![schema],
+            ..Migrations::new()
+        };
+        migrations.run(&conn).await.unwrap();
+        assert_eq!(sequence::next_value(&conn, "user_seq").await.unwrap(), 1);
+        migrations.run(&conn).await.unwrap();
+        assert_eq!(sequence::next_value(&conn, "user_seq").await.unwrap(), 2);
+    }
+}
@@ -1,11 +1,80 @@
 //! The `db` module provides functionality for interacting with the database.
-//! 
+//!
 //! This module contains submodules and traits that define the structure and behavior
 //! of database models, as well as functions for performing common database operations.
 
 /// The `models` module defines the traits and structures for database models.
-/// 
+///
 /// This module includes the `Model` trait, which provides a common interface for
 /// database models, and various implementations of this trait for different
 /// entities in the application.
 pub mod models;
+
+/// The `backend` module detects the database behind a connection and the SQL features it supports.
+pub mod backend;
+
+/// The `decode` module helps decoding result sets into models.
+pub mod decode;
+
+/// The `builder` module provides statement builders returned by the `Model` methods
+/// that need extra options, such as limited deletes and updates.
+pub mod builder;
+
+/// The `migration` module provides versioned migrations, tracked in the `_rusql_migrations` table.
+pub mod migration;
+
+/// The `schema` module introspects the live database and compares it with the model schemas.
+pub mod schema;
+
+/// The `codegen` module generates model definitions from the tables of an existing database.
+pub mod codegen;
+
+/// The `factory` module provides `Factory`, creating model instances with default and
+/// sequence-based values for tests.
+pub mod factory;
+
+/// The `fixtures` module loads rows from fixture files.
+pub mod fixtures;
+
+/// The `batch` module provides `WriteBatch`, coalescing queued writes into multi-row statements.
+pub mod batch;
+
+/// The `sequence` module provides the sequences of `Database::next_sequence_value`, emulated
+/// with the `_rusql_sequences` table on sqlite and mysql.
+pub mod sequence;
+
+/// The `kv` module provides `KvStore`, a key-value store in the `_rusql_kv` table.
+pub mod kv;
+
+/// The `encryption` module encrypts the `Model::ENCRYPTED` columns with the `Cipher` set by
+/// `Database::set_cipher`.
+pub mod encryption;
+
+/// The `copy` module bulk loads postgres tables with `COPY ... FROM STDIN`.
+#[cfg(feature = "postgres")]
+mod copy;
+
+/// The `csv` module reads and writes the CSV files of `Model::export_csv` and `Model::import_csv`.
+mod csv;
+
+/// The `audit` module records the writes of the audited models in the `_audit_log` table.
+pub mod audit;
+
+/// The `testing` module provides models written by hand for the tests of the crate.
+#[cfg(test)]
+pub(crate) mod testing;
+
+/// The `instrument` module reports the statements sent to the database.
+mod instrument;
+
+pub(crate) use instrument::{instrument, QuerySettings, StatementTimeout};
+pub use instrument::{with_timeout, SlowQueryCallback};
+
+/// The `sea` module renders the statements of the builders with `sea-query`.
+#[cfg(feature = "sea-query")]
+mod sea;
+
+/// The `fake` module provides an in-memory store mirroring the `Model` CRUD methods, so code
+/// written against the store can be tested without any database.
+#[cfg(feature = "fake")]
+pub mod fake;
@@ -3,32 +3,384 @@
 //! This module provides traits and implementations for database operations,
 //! including querying, inserting, updating, and deleting records.
 
-use lazy_static::lazy_static;
-use sqlx::{any::AnyRow, FromRow, Row};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{any::AnyRow, Any, Executor, FromRow, Row};
 
-use crate::{get_placeholder, get_type_name, Connection};
+use super::audit::{self, AuditAction};
+use super::backend::{Backend, Capabilities};
+use super::batch::WriteBatch;
+use super::builder::{DeleteBuilder, SelectBuilder, Unscoped, UpdateBuilder, WithDeferred};
+#[cfg(feature = "postgres")]
+use super::copy;
+use super::csv;
+use super::decode::{field_names, locate, DecodeError};
+use super::encryption;
+use super::migration::Migrations;
+use super::schema::{ForeignKey, ModelSchema};
+use super::sequence;
+use super::{instrument, QuerySettings};
+use crate::events::{self, Event};
+use crate::Connection;
 
-lazy_static! {
-    /// The placeholder string for SQL queries, determined by the database type.
-    pub static ref PLACEHOLDER: &'static str = get_placeholder().expect(
-        "DATABASE_URL is not set, make sur the database is 'sqlite', 'postgres' or 'mysql'"
-    );
+#[allow(deprecated)]
+mod legacy {
+    use super::Backend;
+    use lazy_static::lazy_static;
+
+    lazy_static! {
+        /// The placeholder prefix of the database of `DATABASE_URL`, `?` when it is unset.
+        #[deprecated(note = "use `Backend::placeholder` with the backend of the connection")]
+        pub static ref PLACEHOLDER: &'static str = super::placeholder_prefix(
+            std::env::var("DATABASE_URL")
+                .ok()
+                .and_then(|url| Backend::from_url(&url))
+                .unwrap_or(Backend::Sqlite)
+        );
+    }
+}
+
+#[allow(deprecated)]
+pub use legacy::PLACEHOLDER;
+
+/// Returns the prefix of the numbered placeholders of `backend`, `?` or `$`.
+pub(crate) fn placeholder_prefix(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Postgres => "$",
+        Backend::Sqlite | Backend::MySql => "?",
+    }
+}
+
+/// A value bound to a query parameter.
+///
+/// `kwargs!` and `args!` convert their values with `Arg::from`, so any type implementing
+/// `From<T> for Arg` can be used in conditions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+macro_rules! impl_from_for_arg {
+    ($variant:ident: $($ty:ty),*) => {
+        $(
+            impl From<$ty> for Arg {
+                fn from(value: $ty) -> Self {
+                    Arg::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_for_arg!(Bool: bool);
+impl_from_for_arg!(Int: i8, i16, i32, i64, u8, u16, u32);
+impl_from_for_arg!(Float: f32, f64);
+impl_from_for_arg!(Text: String, &String, &str, char);
+impl_from_for_arg!(Bytes: Vec<u8>, &[u8]);
+
+/// Renders the value as a SQL literal, e.g. `'joe'` or `NULL`.
+impl std::fmt::Display for Arg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arg::Null => f.write_str("NULL"),
+            Arg::Bool(b) => write!(f, "{b}"),
+            Arg::Int(n) => write!(f, "{n}"),
+            Arg::Float(n) => write!(f, "{n}"),
+            Arg::Text(s) => write!(f, "'{}'", s.replace('\'', "''")),
+            Arg::Bytes(bytes) => {
+                f.write_str("x'")?;
+                for b in bytes {
+                    write!(f, "{b:02x}")?;
+                }
+                f.write_str("'")
+            }
+        }
+    }
+}
+
+/// `None` is bound as `NULL`.
+impl<T: Into<Arg>> From<Option<T>> for Arg {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Arg::Null, Into::into)
+    }
+}
+
+impl From<u64> for Arg {
+    fn from(value: u64) -> Self {
+        i64::try_from(value)
+            .map(Arg::Int)
+            .unwrap_or_else(|_| Arg::Text(value.to_string()))
+    }
+}
+
+impl From<usize> for Arg {
+    fn from(value: usize) -> Self {
+        Arg::from(value as u64)
+    }
+}
+
+/// Arrays and objects are bound as JSON text.
+impl From<serde_json::Value> for Arg {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Arg::Null,
+            serde_json::Value::Bool(b) => Arg::Bool(b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(n) => Arg::Int(n),
+                None => Arg::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Arg::Text(s),
+            value => Arg::Text(value.to_string()),
+        }
+    }
+}
+
+/// Bytes become an array of numbers, and `NaN` or infinite floats become `null`.
+impl From<Arg> for serde_json::Value {
+    fn from(value: Arg) -> Self {
+        match value {
+            Arg::Null => serde_json::Value::Null,
+            Arg::Bool(b) => b.into(),
+            Arg::Int(n) => n.into(),
+            Arg::Float(n) => n.into(),
+            Arg::Text(s) => s.into(),
+            Arg::Bytes(bytes) => bytes.into(),
+        }
+    }
+}
+
+/// A reference to a column of a table, e.g. `User.id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnRef {
+    pub table: String,
+    pub column: String,
+}
+
+impl std::fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.table, self.column)
+    }
+}
+
+/// A column whose value differs between two instances of a model, see `Model::diff`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub column: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+/// A column of a model, known at compile time.
+///
+/// Models expose their columns as `Model::C.<field>` constants, so a misspelled column in a
+/// join condition is a compile error instead of a failing query.
+///
+/// # Example
+/// ```
+/// let on = User::C.id.equals(Profile::C.user_id);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    pub table: &'static str,
+    pub name: &'static str,
+}
+
+impl Column {
+    /// Creates a column of `table`.
+    pub const fn new(table: &'static str, name: &'static str) -> Self {
+        Self { table, name }
+    }
+
+    fn compare(self, comparison_operator: &str, other: Column) -> Vec<Condition> {
+        vec![Condition::ColumnCondition {
+            left: self.into(),
+            right: other.into(),
+            comparison_operator: comparison_operator.to_string(),
+        }]
+    }
+
+    /// Compares two columns with `=`.
+    pub fn equals(self, other: Column) -> Vec<Condition> {
+        self.compare("=", other)
+    }
+
+    /// Compares two columns with `!=`.
+    pub fn not_equals(self, other: Column) -> Vec<Condition> {
+        self.compare("!=", other)
+    }
+
+    /// Compares two columns with `<`.
+    pub fn less_than(self, other: Column) -> Vec<Condition> {
+        self.compare("<", other)
+    }
+
+    /// Compares two columns with `<=`.
+    pub fn less_or_equal(self, other: Column) -> Vec<Condition> {
+        self.compare("<=", other)
+    }
+
+    /// Compares two columns with `>`.
+    pub fn greater_than(self, other: Column) -> Vec<Condition> {
+        self.compare(">", other)
+    }
+
+    /// Compares two columns with `>=`.
+    pub fn greater_or_equal(self, other: Column) -> Vec<Condition> {
+        self.compare(">=", other)
+    }
+}
+
+impl std::fmt::Display for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.table, self.name)
+    }
+}
+
+impl From<Column> for ColumnRef {
+    fn from(column: Column) -> Self {
+        ColumnRef {
+            table: column.table.to_string(),
+            column: column.name.to_string(),
+        }
+    }
 }
 
 /// Represents a condition in a database query.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Condition {
     /// A condition on a specific field.
     FieldCondition {
         field: String,
-        value: String,
-        value_type: String,
+        value: Arg,
+        comparison_operator: String,
+    },
+    /// A comparison between two columns, rendered as-is and never bound as a value.
+    ColumnCondition {
+        left: ColumnRef,
+        right: ColumnRef,
         comparison_operator: String,
     },
+    /// Conditions wrapped in parentheses.
+    Group(Vec<Condition>),
+    /// Conditions written for each backend, of which only the ones of the backend the query is
+    /// sent to are rendered, see `json_extract`.
+    ByBackend {
+        sqlite: Vec<Condition>,
+        postgres: Vec<Condition>,
+        mysql: Vec<Condition>,
+    },
     /// A logical operator (AND/OR) for combining conditions.
     LogicalOperator { operator: String },
 }
 
+impl Condition {
+    /// Creates a condition on a field, the same way `kwargs!` does.
+    ///
+    /// # Example
+    /// ```
+    /// let conditions = vec![Condition::field("age", ">=", 18)];
+    /// ```
+    pub fn field(
+        field: impl Into<String>,
+        comparison_operator: &str,
+        value: impl Into<Arg>,
+    ) -> Self {
+        Condition::FieldCondition {
+            field: field.into(),
+            value: value.into(),
+            comparison_operator: comparison_operator.to_string(),
+        }
+    }
+}
+
+/// Escapes the `like` wildcards of `text` with `!`, to be matched with `escape '!'`.
+fn escape_like(text: &str) -> String {
+    text.replace('!', "!!")
+        .replace('%', "!%")
+        .replace('_', "!_")
+}
+
+/// Returns the `like` pattern of `similar_to` off postgres, matching `text` anywhere in the
+/// column, with its wildcards escaped by `!`.
+pub(crate) fn similar_pattern(text: &str) -> String {
+    format!("%{}%", escape_like(text))
+}
+
+/// Returns the `like` pattern of `starts_with`, matching the values starting with `text`, with
+/// its wildcards escaped by `!`.
+pub(crate) fn prefix_pattern(text: &str) -> String {
+    format!("{}%", escape_like(text))
+}
+
+/// Renders the `starts_with` operator, a `like` matching the values starting with the text,
+/// see `prefix_pattern`.
+fn starts_with(field: &str, value: &Arg, placeholder: String) -> (String, Arg) {
+    match value {
+        Arg::Text(text) => (
+            format!("{field} like {placeholder} escape '!'"),
+            Arg::Text(prefix_pattern(text)),
+        ),
+        value => (format!("{field} like {placeholder}"), value.clone()),
+    }
+}
+
+/// Renders the `similar_to` operator: the `pg_trgm` similarity `%` on postgres, and elsewhere a
+/// `like` matching the value anywhere in the column, see `similar_pattern`.
+fn similar_to(backend: Backend, field: &str, value: &Arg, placeholder: String) -> (String, Arg) {
+    match (backend, value) {
+        (Backend::Postgres, value) => (format!("{field} % {placeholder}"), value.clone()),
+        (_, Arg::Text(text)) => (
+            format!("{field} like {placeholder} escape '!'"),
+            Arg::Text(similar_pattern(text)),
+        ),
+        (_, value) => (format!("{field} like {placeholder}"), value.clone()),
+    }
+}
+
+/// Combines groups of conditions: the conditions inside a group are OR'ed, and the groups are
+/// AND'ed together, e.g. `(a or b) and (c or d)`.
+///
+/// A group that already contains `and()`/`or()` operators is kept as it is.
+///
+/// # Example
+/// ```
+/// let users = User::filter(
+///     any_of_each(&[
+///         [kwargs!(name == "joe"), kwargs!(email == "joe@gmail.com")].concat(),
+///         kwargs!(age >= 18),
+///     ]),
+///     &conn,
+/// ).await;
+/// ```
+pub fn any_of_each(groups: &[Vec<Condition>]) -> Vec<Condition> {
+    let mut conditions = Vec::new();
+    for group in groups.iter().filter(|group| !group.is_empty()) {
+        let combined = group
+            .iter()
+            .any(|c| matches!(c, Condition::LogicalOperator { .. }));
+        let mut members = Vec::new();
+        for condition in group {
+            if !combined && !members.is_empty() {
+                members.push(Condition::LogicalOperator {
+                    operator: "or".to_string(),
+                });
+            }
+            members.push(condition.clone());
+        }
+        if !conditions.is_empty() {
+            conditions.push(Condition::LogicalOperator {
+                operator: "and".to_string(),
+            });
+        }
+        conditions.push(Condition::Group(members));
+    }
+    conditions
+}
+
 /// Trait for adding OR conditions to a vector of conditions.
 pub trait Or {
     /// Adds OR conditions to the existing conditions.
@@ -61,58 +413,130 @@ impl And for Vec<Condition> {
     }
 }
 
-/// Trait for generating SQL queries from conditions.
+/// Trait for generating SQL queries from conditions, in the dialect of a backend.
 pub trait Query {
     /// Generates an UPDATE query from the conditions.
-    fn to_update_query(&self) -> (String, Vec<(String, String)>);
-    /// Generates a SELECT query from the conditions.
-    fn to_select_query(&self) -> (String, Vec<(String, String)>);
+    fn to_update_query(&self, backend: Backend) -> (String, Vec<Arg>);
+    /// Generates a SELECT query from the conditions, where the conditions without an operator
+    /// between them, such as the ones of `kwargs!(age = 10, name = "z")`, are and'ed.
+    fn to_select_query(&self, backend: Backend) -> (String, Vec<Arg>);
+    /// Generates a SELECT query from the conditions, numbering placeholders after `offset`
+    /// so it can follow other arguments in the same statement.
+    fn to_select_query_from(&self, backend: Backend, offset: usize) -> (String, Vec<Arg>);
     /// Generates an INSERT query from the conditions.
-    fn to_insert_query(&self) -> (String, String, Vec<(String, String)>);
+    fn to_insert_query(&self, backend: Backend) -> (String, String, Vec<Arg>);
 }
 
 impl Query for Vec<Condition> {
-    //                               (placeholders, args:[(value, type)])])
-    fn to_update_query(&self) -> (String, Vec<(String, String)>) {
+    //                               (placeholders, args)
+    fn to_update_query(&self, backend: Backend) -> (String, Vec<Arg>) {
         let mut args = Vec::new();
         let mut placeholders = Vec::new();
         let mut index = 0;
         for condition in self {
-            if let Condition::FieldCondition {
-                field,
-                value,
-                value_type,
-                ..
-            } = condition
-            {
+            if let Condition::FieldCondition { field, value, .. } = condition {
                 index += 1;
-                args.push((value.clone(), value_type.clone()));
+                args.push(value.clone());
                 // (field + = + placeholder + index)
-                let placeholder = PLACEHOLDER.to_string();
-                placeholders.push(format!("{field}={placeholder}{index}",));
+                placeholders.push(format!("{field}={}", backend.placeholder(index)));
             }
         }
         (placeholders.join(", "), args)
     }
 
     //                               (placeholders, args)
-    fn to_select_query(&self) -> (String, Vec<(String, String)>) {
+    fn to_select_query(&self, backend: Backend) -> (String, Vec<Arg>) {
+        self.to_select_query_from(backend, 0)
+    }
+
+    fn to_select_query_from(&self, backend: Backend, offset: usize) -> (String, Vec<Arg>) {
         let mut args = Vec::new();
         let mut placeholders = Vec::new();
-        let mut index = 0;
+        let mut index = offset;
+        let mut joined = false;
         for condition in self {
+            // conditions without an operator between them are and'ed, as in the `sea-query`
+            // rendering
+            let operator = matches!(condition, Condition::LogicalOperator { .. });
+            if joined && !operator {
+                placeholders.push("and".to_string());
+            }
+            joined = !operator;
             match condition {
+                // `= NULL` never matches in SQL, so `None` values are compared with `is null`
+                Condition::FieldCondition {
+                    field,
+                    value: Arg::Null,
+                    comparison_operator,
+                } if matches!(comparison_operator.as_str(), "=" | "==" | "!=") => {
+                    let not = if comparison_operator == "!=" {
+                        " not"
+                    } else {
+                        ""
+                    };
+                    placeholders.push(format!("{field} is{not} null"));
+                }
+                Condition::FieldCondition {
+                    field,
+                    value,
+                    comparison_operator,
+                } if comparison_operator == "similar_to" => {
+                    index += 1;
+                    let (condition, value) =
+                        similar_to(backend, field, value, backend.placeholder(index));
+                    args.push(value);
+                    placeholders.push(condition);
+                }
+                Condition::FieldCondition {
+                    field,
+                    value,
+                    comparison_operator,
+                } if comparison_operator == "starts_with" => {
+                    index += 1;
+                    let (condition, value) = starts_with(field, value, backend.placeholder(index));
+                    args.push(value);
+                    placeholders.push(condition);
+                }
                 Condition::FieldCondition {
                     field,
                     value,
-                    value_type,
                     comparison_operator,
                 } => {
                     index += 1;
-                    args.push((value.clone(), value_type.clone()));
+                    args.push(value.clone());
                     // (field + = + placeholder + index)
-                    let placeholder = PLACEHOLDER.to_string();
-                    placeholders.push(format!("{field}{comparison_operator}{placeholder}{index}",));
+                    placeholders.push(format!(
+                        "{field}{comparison_operator}{}",
+                        backend.placeholder(index)
+                    ));
+                }
+                Condition::ColumnCondition {
+                    left,
+                    right,
+                    comparison_operator,
+                } => {
+                    placeholders.push(format!("{left}{comparison_operator}{right}"));
+                }
+                Condition::Group(conditions) => {
+                    let (fields, group_args) = conditions.to_select_query_from(backend, index);
+                    index += group_args.len();
+                    args.extend(group_args);
+                    placeholders.push(format!("({fields})"));
+                }
+                Condition::ByBackend {
+                    sqlite,
+                    postgres,
+                    mysql,
+                } => {
+                    let conditions = match backend {
+                        Backend::Sqlite => sqlite,
+                        Backend::Postgres => postgres,
+                        Backend::MySql => mysql,
+                    };
+                    let (fields, group_args) = conditions.to_select_query_from(backend, index);
+                    index += group_args.len();
+                    args.extend(group_args);
+                    placeholders.push(format!("({fields})"));
                 }
                 Condition::LogicalOperator { operator } => {
                     placeholders.push(operator.to_owned());
@@ -122,32 +546,531 @@ impl Query for Vec<Condition> {
         (placeholders.join(" "), args)
     }
 
-    //                              fields, placeholders, args:[(value, type)]
-    fn to_insert_query(&self) -> (String, String, Vec<(String, String)>) {
+    //                              fields, placeholders, args
+    fn to_insert_query(&self, backend: Backend) -> (String, String, Vec<Arg>) {
         let mut args = Vec::new();
         let mut fields = Vec::new();
         let mut placeholders = Vec::new();
         let mut index = 0;
         for condition in self {
-            if let Condition::FieldCondition {
-                field,
-                value,
-                value_type,
-                ..
-            } = condition
-            {
+            if let Condition::FieldCondition { field, value, .. } = condition {
                 index += 1;
-                args.push((value.clone(), value_type.clone()));
+                args.push(value.clone());
                 fields.push(field.clone());
-                let placeholder = PLACEHOLDER.to_string();
-                placeholders.push(format!("{placeholder}{index}"));
+                placeholders.push(backend.placeholder(index));
             }
         }
         (fields.join(", "), placeholders.join(", "), args)
     }
 }
 
+/// Drops the conditions on the `COMPUTED` columns of a model, which only the database writes.
+pub(crate) fn writable<M: Model + ?Sized>(kw: Vec<Condition>) -> Vec<Condition> {
+    kw.into_iter()
+        .filter(|condition| {
+            !matches!(
+                condition,
+                Condition::FieldCondition { field, .. } if M::COMPUTED.contains(&field.as_str())
+            )
+        })
+        .collect()
+}
+
+/// Pushes the statements deleting the rows of `table` matching `filter`, after the statements
+/// deleting the rows of the models referencing them.
+fn cascade(
+    models: &[&ModelSchema],
+    table: &str,
+    filter: &str,
+    path: &mut Vec<String>,
+    statements: &mut Vec<String>,
+) {
+    path.push(table.to_string());
+    for model in models {
+        if path.iter().any(|visited| visited == model.name) {
+            continue;
+        }
+        for foreign_key in model.foreign_keys() {
+            if foreign_key.table == table {
+                let referencing = format!(
+                    "{column} in (select {references} from {table} where {filter})",
+                    column = foreign_key.column,
+                    references = foreign_key.references,
+                );
+                cascade(models, model.name, &referencing, path, statements);
+            }
+        }
+    }
+    path.pop();
+    statements.push(format!("delete from {table} where {filter};"));
+}
+
+/// Returns the value of a column of a model instance, `None` if it is null or missing.
+fn column_arg<M: Serialize>(instance: &M, column: &str) -> Option<Arg> {
+    let value = serde_json::to_value(instance).ok()?.get(column).cloned()?;
+    (!value.is_null()).then(|| Arg::from(value))
+}
+
+/// Returns the foreign keys of the join model `J` to `S` and to `T`.
+fn through_keys<S, T, J>() -> Option<(ForeignKey, ForeignKey)>
+where
+    S: Model + ?Sized,
+    T: Model,
+    J: Model,
+{
+    let foreign_keys = ModelSchema::of::<J>().foreign_keys();
+    let source = foreign_keys
+        .iter()
+        .find(|foreign_key| foreign_key.table == S::NAME)?;
+    // the second foreign key when the model is linked to itself
+    let target = foreign_keys
+        .iter()
+        .find(|foreign_key| foreign_key.table == T::NAME && foreign_key.column != source.column)?;
+    Some((source.clone(), target.clone()))
+}
+
+/// Returns the conditions matching the row of the join model `J` linking `source` to `target`.
+fn through_conditions<S, T, J>(source: &S, target: &T) -> Option<Vec<Condition>>
+where
+    S: Model + Serialize,
+    T: Model + Serialize,
+    J: Model,
+{
+    let (source_key, target_key) = through_keys::<S, T, J>()?;
+    let source = column_arg(source, &source_key.references)?;
+    let target = column_arg(target, &target_key.references)?;
+    Some(
+        vec![Condition::field(source_key.column, "=", source)].and(vec![Condition::field(
+            target_key.column,
+            "=",
+            target,
+        )]),
+    )
+}
+
+/// Returns the `select` of the rows of `M` matching `kw`, without the final `;`, restricted to
+/// `M::DEFAULT_SCOPE` when `scoped` is set.
+pub(crate) fn select_query<M: Model + ?Sized>(
+    backend: Backend,
+    kw: &Vec<Condition>,
+    scoped: bool,
+) -> (String, Vec<Arg>) {
+    select_columns_query::<M>(backend, &selected_columns::<M>(false), kw, scoped)
+}
+
+/// Returns the columns selected from `M`: every column when `deferred` is set, every one but
+/// the `DEFERRED` columns otherwise.
+pub(crate) fn selected_columns<M: Model + ?Sized>(deferred: bool) -> String {
+    if deferred || M::DEFERRED.is_empty() {
+        return "*".to_string();
+    }
+    let columns = ModelSchema::of::<M>()
+        .columns()
+        .into_iter()
+        .map(|column| column.name)
+        .filter(|column| !M::DEFERRED.contains(&column.as_str()))
+        .collect::<Vec<_>>();
+    if columns.is_empty() {
+        "*".to_string()
+    } else {
+        columns.join(", ")
+    }
+}
+
+/// Returns the `select` of `columns` of the rows of `M` matching `kw`, see `select_query`.
+pub(crate) fn select_columns_query<M: Model + ?Sized>(
+    backend: Backend,
+    columns: &str,
+    kw: &Vec<Condition>,
+    scoped: bool,
+) -> (String, Vec<Arg>) {
+    let (filter, args) = select_filter::<M>(backend, kw, scoped);
+    let filter = match filter {
+        Some(filter) => format!(" where {filter}"),
+        None => String::new(),
+    };
+    (
+        format!(
+            "select {columns} from {table_name}{filter}",
+            table_name = M::NAME
+        ),
+        args,
+    )
+}
+
+/// Returns the condition of the `where` clause of the rows of `M` matching `kw`, `None` if
+/// every row matches.
+pub(crate) fn select_filter<M: Model + ?Sized>(
+    backend: Backend,
+    kw: &Vec<Condition>,
+    scoped: bool,
+) -> (Option<String>, Vec<Arg>) {
+    let (fields, args) = kw.to_select_query(backend);
+    let scope = M::DEFAULT_SCOPE.trim();
+    let filter = match (scoped && !scope.is_empty(), fields.is_empty()) {
+        (false, true) => None,
+        (false, false) => Some(fields),
+        (true, true) => Some(scope.to_string()),
+        (true, false) => Some(format!("({scope}) and ({fields})")),
+    };
+    (filter, args)
+}
+
+/// Selects the rows of `M` matching `kw`, restricted to `M::DEFAULT_SCOPE` when `scoped` is set
+/// and with the `DEFERRED` columns when `deferred` is set.
+pub(crate) async fn select<M>(
+    kw: Vec<Condition>,
+    scoped: bool,
+    deferred: bool,
+    operation: &'static str,
+    conn: &Connection,
+) -> Vec<M>
+where
+    M: Model + Unpin + for<'r> FromRow<'r, AnyRow>,
+{
+    let columns = selected_columns::<M>(deferred);
+    let (query, args) = select_columns_query::<M>(Backend::of(conn), &columns, &kw, scoped);
+    let query = QuerySettings::of(conn).tagged(format!("{query};"));
+    let mut stream = sqlx::query(&query);
+    binds!(args, stream);
+    instrument(
+        &QuerySettings::of(conn),
+        M::NAME,
+        operation,
+        &query,
+        stream.fetch_all(conn),
+    )
+    .await
+    .and_then(|rows| decode_rows::<M>(&QuerySettings::of(conn), rows))
+    .unwrap_or_default()
+}
+
+/// Decodes the rows of `M` with `Model::from_rows`, after decrypting its `ENCRYPTED` columns.
+///
+/// # Errors
+/// Returns a `DecodeError` locating the first row that cannot be decoded.
+pub(crate) fn decode_rows<M>(
+    settings: &QuerySettings,
+    mut rows: Vec<AnyRow>,
+) -> sqlx::Result<Vec<M>>
+where
+    M: Model + for<'r> FromRow<'r, AnyRow>,
+{
+    encryption::decrypt::<M>(settings, &mut rows)?;
+    M::from_rows(&rows).map_err(|err| locate(M::NAME, &rows, err, |row| M::from_row(row)))
+}
+
+/// Decodes a row of `M`, after decrypting its `ENCRYPTED` columns.
+pub(crate) fn decode_row<M>(settings: &QuerySettings, row: AnyRow) -> sqlx::Result<M>
+where
+    M: Model + for<'r> FromRow<'r, AnyRow>,
+{
+    decode_rows(settings, vec![row]).map(|mut rows| rows.remove(0))
+}
+
+/// Decodes the rows of `M` one by one, skipping the rows that cannot be decoded.
+///
+/// # Returns
+/// The decoded rows, and the errors of the skipped ones.
+pub(crate) fn decode_rows_lenient<M>(
+    settings: &QuerySettings,
+    rows: Vec<AnyRow>,
+) -> (Vec<M>, Vec<DecodeError>)
+where
+    M: Model + for<'r> FromRow<'r, AnyRow>,
+{
+    let mut decoded = Vec::with_capacity(rows.len());
+    let mut errors = Vec::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        let mut row = [row];
+        match encryption::decrypt::<M>(settings, &mut row).and_then(|()| M::from_row(&row[0])) {
+            Ok(instance) => decoded.push(instance),
+            Err(err) => errors.push(DecodeError::new(M::NAME, index, &row[0], err)),
+        }
+    }
+    (decoded, errors)
+}
+
+/// Returns the primary key of a model instance as an argument, or `None` if it is unset
+/// (`None`, `0` or an empty string).
+pub(crate) fn primary_key_arg<M: Model + Serialize>(instance: &M) -> Option<Arg> {
+    let id = serde_json::to_value(instance).ok()?.get(M::PK).cloned()?;
+    match &id {
+        serde_json::Value::Null => None,
+        serde_json::Value::Number(n) if n.as_f64() == Some(0.0) => None,
+        serde_json::Value::String(s) if s.is_empty() => None,
+        _ => Some(id.into()),
+    }
+}
+
+/// Inserts a row of `M`, filling the `UUID_DEFAULTS` and `SEQUENCES` columns that are not
+/// given, and records it in the audit log of an audited model.
+///
+/// # Returns
+/// `None` if the insert failed, otherwise the primary key of the row when it is known.
+async fn insert<M: Model>(kw: Vec<Condition>, conn: &Connection) -> Option<Option<Arg>> {
+    let kw = encryption::encrypt::<M>(&QuerySettings::of(conn), writable::<M>(kw)).ok()?;
+    #[cfg(feature = "uuid")]
+    let kw = {
+        let mut kw = kw;
+        for column in M::UUID_DEFAULTS {
+            let given = kw.iter().any(|condition| {
+                matches!(condition, Condition::FieldCondition { field, .. } if field == column)
+            });
+            if !given {
+                kw.push(Condition::field(*column, "=", crate::types::Uuid::new_v4()));
+            }
+        }
+        kw
+    };
+    let mut kw = kw;
+    for (column, sequence) in M::SEQUENCES {
+        let given = kw.iter().any(|condition| {
+            matches!(condition, Condition::FieldCondition { field, .. } if field == column)
+        });
+        if !given {
+            let value = sequence::next_value(conn, sequence).await.ok()?;
+            kw.push(Condition::field(*column, "=", value));
+        }
+    }
+    let backend = Backend::of(conn);
+    let (fields, placeholders, args) = kw.to_insert_query(backend);
+    let values = audit::changes::<M>(&kw);
+    let given = values.get(M::PK).cloned().map(Arg::from);
+    // the any driver only reports the last insert id of mysql, the other backends return the
+    // generated key from the insert
+    let returning = given.is_none() && Capabilities::of(conn).returning;
+    let query = QuerySettings::of(conn).tagged(format!(
+        "insert into {table_name} ({fields}) values ({placeholders}){returning};",
+        table_name = M::NAME,
+        returning = if returning {
+            format!(" returning {}", M::PK)
+        } else {
+            String::new()
+        },
+    ));
+
+    if !M::AUDITED {
+        let generated = execute_insert(
+            conn,
+            &QuerySettings::of(conn),
+            M::NAME,
+            &query,
+            args,
+            returning,
+        )
+        .await?;
+        let pk = given.or(generated);
+        events::emit::<M>(|| Event::Created {
+            pk: pk.clone(),
+            values,
+        });
+        return Some(pk);
+    }
+
+    let mut tx = conn.begin().await.ok()?;
+    let generated = execute_insert(
+        &mut *tx,
+        &QuerySettings::of(conn),
+        M::NAME,
+        &query,
+        args,
+        returning,
+    )
+    .await?;
+    let pk = given.or(generated);
+    if !audit::record(
+        &mut *tx,
+        backend,
+        &QuerySettings::of(conn),
+        M::NAME,
+        AuditAction::Create,
+        pk.clone(),
+        values.clone(),
+    )
+    .await
+    {
+        return None;
+    }
+    tx.commit().await.ok()?;
+    events::emit::<M>(|| Event::Created {
+        pk: pk.clone(),
+        values,
+    });
+    Some(pk)
+}
+
+/// Runs the insert of a row.
+///
+/// # Returns
+/// `None` if the insert failed, otherwise the generated primary key when it is known.
+async fn execute_insert<'c, E>(
+    executor: E,
+    settings: &QuerySettings,
+    table: &'static str,
+    query: &str,
+    args: Vec<Arg>,
+    returning: bool,
+) -> Option<Option<Arg>>
+where
+    E: Executor<'c, Database = Any>,
+{
+    let mut stream = sqlx::query(query);
+    binds!(args, stream);
+    if returning {
+        let row = instrument(settings, table, "create", query, stream.fetch_one(executor))
+            .await
+            .ok()?;
+        let pk = row
+            .try_get::<i64, _>(0)
+            .map(Arg::from)
+            .or_else(|_| row.try_get::<String, _>(0).map(Arg::from));
+        return Some(pk.ok());
+    }
+    let result = instrument(settings, table, "create", query, stream.execute(executor))
+        .await
+        .ok()?;
+    Some(result.last_insert_id().map(Arg::from))
+}
+
+/// Re-reads `columns` of an instance from its row, keeping its other fields.
+///
+/// # Returns
+/// `true` if the columns were read (or there is none), `false` otherwise.
+async fn refresh_columns<M>(
+    instance: &mut M,
+    columns: &[&str],
+    operation: &'static str,
+    conn: &Connection,
+) -> bool
+where
+    M: Model + Serialize + DeserializeOwned + Send + Unpin + for<'r> FromRow<'r, AnyRow>,
+{
+    if columns.is_empty() {
+        return true;
+    }
+    let Some(id_arg) = primary_key_arg(instance) else {
+        return false;
+    };
+    let query = QuerySettings::of(conn).tagged(format!(
+        "select * from {table_name} where {id}={placeholder};",
+        id = M::PK,
+        placeholder = Backend::of(conn).placeholder(1),
+        table_name = M::NAME,
+    ));
+    let mut stream = sqlx::query(&query);
+    binds!([id_arg], stream);
+    let Ok(Some(fresh)) = instrument(
+        &QuerySettings::of(conn),
+        M::NAME,
+        operation,
+        &query,
+        stream.fetch_optional(conn),
+    )
+    .await
+    .and_then(|row| {
+        row.map(|row| decode_row::<M>(&QuerySettings::of(conn), row))
+            .transpose()
+    }) else {
+        return false;
+    };
+    let (Ok(serde_json::Value::Object(mut row)), Ok(serde_json::Value::Object(fresh))) = (
+        serde_json::to_value(&*instance),
+        serde_json::to_value(&fresh),
+    ) else {
+        return false;
+    };
+    for column in columns {
+        if let Some(value) = fresh.get(*column) {
+            row.insert(column.to_string(), value.clone());
+        }
+    }
+    match serde_json::from_value(serde_json::Value::Object(row)) {
+        Ok(refreshed) => {
+            *instance = refreshed;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Replaces the value of `column` in an instance.
+///
+/// # Returns
+/// `false` if the instance does not deserialize with the new value.
+fn set_column<M: Serialize + DeserializeOwned>(instance: &mut M, column: &str, value: Arg) -> bool {
+    let Ok(serde_json::Value::Object(mut row)) = serde_json::to_value(&*instance) else {
+        return false;
+    };
+    row.insert(column.to_string(), value.into());
+    match serde_json::from_value(serde_json::Value::Object(row)) {
+        Ok(updated) => {
+            *instance = updated;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Returns the columns and values of an instance to insert, without the computed columns,
+/// an unset primary key and the unset `DB_GENERATED` columns, which are left to the database.
+pub(crate) fn insert_values<M: Model + Serialize>(instance: &M) -> Option<(Vec<String>, Vec<Arg>)> {
+    let serde_json::Value::Object(row) = serde_json::to_value(instance).ok()? else {
+        return None;
+    };
+    let unset_pk = primary_key_arg(instance).is_none();
+    Some(
+        row.into_iter()
+            .filter(|(column, value)| {
+                !(M::COMPUTED.contains(&column.as_str())
+                    || (column == M::PK && unset_pk)
+                    || (value.is_null() && M::DB_GENERATED.contains(&column.as_str())))
+            })
+            .map(|(column, value)| (column, Arg::from(value)))
+            .unzip(),
+    )
+}
+
+/// The name of the database of the models that do not declare one.
+pub const DEFAULT_DATABASE: &str = "default";
+
 /// Trait for database model operations.
+///
+/// `#[derive(Model)]` writes `SCHEMA`, `NAME`, `PK`, `save`, `update` and `delete`. The other
+/// constants have no attribute in the derive and keep their default there; a model using them
+/// implements the trait by hand and sets them directly.
+///
+/// # Example
+/// ```
+/// #[derive(Debug, Clone, Default, FromRow, Serialize, Deserialize)]
+/// struct Post {
+///     id: Option<Integer>,
+///     title: Text,
+///     is_active: Boolean,
+/// }
+///
+/// #[async_trait]
+/// impl Model for Post {
+///     const SCHEMA: &'static str = "create table if not exists post (\
+///         id integer primary key autoincrement, title text not null, is_active boolean not null);";
+///     const NAME: &'static str = "post";
+///     const PK: &'static str = "id";
+///     const DEFAULT_SCOPE: &'static str = "is_active = true";
+///     const AUDITED: bool = true;
+///
+///     async fn save(&self, conn: &Connection) -> bool {
+///         Self::create(kwargs!(title = self.title, is_active = self.is_active), conn).await
+///     }
+///
+///     async fn update(&self, conn: &Connection) -> bool {
+///         Self::set(self.id, kwargs!(title = self.title, is_active = self.is_active), conn).await
+///     }
+///
+///     async fn delete(&self, conn: &Connection) -> bool {
+///         Self::delete_where(kwargs!(id = self.id)).execute(conn).await > 0
+///     }
+/// }
+/// ```
 #[async_trait::async_trait]
 pub trait Model {
     // The SQL schema of the model
@@ -156,258 +1079,1622 @@ pub trait Model {
     const NAME: &'static str;
     // The Primary Key of the model
     const PK: &'static str;
+    // The columns filled by the database (defaults, triggers, generated columns), left to it
+    // when unset, e.g. `&["created_at"]`
+    const DB_GENERATED: &'static [&'static str] = &[];
+    // The generated columns computed from other columns, left out of the writes,
+    // e.g. `&["total"]` for `total real generated always as (price * quantity) stored`
+    const COMPUTED: &'static [&'static str] = &[];
+    // The sets of columns that must be unique together, e.g. `&[&["owner", "name"]]`
+    const UNIQUE_TOGETHER: &'static [&'static [&'static str]] = &[];
+    // The `Uuid` columns filled with a random UUID by `create` when no value is given,
+    // e.g. `&["id"]`
+    const UUID_DEFAULTS: &'static [&'static str] = &[];
+    // The columns pulled from a sequence by `create` when no value is given, with their
+    // sequence created by `migrate`, e.g. `&[("number", "order_number_seq")]`
+    const SEQUENCES: &'static [(&'static str, &'static str)] = &[];
+    // The environments the model is migrated in, e.g. `&["test"]`, every environment when empty
+    const ENV: &'static [&'static str] = &[];
+    // The name of the database of the model in a `DatabaseRegistry`, e.g. `"analytics"`
+    const DATABASE: &'static str = DEFAULT_DATABASE;
+    // Whether the writes of the crate's methods are recorded in the audit log, see
+    // `Model::audit` for the writes it leaves out
+    const AUDITED: bool = false;
+    // The condition added to `all`, `filter` and `get`, e.g. `"is_active = true"`, see
+    // `Model::unscoped`
+    const DEFAULT_SCOPE: &'static str = "";
+    // The columns left out of `to_json`, e.g. `&["password_hash"]`
+    const HIDDEN: &'static [&'static str] = &[];
+    // The columns holding secrets, e.g. `&["api_key"]`, left out of `to_json` and redacted in
+    // the audit log, the events and `diff`
+    const SENSITIVE: &'static [&'static str] = &[];
+    // The columns left out by `all` and `filter` unless `with_deferred` is used, e.g. large
+    // text bodies, see `load_deferred`
+    const DEFERRED: &'static [&'static str] = &[];
+    // The text columns encrypted with the cipher of `Database::set_cipher`, e.g.
+    // `&["api_token"]`, see the `encryption` module
+    const ENCRYPTED: &'static [&'static str] = &[];
+    // The partition key of the table on postgres, e.g. `"range(at)"`, see
+    // `Migrations::monthly_partitions`
+    const PARTITION_BY: &'static str = "";
+
+    /// Returns the statements creating the table of the model in the dialect of `backend`, then
+    /// the unique indexes of its `UNIQUE_TOGETHER` column sets, and the audit log table for an
+    /// audited model.
+    ///
+    /// # Example
+    /// ```rust
+    /// for sql in User::up(Backend::of(&conn)) {
+    ///     println!("{sql}");
+    /// }
+    /// ```
+    fn up(backend: Backend) -> Vec<String>
+    where
+        Self: Sized,
+    {
+        let mut statements = ModelSchema::of::<Self>().up(backend);
+        if Self::AUDITED {
+            statements.push(audit::create_table());
+        }
+        statements
+    }
+
+    /// Returns the columns whose value differs between the current instance and `other`, with
+    /// the old value (of `self`) and the new value (of `other`).
+    ///
+    /// # Example
+    /// ```rust
+    /// let before = user.clone();
+    /// user.role = "admin".into();
+    /// for change in before.diff(&user) {
+    ///     println!("{}: {} -> {}", change.column, change.old, change.new);
+    /// }
+    /// ```
+    fn diff(&self, other: &Self) -> Vec<FieldChange>
+    where
+        Self: Sized + Serialize,
+    {
+        let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+            (serde_json::to_value(self), serde_json::to_value(other))
+        else {
+            return Vec::new();
+        };
+        new.into_iter()
+            .filter_map(|(column, new)| {
+                let old = old.get(&column).cloned().unwrap_or_default();
+                if old == new {
+                    return None;
+                }
+                if Self::SENSITIVE.contains(&column.as_str()) {
+                    let redacted = serde_json::Value::from(audit::REDACTED);
+                    return Some(FieldChange {
+                        column,
+                        old: redacted.clone(),
+                        new: redacted,
+                    });
+                }
+                Some(FieldChange { column, old, new })
+            })
+            .collect()
+    }
+
+    /// Builds an instance from a JSON object, e.g. the body of a request.
+    ///
+    /// # Errors
+    /// Returns an error if the value is not an object, if a key is not a column of the model,
+    /// or if the object does not deserialize into the model (missing or mistyped fields).
+    ///
+    /// # Example
+    /// ```rust
+    /// let user = User::from_json(serde_json::json!({"name": "joe", "age": 19}))?;
+    /// ```
+    fn from_json(value: serde_json::Value) -> Result<Self, serde_json::Error>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        let serde_json::Value::Object(fields) = &value else {
+            return Err(serde::de::Error::custom("expected a JSON object"));
+        };
+        let columns = ModelSchema::of::<Self>().columns();
+        if let Some(field) = fields
+            .keys()
+            .find(|field| !columns.iter().any(|column| &&column.name == field))
+        {
+            return Err(serde::de::Error::custom(format!(
+                "unknown column `{field}`"
+            )));
+        }
+        serde_json::from_value(value)
+    }
+
+    /// Returns the instance as a JSON object, without the `HIDDEN` and `SENSITIVE` columns, e.g.
+    /// for the response of a request.
+    ///
+    /// # Example
+    /// ```rust
+    /// let body = user.to_json(); // no `password`
+    /// ```
+    fn to_json(&self) -> serde_json::Value
+    where
+        Self: Sized + Serialize,
+    {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let serde_json::Value::Object(fields) = &mut value {
+            for column in Self::HIDDEN.iter().chain(Self::SENSITIVE) {
+                fields.remove(*column);
+            }
+        }
+        value
+    }
+
+    /// Writes the rows matching the conditions as CSV, every row when `kw` is empty, with a
+    /// header of the column names.
+    ///
+    /// # Returns
+    /// The number of exported rows.
+    ///
+    /// # Example
+    /// ```rust
+    /// let file = std::fs::File::create("users.csv")?;
+    /// User::export_csv(file, kwargs!(age >= 18), &conn).await?;
+    /// ```
+    async fn export_csv<W>(
+        mut writer: W,
+        kw: Vec<Condition>,
+        conn: &Connection,
+    ) -> anyhow::Result<u64>
+    where
+        W: std::io::Write + Send,
+        Self: Sized + Serialize + Unpin + for<'r> FromRow<'r, AnyRow>,
+    {
+        let columns = ModelSchema::of::<Self>()
+            .columns()
+            .into_iter()
+            .map(|column| column.name)
+            .collect::<Vec<_>>();
+        csv::write_record(&mut writer, &columns)?;
+        let rows = select::<Self>(kw, true, true, "export_csv", conn).await;
+        for row in &rows {
+            let row = serde_json::to_value(row)?;
+            let fields = columns
+                .iter()
+                .map(|column| csv::field(row.get(column)))
+                .collect::<Vec<_>>();
+            csv::write_record(&mut writer, &fields)?;
+        }
+        writer.flush()?;
+        Ok(rows.len() as u64)
+    }
+
+    /// Inserts the rows of a CSV file whose header names the columns, in any order, with
+    /// multi-row inserts in one transaction, see `WriteBatch`.
+    ///
+    /// The fields are converted to the type of their column, and the empty fields are
+    /// inserted as `NULL`.
+    ///
+    /// # Returns
+    /// The number of imported rows.
+    ///
+    /// # Errors
+    /// Returns an error if a header is not a column of the model, if a field cannot be
+    /// converted to the type of its column, or if an insert fails, in which case no row is
+    /// imported.
+    ///
+    /// # Example
+    /// ```rust
+    /// let file = std::fs::File::open("users.csv")?;
+    /// let imported = User::import_csv(file, &conn).await?;
+    /// ```
+    async fn import_csv<R>(reader: R, conn: &Connection) -> anyhow::Result<u64>
+    where
+        R: std::io::Read + Send,
+        Self: Sized,
+    {
+        let mut records = csv::read_records(reader)?.into_iter();
+        let Some(header) = records.next() else {
+            return Ok(0);
+        };
+        let columns = ModelSchema::of::<Self>().columns();
+        let types = header
+            .iter()
+            .map(|name| {
+                columns
+                    .iter()
+                    .find(|column| column.name == *name)
+                    .map(|column| column.sql_type.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("unknown column `{name}`"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut batch = WriteBatch::new();
+        for (index, record) in records.enumerate() {
+            if record.len() != header.len() {
+                anyhow::bail!(
+                    "record {} has {} fields, expected {}",
+                    index + 1,
+                    record.len(),
+                    header.len()
+                );
+            }
+            let values = record
+                .iter()
+                .zip(&header)
+                .zip(&types)
+                .map(|((field, name), sql_type)| {
+                    csv::arg(field, sql_type)
+                        .map_err(|err| anyhow::anyhow!("record {}, `{name}`: {err}", index + 1))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            batch.insert(Self::NAME, Self::ENCRYPTED, header.clone(), values);
+        }
+        batch.flush(conn).await
+    }
+
+    /// Inserts the instances, updating the rows that already exist with the same
+    /// `conflict_columns`, e.g. to sync an external dataset without deleting the table first.
+    ///
+    /// The rows are written with multi-row `insert ... on conflict do update` statements (`on
+    /// duplicate key update` on mysql) in one transaction, see `WriteBatch::upsert`. The
+    /// `conflict_columns` must be the primary key or a unique index, and an instance must not
+    /// appear twice on postgres.
+    ///
+    /// # Returns
+    /// The number of affected rows, as reported by the database.
+    ///
+    /// # Errors
+    /// Returns an error if an instance cannot be encrypted, or the `Error` of the first failing
+    /// statement, in which case no row is written.
+    ///
+    /// # Example
+    /// ```rust
+    /// let products = fetch_catalog().await?;
+    /// Product::bulk_upsert(&products, &["sku"], &conn).await?;
+    /// ```
+    async fn bulk_upsert(
+        rows: &[Self],
+        conflict_columns: &[&str],
+        conn: &Connection,
+    ) -> anyhow::Result<u64>
+    where
+        Self: Sized + Serialize + Sync,
+    {
+        let mut batch = WriteBatch::new();
+        for row in rows {
+            batch.upsert(row, conflict_columns);
+        }
+        batch.flush(conn).await
+    }
+
+    /// Loads the instances into the table with `COPY ... FROM STDIN` on postgres, an order of
+    /// magnitude faster than multi-row inserts for large imports.
+    ///
+    /// The rows are sent as serialized, like `WriteBatch`, on a connection of their own and in
+    /// one transaction, without the audit log and the events.
+    ///
+    /// # Returns
+    /// The number of copied rows.
+    ///
+    /// # Errors
+    /// Returns an error on the other backends, or if a row is rejected, in which case no row
+    /// is copied.
+    ///
+    /// # Example
+    /// ```rust
+    /// let readings = parse_readings(file)?;
+    /// Reading::copy_in(&readings, &conn).await?;
+    /// ```
+    #[cfg(feature = "postgres")]
+    async fn copy_in(rows: &[Self], conn: &Connection) -> anyhow::Result<u64>
+    where
+        Self: Sized + Serialize + Sync,
+    {
+        copy::copy_in(rows, conn).await
+    }
+
+    /// Migrates the model schema to the database
+    ///
+    /// The `UNIQUE_TOGETHER` column sets are created as unique indexes after the table.
+    /// Existing tables are left untouched, use `Database::reset_schema` to drop and recreate them.
+    ///
+    /// # Arguments
+    /// * `conn` - The database connection
+    ///
+    /// # Returns
+    /// `true` if the migration was successful, `false` otherwise
+    ///
+    /// # Example
+    /// ```rust
+    /// let success = User::migrate(&conn).await;
+    /// println!("Migration success: {}", success);
+    /// ```
+    async fn migrate(conn: &Connection) -> bool
+    where
+        Self: Sized,
+    {
+        for query in Self::up(Backend::of(conn)) {
+            if let Err(err) = instrument(
+                &QuerySettings::of(conn),
+                Self::NAME,
+                "migrate",
+                &query,
+                sqlx::query(&query).execute(conn),
+            )
+            .await
+            {
+                eprintln!("Error during the migration\n->{err}");
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Saves the current model instance to the database.
+    ///
+    /// # Arguments
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// `true` if save is successful, `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// let user = User {
+    ///     name: "johnDoe@gmail.com".to_string(),
+    ///     email: "21john@gmail.com".to_string(),
+    ///     password: "p455w0rd".to_string(),
+    ///     age: 18,
+    ///     weight: 60.0,
+    ///     ..Default::default()
+    /// };
+    /// let success = user.save(&conn).await;
+    /// println!("Save success: {}", success);
+    /// ```
+    async fn save(&self, conn: &Connection) -> bool
+    where
+        Self: Sized;
+
+    /// Saves the instances in order, e.g. to seed a database in tests.
+    ///
+    /// # Returns
+    /// `true` if every instance was saved, `false` at the first failure.
+    ///
+    /// # Example
+    /// ```
+    /// User::seed(
+    ///     vec![
+    ///         User { name: "joe".to_string(), ..Default::default() },
+    ///         User { name: "jane".to_string(), ..Default::default() },
+    ///     ],
+    ///     &conn,
+    /// )
+    /// .await;
+    /// ```
+    async fn seed(instances: Vec<Self>, conn: &Connection) -> bool
+    where
+        Self: Sized + Send,
+    {
+        for instance in instances {
+            if !instance.save(conn).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Creates a new model instance with the specified parameters.
+    ///
+    /// # Arguments
+    /// * `kw` - The key-value arguments for the new instance.
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// `true` if creation is successful, `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// let success = User::create(
+    ///     kwargs!(
+    ///         name = "joe",
+    ///         email = "24nomeniavo@gmail.com",
+    ///         password = "strongpassword",
+    ///         age = 19,
+    ///         weight = 80.1
+    ///     ),
+    ///     &conn,
+    /// ).await;
+    /// println!("Create success: {}", success);
+    /// ```
+    async fn create(kw: Vec<Condition>, conn: &Connection) -> bool
+    where
+        Self: Sized,
+    {
+        insert::<Self>(kw, conn).await.is_some()
+    }
+
+    /// Creates a new model instance like `create` and returns its primary key, e.g. to insert
+    /// the rows referencing it without reading it back.
+    ///
+    /// The key is the given value, or the one generated by the database, returned by the
+    /// insert with `RETURNING`, or the last insert id on mysql.
+    ///
+    /// # Returns
+    /// The primary key, or `None` if the creation failed or the key does not decode as `T`.
+    ///
+    /// # Example
+    /// ```
+    /// let user_id: i32 = User::create_returning_pk(kwargs!(name = "joe"), &conn).await?;
+    /// Profile::create(kwargs!(user_id = user_id, bio = "hi"), &conn).await;
+    /// ```
+    async fn create_returning_pk<T: DeserializeOwned + Send>(
+        kw: Vec<Condition>,
+        conn: &Connection,
+    ) -> Option<T>
+    where
+        Self: Sized,
+    {
+        let pk = insert::<Self>(kw, conn).await??;
+        serde_json::from_value(pk.into()).ok()
+    }
+
+    /// Saves the current instance and returns its primary key, see `create_returning_pk`.
+    ///
+    /// An unset primary key (`None`, `0` or an empty string) is left to the database, like
+    /// the computed columns and the unset `DB_GENERATED` columns.
+    ///
+    /// # Example
+    /// ```
+    /// let order_id: i64 = order.save_returning_pk(&conn).await?;
+    /// for line in lines {
+    ///     OrderLine::create(kwargs!(order_id = order_id, sku = line.sku), &conn).await;
+    /// }
+    /// ```
+    async fn save_returning_pk<T: DeserializeOwned + Send>(&self, conn: &Connection) -> Option<T>
+    where
+        Self: Sized + Serialize + Send + Sync,
+    {
+        let (columns, values) = insert_values(self)?;
+        let kw = columns
+            .into_iter()
+            .zip(values)
+            .map(|(column, value)| Condition::field(column, "=", value))
+            .collect();
+        Self::create_returning_pk(kw, conn).await
+    }
+
+    /// Updates the current model instance in the database.
+    ///
+    /// # Arguments
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// `true` if update is successful, `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// if let Some(mut user) = User::get(
+    ///     kwargs!(email == "24nomeniavo@gmail.com").and(kwargs!(password == "strongpassword")),
+    ///     &conn,
+    /// ).await {
+    ///     user.role = "admin".to_string();
+    ///     let success = user.update(&conn).await;
+    ///     println!("Update success: {}", success);
+    /// }
+    /// ```
+    async fn update(&self, conn: &Connection) -> bool
+    where
+        Self: Sized;
+
+    /// Saves the current model instance, updating the existing row instead when the primary
+    /// key is set and a row with that key exists.
+    ///
+    /// A primary key is unset when it is `None`, `0` or an empty string. With a primary key, the
+    /// row is inserted with `create`, and updated with `set` when the insert fails because the
+    /// key is taken, a concurrent insert of the same key included. Both are recorded in the
+    /// audit log of an audited model and emit their events. Another unique column taken by a
+    /// different row makes both writes fail, so that row is never overwritten.
+    ///
+    /// # Arguments
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// `true` if the insert or update is successful, `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// let mut user = User {
+    ///     name: "joe".to_string(),
+    ///     ..Default::default()
+    /// };
+    /// user.save_or_update(&conn).await; // insert
+    ///
+    /// let mut user = User::get(kwargs!(name == "joe"), &conn).await.unwrap();
+    /// user.age = 20;
+    /// user.save_or_update(&conn).await; // update
+    /// ```
+    async fn save_or_update(&self, conn: &Connection) -> bool
+    where
+        Self: Sized + Serialize + Send + Sync,
+    {
+        let Some(id_arg) = primary_key_arg(self) else {
+            return self.save(conn).await;
+        };
+        let Some((columns, values)) = insert_values(self) else {
+            return false;
+        };
+        let kw = columns
+            .into_iter()
+            .zip(values)
+            .map(|(column, value)| Condition::field(column, "=", value))
+            .collect::<Vec<_>>();
+        let changes = kw
+            .iter()
+            .filter(|condition| {
+                !matches!(condition, Condition::FieldCondition { field, .. } if field == Self::PK)
+            })
+            .cloned()
+            .collect();
+        if Self::create(kw, conn).await {
+            return true;
+        }
+
+        // the insert failed, update the row only if it is there
+        let query = QuerySettings::of(conn).tagged(format!(
+            "select count(*) from {table_name} where {id}={placeholder};",
+            id = Self::PK,
+            placeholder = Backend::of(conn).placeholder(1),
+            table_name = Self::NAME,
+        ));
+        let mut stream = sqlx::query(&query);
+        binds!([id_arg.clone()], stream);
+        let exists = instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "save_or_update",
+            &query,
+            stream.fetch_one(conn),
+        )
+        .await
+        .is_ok_and(|row| row.get::<i64, _>(0) > 0);
+        exists && Self::set(id_arg, changes, conn).await
+    }
+
+    /// Re-reads the `DB_GENERATED` columns of the current instance from the database.
+    ///
+    /// `save` and `update` take `&self` and leave the instance as it was, so call it after them,
+    /// or use `save_and_refresh` and `update_and_refresh`, which do. The primary key has to be
+    /// set.
+    ///
+    /// # Arguments
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// `true` if the columns were refreshed (or there is nothing to refresh), `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// user.update(&conn).await;
+    /// user.refresh_generated(&conn).await;
+    /// println!("updated at {}", user.updated_at);
+    /// ```
+    async fn refresh_generated(&mut self, conn: &Connection) -> bool
+    where
+        Self: Sized + Serialize + DeserializeOwned + Send + Unpin + for<'r> FromRow<'r, AnyRow>,
+    {
+        refresh_columns(self, Self::DB_GENERATED, "refresh_generated", conn).await
+    }
+
+    /// Saves the current instance like `save_returning_pk`, then stores the primary key
+    /// generated by the database in it and re-reads its `DB_GENERATED` columns, so it never
+    /// holds stale generated values.
+    ///
+    /// # Returns
+    /// `true` if the instance was saved and refreshed, `false` otherwise. The row may have been
+    /// saved when the refresh fails, e.g. on mysql when the key is not an auto increment.
+    ///
+    /// # Example
+    /// ```
+    /// let mut order = Order { total: 42.0, ..Default::default() };
+    /// order.save_and_refresh(&conn).await;
+    /// println!("order {} created at {}", order.id, order.created_at);
+    /// ```
+    async fn save_and_refresh(&mut self, conn: &Connection) -> bool
+    where
+        Self: Sized
+            + Serialize
+            + DeserializeOwned
+            + Send
+            + Sync
+            + Unpin
+            + for<'r> FromRow<'r, AnyRow>,
+    {
+        let Some((columns, values)) = insert_values(self) else {
+            return false;
+        };
+        let kw = columns
+            .into_iter()
+            .zip(values)
+            .map(|(column, value)| Condition::field(column, "=", value))
+            .collect();
+        let Some(pk) = insert::<Self>(kw, conn).await else {
+            return false;
+        };
+        if primary_key_arg(self).is_none() {
+            let Some(pk) = pk else {
+                return false;
+            };
+            if !set_column(self, Self::PK, pk) {
+                return false;
+            }
+        }
+        self.refresh_generated(conn).await
+    }
+
+    /// Updates the current instance like `update`, then re-reads its `DB_GENERATED` columns,
+    /// e.g. an `updated_at` column set by a trigger.
+    ///
+    /// # Returns
+    /// `true` if the instance was updated and refreshed, `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// user.role = "admin".to_string();
+    /// user.update_and_refresh(&conn).await;
+    /// println!("updated at {}", user.updated_at);
+    /// ```
+    async fn update_and_refresh(&mut self, conn: &Connection) -> bool
+    where
+        Self: Sized
+            + Serialize
+            + DeserializeOwned
+            + Send
+            + Sync
+            + Unpin
+            + for<'r> FromRow<'r, AnyRow>,
+    {
+        self.update(conn).await && self.refresh_generated(conn).await
+    }
+
+    /// Reads the `DEFERRED` columns of the current instance, left out by `all` and `filter`.
+    /// The primary key has to be set.
+    ///
+    /// # Returns
+    /// `true` if the columns were loaded (or the model has none), `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// let mut article = Article::get(kwargs!(slug == "hello"), &conn).await.unwrap();
+    /// article.load_deferred(&conn).await;
+    /// println!("{}", article.body);
+    /// ```
+    async fn load_deferred(&mut self, conn: &Connection) -> bool
+    where
+        Self: Sized + Serialize + DeserializeOwned + Send + Unpin + for<'r> FromRow<'r, AnyRow>,
+    {
+        refresh_columns(self, Self::DEFERRED, "load_deferred", conn).await
+    }
+
+    /// Re-reads the row of the current instance by its primary key and replaces every field
+    /// with the stored values, to pick up the changes made by other writers.
+    ///
+    /// # Arguments
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// `true` if the instance was refreshed, `false` if the primary key is unset or the row no
+    /// longer exists, in which case the instance is left unchanged.
+    ///
+    /// # Example
+    /// ```
+    /// if !user.refresh(&conn).await {
+    ///     println!("the user was deleted");
+    /// }
+    /// ```
+    async fn refresh(&mut self, conn: &Connection) -> bool
+    where
+        Self: Sized + Serialize + Send + Unpin + for<'r> FromRow<'r, AnyRow>,
+    {
+        let Some(id_arg) = primary_key_arg(self) else {
+            return false;
+        };
+        let query = QuerySettings::of(conn).tagged(format!(
+            "select * from {table_name} where {id}={placeholder};",
+            id = Self::PK,
+            placeholder = Backend::of(conn).placeholder(1),
+            table_name = Self::NAME,
+        ));
+        let mut stream = sqlx::query(&query);
+        binds!([id_arg], stream);
+        match instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "refresh",
+            &query,
+            stream.fetch_optional(conn),
+        )
+        .await
+        .and_then(|row| {
+            row.map(|row| decode_row::<Self>(&QuerySettings::of(conn), row))
+                .transpose()
+        }) {
+            Ok(Some(fresh)) => {
+                *self = fresh;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Updates a specific model instance identified by its primary key with the given parameters.
+    ///
+    /// # Arguments
+    /// * `id_value` - The value of the primary key.
+    /// * `kw` - The key-value arguments for the update.
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// `true` if update is successful, `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// let success = User::set(
+    ///     user_id,
+    ///     kwargs!(role = "admin"),
+    ///     &conn,
+    /// ).await;
+    /// println!("Set success: {}", success);
+    /// ```
+    async fn set<T: Into<Arg> + Send>(id_value: T, kw: Vec<Condition>, conn: &Connection) -> bool {
+        let Ok(kw) = encryption::encrypt::<Self>(&QuerySettings::of(conn), writable::<Self>(kw))
+        else {
+            return false;
+        };
+        let (placeholders, mut args) = kw.to_update_query(Backend::of(conn));
+        let id_arg: Arg = id_value.into();
+        args.push(id_arg.clone());
+        let index_id = args.len();
+        let query = QuerySettings::of(conn).tagged(format!(
+            "update {table_name} set {placeholders} where {id}={placeholder};",
+            id = Self::PK,
+            placeholder = Backend::of(conn).placeholder(index_id),
+            table_name = Self::NAME,
+        ));
+        let mut stream = sqlx::query(&query);
+        binds!(args, stream);
+        let values = audit::changes::<Self>(&kw);
+        if !Self::AUDITED {
+            if instrument(
+                &QuerySettings::of(conn),
+                Self::NAME,
+                "set",
+                &query,
+                stream.execute(conn),
+            )
+            .await
+            .is_err()
+            {
+                return false;
+            }
+        } else {
+            let Ok(mut tx) = conn.begin().await else {
+                return false;
+            };
+            if instrument(
+                &QuerySettings::of(conn),
+                Self::NAME,
+                "set",
+                &query,
+                stream.execute(&mut *tx),
+            )
+            .await
+            .is_err()
+            {
+                return false;
+            }
+            let pk = Some(id_arg.clone());
+            if !audit::record(
+                &mut *tx,
+                Backend::of(conn),
+                &QuerySettings::of(conn),
+                Self::NAME,
+                AuditAction::Update,
+                pk,
+                values.clone(),
+            )
+            .await
+                || tx.commit().await.is_err()
+            {
+                return false;
+            }
+        }
+        events::emit::<Self>(|| Event::Updated { pk: id_arg, values });
+        true
+    }
+
+    /// Updates a specific model instance identified by its primary key and returns the fresh row.
+    ///
+    /// The row is read back with `RETURNING *`, or with a select in the same transaction when
+    /// the backend does not support it, so values modified by triggers or defaults are visible right away.
+    ///
+    /// # Arguments
+    /// * `id_value` - The value of the primary key.
+    /// * `kw` - The key-value arguments for the update.
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// The updated instance, or `None` if the update failed or no row matched.
+    ///
+    /// # Example
+    /// ```
+    /// if let Some(user) = User::update_returning(user_id, kwargs!(role = "admin"), &conn).await {
+    ///     println!("{:#?}", user);
+    /// }
+    /// ```
+    async fn update_returning<T: Into<Arg> + Send>(
+        id_value: T,
+        kw: Vec<Condition>,
+        conn: &Connection,
+    ) -> Option<Self>
+    where
+        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
+    {
+        let kw =
+            encryption::encrypt::<Self>(&QuerySettings::of(conn), writable::<Self>(kw)).ok()?;
+        let (placeholders, mut args) = kw.to_update_query(Backend::of(conn));
+        let id_arg: Arg = id_value.into();
+        args.push(id_arg.clone());
+        let index_id = args.len();
+        let query = format!(
+            "update {table_name} set {placeholders} where {id}={placeholder}",
+            id = Self::PK,
+            placeholder = Backend::of(conn).placeholder(index_id),
+            table_name = Self::NAME,
+        );
+
+        // the audit entry is recorded in the transaction of the update
+        if Capabilities::of(conn).returning && !Self::AUDITED {
+            let query = QuerySettings::of(conn).tagged(format!("{query} returning *;"));
+            let mut stream = sqlx::query(&query);
+            binds!(args, stream);
+            let row = instrument(
+                &QuerySettings::of(conn),
+                Self::NAME,
+                "update_returning",
+                &query,
+                stream.fetch_optional(conn),
+            )
+            .await
+            .ok()
+            .flatten()?;
+            let row = decode_row::<Self>(&QuerySettings::of(conn), row).ok()?;
+            events::emit::<Self>(|| Event::Updated {
+                pk: id_arg,
+                values: audit::changes::<Self>(&kw),
+            });
+            return Some(row);
+        }
+
+        let mut tx = conn.begin().await.ok()?;
+        let query = QuerySettings::of(conn).tagged(format!("{query};"));
+        let mut stream = sqlx::query(&query);
+        binds!(args, stream);
+        instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "update_returning",
+            &query,
+            stream.execute(&mut *tx),
+        )
+        .await
+        .ok()?;
+
+        let query = QuerySettings::of(conn).tagged(format!(
+            "select * from {table_name} where {id}={placeholder};",
+            id = Self::PK,
+            placeholder = Backend::of(conn).placeholder(1),
+            table_name = Self::NAME,
+        ));
+        let mut stream = sqlx::query(&query);
+        binds!([id_arg.clone()], stream);
+        let row = instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "update_returning",
+            &query,
+            stream.fetch_optional(&mut *tx),
+        )
+        .await
+        .ok()?;
+        let row = decode_row::<Self>(&QuerySettings::of(conn), row?).ok()?;
+        let values = audit::changes::<Self>(&kw);
+        if Self::AUDITED {
+            let pk = Some(id_arg.clone());
+            if !audit::record(
+                &mut *tx,
+                Backend::of(conn),
+                &QuerySettings::of(conn),
+                Self::NAME,
+                AuditAction::Update,
+                pk,
+                values.clone(),
+            )
+            .await
+            {
+                return None;
+            }
+        }
+        tx.commit().await.ok()?;
+        events::emit::<Self>(|| Event::Updated { pk: id_arg, values });
+        Some(row)
+    }
+
+    /// Deletes the current model instance from the database.
+    ///
+    /// # Arguments
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// `true` if delete is successful, `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// let success = user.delete(&conn).await;
+    /// println!("Delete success: {}", success);
+    /// ```
+    async fn delete(&self, conn: &Connection) -> bool
+    where
+        Self: Sized;
+
+    /// Deletes the current instance and, first, the rows referencing it, for tables whose
+    /// foreign keys have no `ON DELETE CASCADE`.
+    ///
+    /// The referencing rows are found from the foreign keys of the models registered in
+    /// `models`, recursively, and everything is deleted in one transaction. Cycles, such as a
+    /// foreign key of a table to itself, are not followed.
+    ///
+    /// # Returns
+    /// `true` if the instance was deleted, `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// let models = Migrations::new().model::<User>().model::<Product>();
+    /// // deletes the products of the user, then the user
+    /// user.delete_cascade(&models, &conn).await;
+    /// ```
+    async fn delete_cascade(&self, models: &Migrations, conn: &Connection) -> bool
+    where
+        Self: Sized + Serialize + Sync,
+    {
+        let Some(id_arg) = primary_key_arg(self) else {
+            return false;
+        };
+        let filter = format!("{id} = {}", Backend::of(conn).placeholder(1), id = Self::PK);
+        let models = models.models().collect::<Vec<_>>();
+        let mut statements = Vec::new();
+        cascade(&models, Self::NAME, &filter, &mut vec![], &mut statements);
+
+        let Ok(mut tx) = conn.begin().await else {
+            return false;
+        };
+        let mut deleted = 0;
+        let settings = QuerySettings::of(conn);
+        for query in statements.into_iter().map(|query| settings.tagged(query)) {
+            let mut stream = sqlx::query(&query);
+            binds!([id_arg.clone()], stream);
+            match instrument(
+                &settings,
+                Self::NAME,
+                "delete_cascade",
+                &query,
+                stream.execute(&mut *tx),
+            )
+            .await
+            {
+                Ok(result) => deleted = result.rows_affected(),
+                Err(_) => return false,
+            }
+        }
+        // the last statement deletes the instance itself
+        if deleted == 0 {
+            return false;
+        }
+        if Self::AUDITED {
+            let changes = serde_json::to_value(self).unwrap_or_default();
+            let pk = Some(id_arg.clone());
+            if !audit::record(
+                &mut *tx,
+                Backend::of(conn),
+                &QuerySettings::of(conn),
+                Self::NAME,
+                AuditAction::Delete,
+                pk,
+                changes,
+            )
+            .await
+            {
+                return false;
+            }
+        }
+        if tx.commit().await.is_err() {
+            return false;
+        }
+        events::emit::<Self>(|| Event::Deleted { pk: id_arg });
+        true
+    }
+
+    /// Records a write of the current instance in the audit log when the model is audited.
+    ///
+    /// Only the writes of the crate's methods are recorded on their own, in their transaction:
+    /// `create`, `set`, `update_returning`, `delete_cascade`, `delete_where` and `update_where`.
+    /// `save`, `update` and `delete` are written by the derive, which knows nothing of the audit
+    /// log, so their writes are only recorded by calling this method after them, outside of
+    /// their transaction.
+    ///
+    /// # Returns
+    /// `true` if the entry was recorded or the model is not audited, `false` otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// user.audit(AuditAction::Update, serde_json::json!({"role": "admin"}), &conn).await;
+    /// ```
+    async fn audit(
+        &self,
+        action: AuditAction,
+        changes: serde_json::Value,
+        conn: &Connection,
+    ) -> bool
+    where
+        Self: Sized + Serialize + Sync,
+    {
+        if !Self::AUDITED {
+            return true;
+        }
+        audit::record(
+            conn,
+            Backend::of(conn),
+            &QuerySettings::of(conn),
+            Self::NAME,
+            action,
+            primary_key_arg(self),
+            changes,
+        )
+        .await
+    }
+
+    /// Starts a `SELECT` of the rows matching the conditions, with row locks or a limit, see
+    /// `SelectBuilder`.
+    ///
+    /// # Example
+    /// ```
+    /// let job = Job::select(kwargs!(status == "pending"))
+    ///     .limit(1)
+    ///     .for_update()
+    ///     .skip_locked()
+    ///     .fetch_in(tx)
+    ///     .await?;
+    /// ```
+    fn select(kw: Vec<Condition>) -> SelectBuilder<Self>
+    where
+        Self: Sized,
+    {
+        SelectBuilder::new(kw)
+    }
+
+    /// Returns the queries of the model ignoring its `DEFAULT_SCOPE`, e.g. to list the
+    /// soft-deleted rows.
+    ///
+    /// # Example
+    /// ```
+    /// let archived = Post::unscoped().filter(kwargs!(is_active == false), &conn).await;
+    /// ```
+    fn unscoped() -> Unscoped<Self>
+    where
+        Self: Sized,
+    {
+        Unscoped::new()
+    }
+
+    /// Returns the queries of the model selecting the `DEFERRED` columns too.
+    ///
+    /// # Example
+    /// ```
+    /// let articles = Article::with_deferred().filter(kwargs!(published == true), &conn).await;
+    /// ```
+    fn with_deferred() -> WithDeferred<Self>
+    where
+        Self: Sized,
+    {
+        WithDeferred::new()
+    }
+
+    /// Starts a `DELETE` of the rows matching the conditions, see `DeleteBuilder`.
+    ///
+    /// # Arguments
+    /// * `kw` - The key-value arguments for filtering.
+    ///
+    /// # Example
+    /// ```
+    /// let deleted = User::delete_where(kwargs!(age < 18)).limit(1000).execute(&conn).await;
+    /// println!("Deleted {deleted} users");
+    /// ```
+    fn delete_where(kw: Vec<Condition>) -> DeleteBuilder<Self>
+    where
+        Self: Sized,
+    {
+        DeleteBuilder::new(kw)
+    }
+
+    /// Starts an `UPDATE` of the rows matching the conditions, see `UpdateBuilder`.
+    ///
+    /// # Arguments
+    /// * `kw` - The key-value arguments for filtering.
+    /// * `values` - The key-value arguments to set.
+    ///
+    /// # Example
+    /// ```
+    /// let updated = User::update_where(kwargs!(age < 18), kwargs!(role = "minor"))
+    ///     .limit(1000)
+    ///     .execute(&conn)
+    ///     .await;
+    /// println!("Updated {updated} users");
+    /// ```
+    fn update_where(kw: Vec<Condition>, values: Vec<Condition>) -> UpdateBuilder<Self>
+    where
+        Self: Sized,
+    {
+        UpdateBuilder::new(kw, values)
+    }
+
+    /// Decodes the rows of a result set, used by `all` and `filter`.
+    ///
+    /// The default implementation decodes every row with `FromRow`. Implementations can
+    /// resolve the column ordinals once with `ColumnOrdinals` to speed up wide result sets.
+    ///
+    /// # Errors
+    /// Returns the first decoding error.
+    fn from_rows(rows: &[AnyRow]) -> sqlx::Result<Vec<Self>>
+    where
+        Self: Sized + for<'r> FromRow<'r, AnyRow>,
+    {
+        rows.iter().map(Self::from_row).collect()
+    }
+
+    /// Retrieves all instances of the model from the database.
+    ///
+    /// # Arguments
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// A vector of all instances of the model.
+    ///
+    /// # Example
+    /// ```
+    /// let users = User::all(&conn).await;
+    /// println!("{:#?}", users);
+    /// ```
+    async fn all(conn: &Connection) -> Vec<Self>
+    where
+        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
+    {
+        select(Vec::new(), true, false, "all", conn).await
+    }
+
+    /// Filters instances of the model based on the provided parameters.
+    ///
+    /// # Arguments
+    /// * `kw` - The key-value arguments for filtering.
+    /// * `conn` - The database connection.
+    ///
+    /// # Returns
+    /// A vector of instances matching the filter criteria.
+    ///
+    /// # Example
+    /// ```
+    /// let users = User::filter(
+    ///     kwargs!(age <= 18).and(kwargs!(weight == 80.0)),
+    ///     &conn,
+    /// ).await;
+    /// println!("{:#?}", users);
+    /// ```
+    ///
+    /// Groups of alternatives, e.g. from a search form, can be combined with `any_of_each`.
+    async fn filter(kw: Vec<Condition>, conn: &Connection) -> Vec<Self>
+    where
+        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
+    {
+        select(kw, true, false, "filter", conn).await
+    }
 
-    /// Migrates the model schema to the database
+    /// Filters the rows like `filter` but decodes them as `D`, selecting only the columns
+    /// named by the fields of `D`, e.g. so a listing skips the heavy columns of the model.
     ///
-    /// # Arguments
-    /// * `conn` - The database connection
+    /// The columns are read from the `Deserialize` implementation of `D`, see `field_names`;
+    /// every column is selected when `D` is not a plain struct.
     ///
     /// # Returns
-    /// `true` if the migration was successful, `false` otherwise
+    /// The matching rows, empty if the statement failed or a row does not decode as `D`.
     ///
     /// # Example
-    /// ```rust
-    /// let success = User::migrate(&conn).await;
-    /// println!("Migration success: {}", success);
     /// ```
-    async fn migrate(conn: &Connection) -> bool
+    /// #[derive(FromRow, Deserialize)]
+    /// struct DocumentTitle {
+    ///     id: i32,
+    ///     title: String,
+    /// }
+    ///
+    /// // select id, title from document where owner = ?1;
+    /// let titles = Document::filter_as::<DocumentTitle>(kwargs!(owner == user_id), &conn).await;
+    /// ```
+    async fn filter_as<D>(kw: Vec<Condition>, conn: &Connection) -> Vec<D>
     where
         Self: Sized,
+        D: DeserializeOwned + Send + Unpin + for<'r> FromRow<'r, AnyRow>,
     {
-        println!("{:?}", Self::SCHEMA);
-        if let Err(err) = sqlx::query(Self::SCHEMA).execute(conn).await {
-            eprintln!("Error during the migration\n->{err}");
-            false
-        } else {
-            true
-        }
+        let columns = field_names::<D>().map_or("*".to_string(), |fields| fields.join(", "));
+        let (query, args) = select_columns_query::<Self>(Backend::of(conn), &columns, &kw, true);
+        let query = QuerySettings::of(conn).tagged(format!("{query};"));
+        let mut stream = sqlx::query(&query);
+        binds!(args, stream);
+        instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "filter_as",
+            &query,
+            stream.fetch_all(conn),
+        )
+        .await
+        .and_then(|mut rows| {
+            encryption::decrypt::<Self>(&QuerySettings::of(conn), &mut rows)?;
+            rows.iter()
+                .map(D::from_row)
+                .collect::<sqlx::Result<_>>()
+                .map_err(|err| locate(Self::NAME, &rows, err, |row| D::from_row(row)))
+        })
+        .unwrap_or_default()
     }
 
-    /// Saves the current model instance to the database.
+    /// Retrieves the first instance of the model matching the filter criteria.
     ///
     /// # Arguments
+    /// * `kw` - The key-value arguments for filtering.
     /// * `conn` - The database connection.
     ///
     /// # Returns
-    /// `true` if save is successful, `false` otherwise.
+    /// An optional instance matching the filter criteria.
     ///
     /// # Example
     /// ```
-    /// let user = User {
-    ///     name: "johnDoe@gmail.com".to_string(),
-    ///     email: "21john@gmail.com".to_string(),
-    ///     password: "p455w0rd".to_string(),
-    ///     age: 18,
-    ///     weight: 60.0,
-    ///     ..Default::default()
-    /// };
-    /// let success = user.save(&conn).await;
-    /// println!("Save success: {}", success);
+    /// let user = User::get(
+    ///     kwargs!(email == "24nomeniavo@gmail.com").and(kwargs!(password == "strongpassword")),
+    ///     &conn,
+    /// ).await;
+    /// println!("{:#?}", user);
     /// ```
-    async fn save(&self, conn: &Connection) -> bool
+    async fn get(kw: Vec<Condition>, conn: &Connection) -> Option<Self>
     where
-        Self: Sized;
+        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
+    {
+        Self::filter(kw, conn).await.first().cloned()
+    }
 
-    /// Creates a new model instance with the specified parameters.
+    /// Retrieves `n` instances picked at random, e.g. sample rows for admin and testing tools.
     ///
-    /// # Arguments
-    /// * `kw` - The key-value arguments for the new instance.
-    /// * `conn` - The database connection.
+    /// The rows are sorted with `random()`, `rand()` on mysql, which reads the whole table; see
+    /// `sample` for large tables.
     ///
     /// # Returns
-    /// `true` if creation is successful, `false` otherwise.
+    /// At most `n` instances, empty if the statement failed.
     ///
     /// # Example
     /// ```
-    /// let success = User::create(
-    ///     kwargs!(
-    ///         name = "joe",
-    ///         email = "24nomeniavo@gmail.com",
-    ///         password = "strongpassword",
-    ///         age = 19,
-    ///         weight = 80.1
-    ///     ),
-    ///     &conn,
-    /// ).await;
-    /// println!("Create success: {}", success);
+    /// let users = User::random(5, &conn).await;
     /// ```
-    async fn create(kw: Vec<Condition>, conn: &Connection) -> bool
+    async fn random(n: usize, conn: &Connection) -> Vec<Self>
     where
-        Self: Sized,
+        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
     {
-        let (fields, placeholders, args) = kw.to_insert_query();
-
-        let query = format!(
-            "insert into {table_name} ({fields}) values ({placeholders});",
-            table_name = Self::NAME
-        );
+        let (query, args) = select_query::<Self>(Backend::of(conn), &Vec::new(), true);
+        let query = QuerySettings::of(conn).tagged(format!(
+            "{query} order by {random} limit {n};",
+            random = Backend::of(conn).random()
+        ));
         let mut stream = sqlx::query(&query);
         binds!(args, stream);
-        stream.execute(conn).await.is_ok()
+        instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "random",
+            &query,
+            stream.fetch_all(conn),
+        )
+        .await
+        .and_then(|rows| decode_rows::<Self>(&QuerySettings::of(conn), rows))
+        .unwrap_or_default()
     }
 
-    /// Updates the current model instance in the database.
+    /// Retrieves about `percent` percent of the instances, each row being picked independently.
     ///
-    /// # Arguments
-    /// * `conn` - The database connection.
+    /// Postgres samples the table with `tablesample bernoulli`, the other databases filter the
+    /// rows on a random value. Unlike `random`, no sort is needed, so it suits large tables.
     ///
     /// # Returns
-    /// `true` if update is successful, `false` otherwise.
+    /// The sampled instances, empty if the statement failed.
     ///
     /// # Example
     /// ```
-    /// if let Some(mut user) = User::get(
-    ///     kwargs!(email == "24nomeniavo@gmail.com").and(kwargs!(password == "strongpassword")),
-    ///     &conn,
-    /// ).await {
-    ///     user.role = "admin".to_string();
-    ///     let success = user.update(&conn).await;
-    ///     println!("Update success: {}", success);
-    /// }
+    /// // about 1% of the orders
+    /// let orders = Order::sample(1.0, &conn).await;
     /// ```
-    async fn update(&self, conn: &Connection) -> bool
+    async fn sample(percent: f64, conn: &Connection) -> Vec<Self>
     where
-        Self: Sized;
+        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
+    {
+        let percent = percent.clamp(0.0, 100.0);
+        let (filter, args) = select_filter::<Self>(Backend::of(conn), &Vec::new(), true);
+        let query = match (Backend::of(conn), filter) {
+            (Backend::Postgres, filter) => format!(
+                "select {columns} from {table_name} tablesample bernoulli ({percent}){filter};",
+                columns = selected_columns::<Self>(false),
+                table_name = Self::NAME,
+                filter = filter.map_or(String::new(), |filter| format!(" where {filter}"))
+            ),
+            (backend, filter) => {
+                let random = match backend {
+                    // random() returns a 64-bit integer on sqlite
+                    Backend::Sqlite => "(random() / 18446744073709551616.0 + 0.5)",
+                    _ => backend.random(),
+                };
+                let sampled = format!("{random} < {}", percent / 100.0);
+                let filter = match filter {
+                    Some(filter) => format!("({filter}) and {sampled}"),
+                    None => sampled,
+                };
+                format!(
+                    "select {columns} from {table_name} where {filter};",
+                    columns = selected_columns::<Self>(false),
+                    table_name = Self::NAME
+                )
+            }
+        };
+        let query = QuerySettings::of(conn).tagged(query);
+        let mut stream = sqlx::query(&query);
+        binds!(args, stream);
+        instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "sample",
+            &query,
+            stream.fetch_all(conn),
+        )
+        .await
+        .and_then(|rows| decode_rows::<Self>(&QuerySettings::of(conn), rows))
+        .unwrap_or_default()
+    }
 
-    /// Updates a specific model instance identified by its primary key with the given parameters.
+    /// Retrieves a page of the instances matching the filter criteria, with the number of
+    /// matching instances, e.g. for paginated endpoints.
+    ///
+    /// The rows are ordered by primary key, so the pages do not overlap. The page and the count
+    /// are two statements, a write committed in between can make them disagree.
     ///
     /// # Arguments
-    /// * `id_value` - The value of the primary key.
-    /// * `kw` - The key-value arguments for the update.
+    /// * `kw` - The key-value arguments for filtering.
+    /// * `limit` - The maximum number of instances of the page.
+    /// * `offset` - The number of instances before the page.
     /// * `conn` - The database connection.
     ///
     /// # Returns
-    /// `true` if update is successful, `false` otherwise.
+    /// The instances of the page and the number of matching instances, empty and `0` if a
+    /// statement failed.
     ///
     /// # Example
     /// ```
-    /// let success = User::set(
-    ///     user_id,
-    ///     kwargs!(role = "admin"),
-    ///     &conn,
-    /// ).await;
-    /// println!("Set success: {}", success);
+    /// let (users, total) = User::filter_and_count(kwargs!(age >= 18), 20, 40, &conn).await;
+    /// println!("{} of {total}", users.len());
     /// ```
-    async fn set<T: ToString + Clone + Send + Sync>(
-        id_value: T,
+    async fn filter_and_count(
         kw: Vec<Condition>,
+        limit: usize,
+        offset: usize,
         conn: &Connection,
-    ) -> bool {
-        let (placeholders, mut args) = kw.to_update_query();
+    ) -> (Vec<Self>, i64)
+    where
+        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
+    {
+        let (query, args) = select_query::<Self>(Backend::of(conn), &kw, true);
 
-        args.push((
-            id_value.clone().to_string(),
-            get_type_name(id_value.clone()).to_string(),
-        ));
-        let index_id = args.len();
-        let placeholder = PLACEHOLDER.to_string();
-        let query = format!(
-            "update {table_name} set {placeholders} where {id}={placeholder}{index_id};",
-            id = Self::PK,
-            table_name = Self::NAME,
-        );
+        let count_query =
+            QuerySettings::of(conn).tagged(format!("select count(*) from ({query}) as matching;"));
+        let mut stream = sqlx::query(&count_query);
+        binds!(args.clone(), stream);
+        let Ok(total) = instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "filter_and_count",
+            &count_query,
+            stream.fetch_one(conn),
+        )
+        .await
+        .and_then(|row| row.try_get::<i64, _>(0)) else {
+            return (Vec::new(), 0);
+        };
 
+        let query = QuerySettings::of(conn).tagged(format!(
+            "{query} order by {pk} limit {limit} offset {offset};",
+            pk = Self::PK
+        ));
         let mut stream = sqlx::query(&query);
         binds!(args, stream);
-        stream.execute(conn).await.is_ok()
+        match instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "filter_and_count",
+            &query,
+            stream.fetch_all(conn),
+        )
+        .await
+        .and_then(|rows| decode_rows::<Self>(&QuerySettings::of(conn), rows))
+        {
+            Ok(rows) => (rows, total),
+            Err(_) => (Vec::new(), 0),
+        }
     }
 
-    /// Deletes the current model instance from the database.
+    /// Returns the row of `T` referenced by the foreign key `column` of the current instance.
     ///
-    /// # Arguments
-    /// * `conn` - The database connection.
+    /// The foreign key is read from the schema of the model; relation accessors such as
+    /// `product.owner_object(&conn)` delegate to it.
     ///
     /// # Returns
-    /// `true` if delete is successful, `false` otherwise.
+    /// The referenced instance, or `None` if the column is not a foreign key to `T`, is null or
+    /// references no row.
     ///
     /// # Example
     /// ```
-    /// let success = user.delete(&conn).await;
-    /// println!("Delete success: {}", success);
+    /// // product (..., owner integer references user(id))
+    /// let owner: Option<User> = product.related("owner", &conn).await;
     /// ```
-    async fn delete(&self, conn: &Connection) -> bool
+    async fn related<T>(&self, column: &str, conn: &Connection) -> Option<T>
     where
-        Self: Sized;
+        Self: Sized + Serialize + Sync,
+        T: Model + Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone + Send,
+    {
+        let foreign_key = ModelSchema::of::<Self>()
+            .foreign_keys()
+            .into_iter()
+            .find(|foreign_key| foreign_key.column == column && foreign_key.table == T::NAME)?;
+        let value = column_arg(self, column)?;
+        T::get(
+            vec![Condition::field(foreign_key.references, "=", value)],
+            conn,
+        )
+        .await
+    }
 
-    /// Retrieves all instances of the model from the database.
+    /// Returns the rows of `T` whose foreign key `column` references the current instance.
     ///
-    /// # Arguments
-    /// * `conn` - The database connection.
+    /// Reverse accessors such as `user.product_set(&conn)` delegate to it.
     ///
     /// # Returns
-    /// A vector of all instances of the model.
+    /// The referencing instances, empty if `column` is not a foreign key of `T` to this model.
     ///
     /// # Example
     /// ```
-    /// let users = User::all(&conn).await;
-    /// println!("{:#?}", users);
+    /// // product (..., owner integer references user(id))
+    /// let products: Vec<Product> = user.related_set("owner", &conn).await;
     /// ```
-    async fn all(conn: &Connection) -> Vec<Self>
+    async fn related_set<T>(&self, column: &str, conn: &Connection) -> Vec<T>
     where
-        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
+        Self: Sized + Serialize + Sync,
+        T: Model + Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone + Send,
     {
-        let query = format!("select * from {table_name}", table_name = Self::NAME);
-        sqlx::query_as::<_, Self>(&query)
-            .fetch_all(conn)
-            .await
-            .unwrap_or_default()
+        let Some(foreign_key) = ModelSchema::of::<T>()
+            .foreign_keys()
+            .into_iter()
+            .find(|foreign_key| foreign_key.column == column && foreign_key.table == Self::NAME)
+        else {
+            return Vec::new();
+        };
+        match column_arg(self, &foreign_key.references) {
+            Some(value) => T::filter(vec![Condition::field(column, "=", value)], conn).await,
+            None => Vec::new(),
+        }
     }
 
-    /// Filters instances of the model based on the provided parameters.
+    /// Returns the row of `T` whose unique foreign key `column` references the current instance,
+    /// the reverse side of a one-to-one relation.
     ///
-    /// # Arguments
-    /// * `kw` - The key-value arguments for filtering.
-    /// * `conn` - The database connection.
+    /// # Example
+    /// ```
+    /// // profile (..., user integer unique references user(id))
+    /// let profile: Option<Profile> = user.related_one("user", &conn).await;
+    /// ```
+    async fn related_one<T>(&self, column: &str, conn: &Connection) -> Option<T>
+    where
+        Self: Sized + Serialize + Sync,
+        T: Model + Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone + Send,
+    {
+        self.related_set(column, conn).await.into_iter().next()
+    }
+
+    /// Links the current instance to `target` with a row of the join model `J`, whose foreign
+    /// keys reference both models.
     ///
     /// # Returns
-    /// A vector of instances matching the filter criteria.
+    /// `true` if the row was inserted, `false` otherwise.
     ///
     /// # Example
     /// ```
-    /// let users = User::filter(
-    ///     kwargs!(age <= 18).and(kwargs!(weight == 80.0)),
-    ///     &conn,
-    /// ).await;
-    /// println!("{:#?}", users);
+    /// // product_tag (product integer references product(id), tag integer references tag(id))
+    /// product.add_related::<Tag, ProductTag>(&tag, &conn).await;
     /// ```
-    async fn filter(kw: Vec<Condition>, conn: &Connection) -> Vec<Self>
+    async fn add_related<T, J>(&self, target: &T, conn: &Connection) -> bool
     where
-        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
+        Self: Sized + Serialize + Sync,
+        T: Model + Serialize + Sync,
+        J: Model + Send + Sync,
     {
-        let (fields, args) = kw.to_select_query();
-
-        let query = format!(
-            "SELECT * FROM {table_name} WHERE {fields};",
-            table_name = Self::NAME
-        );
-
-        let mut stream = sqlx::query_as::<_, Self>(&query);
-        binds!(args, stream);
-        stream.fetch_all(conn).await.unwrap_or_default()
+        let Some(kw) = through_conditions::<Self, T, J>(self, target) else {
+            return false;
+        };
+        J::create(kw, conn).await
     }
 
-    /// Retrieves the first instance of the model matching the filter criteria.
-    ///
-    /// # Arguments
-    /// * `kw` - The key-value arguments for filtering.
-    /// * `conn` - The database connection.
+    /// Removes the rows of the join model `J` linking the current instance to `target`.
     ///
     /// # Returns
-    /// An optional instance matching the filter criteria.
+    /// `true` if a row was deleted, `false` otherwise.
+    async fn remove_related<T, J>(&self, target: &T, conn: &Connection) -> bool
+    where
+        Self: Sized + Serialize + Sync,
+        T: Model + Serialize + Sync,
+        J: Model + Send + Sync,
+    {
+        let Some(kw) = through_conditions::<Self, T, J>(self, target) else {
+            return false;
+        };
+        J::delete_where(kw).execute(conn).await > 0
+    }
+
+    /// Returns the instances of `T` linked to the current instance by the join model `J`.
     ///
     /// # Example
     /// ```
-    /// let user = User::get(
-    ///     kwargs!(email == "24nomeniavo@gmail.com").and(kwargs!(password == "strongpassword")),
-    ///     &conn,
-    /// ).await;
-    /// println!("{:#?}", user);
+    /// let tags: Vec<Tag> = product.related_through::<Tag, ProductTag>(&conn).await;
     /// ```
-    async fn get(kw: Vec<Condition>, conn: &Connection) -> Option<Self>
+    async fn related_through<T, J>(&self, conn: &Connection) -> Vec<T>
     where
-        Self: Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Clone,
+        Self: Sized + Serialize + Sync,
+        T: Model + Sized + Unpin + for<'r> FromRow<'r, AnyRow> + Send,
+        J: Model + Send + Sync,
     {
-        Self::filter(kw, conn).await.first().cloned()
+        let Some((source, target)) = through_keys::<Self, T, J>() else {
+            return Vec::new();
+        };
+        let Some(value) = column_arg(self, &source.references) else {
+            return Vec::new();
+        };
+        let query = QuerySettings::of(conn).tagged(format!(
+            "select {columns} from {table_name} where {references} in \
+             (select {column} from {through} where {source} = {placeholder});",
+            columns = selected_columns::<T>(false),
+            table_name = T::NAME,
+            references = target.references,
+            column = target.column,
+            through = J::NAME,
+            source = source.column,
+            placeholder = Backend::of(conn).placeholder(1),
+        ));
+        let mut stream = sqlx::query(&query);
+        binds!([value], stream);
+        instrument(
+            &QuerySettings::of(conn),
+            T::NAME,
+            "related_through",
+            &query,
+            stream.fetch_all(conn),
+        )
+        .await
+        .and_then(|rows| decode_rows::<T>(&QuerySettings::of(conn), rows))
+        .unwrap_or_default()
     }
 
     /// Counts the number of instances of the model in the database.
@@ -427,11 +2714,19 @@ pub trait Model {
     where
         Self: Sized,
     {
-        let query = format!("select count(*) from {table_name}", table_name = Self::NAME);
-        sqlx::query(query.as_str())
-            .fetch_one(conn)
-            .await
-            .map_or(0, |r| r.get(0))
+        let query = QuerySettings::of(conn).tagged(format!(
+            "select count(*) from {table_name}",
+            table_name = Self::NAME
+        ));
+        instrument(
+            &QuerySettings::of(conn),
+            Self::NAME,
+            "count",
+            &query,
+            sqlx::query(query.as_str()).fetch_one(conn),
+        )
+        .await
+        .map_or(0, |r| r.get(0))
     }
 }
 
@@ -492,7 +2787,354 @@ where
     ///
     /// In the above example, all records from the `Product` table will be deleted.
     async fn delete(&self, conn: &Connection) -> bool {
-        let query = format!("delete from {table_name}", table_name = T::NAME);
-        sqlx::query(query.as_str()).execute(conn).await.is_ok()
+        let query = QuerySettings::of(conn)
+            .tagged(format!("delete from {table_name}", table_name = T::NAME));
+        instrument(
+            &QuerySettings::of(conn),
+            T::NAME,
+            "delete",
+            &query,
+            sqlx::query(query.as_str()).execute(conn),
+        )
+        .await
+        .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::audit::{with_actor, AUDIT_TABLE};
+    use crate::db::testing::{memory, AuditedUser, User};
+
+    fn field(field: &str, comparison_operator: &str, value: impl Into<Arg>) -> Vec<Condition> {
+        vec![Condition::field(field, comparison_operator, value)]
+    }
+
+    #[test]
+    fn ands_the_adjacent_conditions() {
+        let kw = [field("age", "=", 10), field("name", "=", "z")].concat();
+        let (sql, args) = kw.to_select_query(Backend::Sqlite);
+        assert_eq!(sql, "age=?1 and name=?2");
+        assert_eq!(args.len(), 2);
+        let kw = [field("a", "=", 1), field("b", "=", 2)]
+            .concat()
+            .or(field("c", "=", 3));
+        assert_eq!(
+            kw.to_select_query(Backend::Postgres).0,
+            "a=$1 and b=$2 or c=$3"
+        );
+    }
+
+    #[test]
+    fn renders_the_conditions_with_numbered_placeholders() {
+        let kw = field("name", "=", "joe").and(field("age", ">=", 18));
+        let (sql, args) = kw.to_select_query(Backend::Postgres);
+        assert_eq!(sql, "name=$1 and age>=$2");
+        assert_eq!(args, vec![Arg::from("joe"), Arg::Int(18)]);
+
+        let (sql, _) = kw.to_select_query_from(Backend::Sqlite, 2);
+        assert_eq!(sql, "name=?3 and age>=?4");
+    }
+
+    #[test]
+    fn compares_null_with_is_null() {
+        let kw = field("deleted_at", "=", None::<i32>).and(field("name", "!=", None::<&str>));
+        let (sql, args) = kw.to_select_query(Backend::Sqlite);
+        assert_eq!(sql, "deleted_at is null and name is not null");
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn renders_column_conditions_without_binding_them() {
+        let kw = Column::new("user", "id").equals(Column::new("post", "author"));
+        let (sql, args) = kw.to_select_query(Backend::Postgres);
+        assert_eq!(sql, "user.id=post.author");
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn combines_groups_with_any_of_each() {
+        let kw = any_of_each(&[
+            [field("name", "=", "joe"), field("email", "=", "joe@x.org")].concat(),
+            Vec::new(),
+            field("age", ">=", 18),
+        ]);
+        let (sql, args) = kw.to_select_query(Backend::Postgres);
+        assert_eq!(sql, "(name=$1 or email=$2) and (age>=$3)");
+        assert_eq!(args.len(), 3);
+
+        // a group already combined is kept as it is
+        let kw = any_of_each(&[
+            field("a", "=", 1).and(field("b", "=", 2)),
+            field("c", "=", 3),
+        ]);
+        let (sql, _) = kw.to_select_query(Backend::Sqlite);
+        assert_eq!(sql, "(a=?1 and b=?2) and (c=?3)");
+        assert!(any_of_each(&[]).is_empty());
+    }
+
+    #[test]
+    fn escapes_the_similar_to_pattern() {
+        assert_eq!(similar_pattern("joe"), "%joe%");
+        assert_eq!(similar_pattern("50%_off!"), "%50!%!_off!!%");
+
+        let kw = field("name", "similar_to", "jo_");
+        let (sql, args) = kw.to_select_query(Backend::Sqlite);
+        assert_eq!(sql, "name like ?1 escape '!'");
+        assert_eq!(args, vec![Arg::from("%jo!_%")]);
+        let (sql, args) = kw.to_select_query(Backend::Postgres);
+        assert_eq!(sql, "name % $1");
+        assert_eq!(args, vec![Arg::from("jo_")]);
+    }
+
+    #[test]
+    fn escapes_the_starts_with_pattern() {
+        assert_eq!(prefix_pattern("10.0_!%"), "10.0!_!!!%%");
+
+        let kw = field("addr", "starts_with", "10_");
+        let (sql, args) = kw.to_select_query(Backend::Postgres);
+        assert_eq!(sql, "addr like $1 escape '!'");
+        assert_eq!(args, vec![Arg::from("10!_%")]);
+    }
+
+    #[test]
+    fn renders_the_conditions_of_the_backend() {
+        let kw = vec![Condition::ByBackend {
+            sqlite: field("a", "=", 1),
+            postgres: field("b", "=", 1),
+            mysql: field("c", "=", 1),
+        }];
+        assert_eq!(kw.to_select_query(Backend::Sqlite).0, "(a=?1)");
+        assert_eq!(kw.to_select_query(Backend::Postgres).0, "(b=$1)");
+        assert_eq!(kw.to_select_query(Backend::MySql).0, "(c=?)");
+    }
+
+    #[test]
+    fn renders_args_as_literals() {
+        assert_eq!(Arg::from("it's").to_string(), "'it''s'");
+        assert_eq!(Arg::from(None::<i32>).to_string(), "NULL");
+        assert_eq!(Arg::from(vec![0u8, 255]).to_string(), "x'00ff'");
+        assert_eq!(Arg::from(u64::MAX), Arg::Text(u64::MAX.to_string()));
+    }
+
+    async fn users() -> Connection {
+        let conn = memory().await;
+        assert!(User::migrate(&conn).await);
+        assert!(User::seed(vec![User::new("joe", 19), User::new("jane", 17)], &conn).await);
+        conn
+    }
+
+    #[tokio::test]
+    async fn saves_and_filters_rows() {
+        let conn = users().await;
+        assert_eq!(User::all(&conn).await.len(), 2);
+        let adults = User::filter(field("age", ">=", 18), &conn).await;
+        assert_eq!(
+            adults,
+            vec![User {
+                id: 1,
+                ..User::new("joe", 19)
+            }]
+        );
+        let jane = User::get(field("name", "=", "jane"), &conn).await.unwrap();
+        assert_eq!(jane.id, 2);
+        assert!(User::get(field("name", "=", "bob"), &conn).await.is_none());
+        assert_eq!(jane.count(&conn).await, 2);
+
+        let kw = field("name", "=", "joe").or(field("age", "<", 18));
+        assert_eq!(User::filter(kw, &conn).await.len(), 2);
+        let kw = [field("name", "=", "joe"), field("age", "=", 19)].concat();
+        assert_eq!(User::filter(kw, &conn).await.len(), 1);
+        let kw = [field("name", "=", "joe"), field("age", "=", 17)].concat();
+        assert!(User::filter(kw, &conn).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn updates_and_deletes_rows() {
+        let conn = users().await;
+        let mut joe = User::get(field("name", "=", "joe"), &conn).await.unwrap();
+        joe.age = 20;
+        assert!(joe.update(&conn).await);
+        assert!(User::set(2, field("age", "=", 18), &conn).await);
+        let ages = User::all(&conn)
+            .await
+            .iter()
+            .map(|user| user.age)
+            .collect::<Vec<_>>();
+        assert_eq!(ages, vec![20, 18]);
+
+        assert!(joe.delete(&conn).await);
+        assert_eq!(User::all(&conn).await.len(), 1);
+        assert!(User::all(&conn).await.delete(&conn).await);
+        assert!(User::all(&conn).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn saves_or_updates_from_the_primary_key() {
+        let conn = users().await;
+        assert!(User::new("bob", 30).save_or_update(&conn).await);
+        assert_eq!(User::all(&conn).await.len(), 3);
+
+        let bob = User {
+            id: 3,
+            ..User::new("bob", 31)
+        };
+        assert!(bob.save_or_update(&conn).await);
+        assert_eq!(User::get(field("id", "=", 3), &conn).await, Some(bob));
+
+        let kim = User {
+            id: 10,
+            ..User::new("kim", 40)
+        };
+        assert!(kim.save_or_update(&conn).await);
+        assert_eq!(User::get(field("id", "=", 10), &conn).await, Some(kim));
+        assert_eq!(User::all(&conn).await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn returns_the_generated_primary_key() {
+        let conn = users().await;
+        let id: i64 =
+            User::create_returning_pk(field("name", "=", "bob").and(field("age", "=", 1)), &conn)
+                .await
+                .unwrap();
+        assert_eq!(id, 3);
+        assert_eq!(
+            User::new("kim", 2).save_returning_pk::<i64>(&conn).await,
+            Some(4)
+        );
+
+        let mut ann = User::new("ann", 3);
+        assert!(ann.save_and_refresh(&conn).await);
+        assert_eq!(ann.id, 5);
+    }
+
+    #[tokio::test]
+    async fn reads_back_updated_rows() {
+        let conn = users().await;
+        let joe = User::update_returning(1, field("age", "=", 21), &conn)
+            .await
+            .unwrap();
+        assert_eq!(joe.age, 21);
+        assert!(User::update_returning(9, field("age", "=", 21), &conn)
+            .await
+            .is_none());
+
+        let mut stale = User {
+            id: 2,
+            ..User::default()
+        };
+        assert!(stale.refresh(&conn).await);
+        assert_eq!(
+            stale,
+            User {
+                id: 2,
+                ..User::new("jane", 17)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_and_counts_rows() {
+        let conn = users().await;
+        User::seed((0..5).map(|age| User::new("kid", age)).collect(), &conn).await;
+        let (page, total) = User::filter_and_count(field("name", "=", "kid"), 2, 2, &conn).await;
+        assert_eq!(total, 5);
+        assert_eq!(
+            page.iter().map(|user| user.age).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        let deleted = User::delete_where(field("name", "=", "kid"))
+            .execute(&conn)
+            .await;
+        assert_eq!(deleted, 5);
+        let updated = User::update_where(field("age", "<", 18), field("name", "=", "minor"))
+            .execute(&conn)
+            .await;
+        assert_eq!(updated, 1);
+    }
+
+    async fn audit_log(conn: &Connection) -> Vec<(String, Option<String>, Option<String>)> {
+        sqlx::query_as(&format!(
+            "select action, pk, actor from {AUDIT_TABLE} order by created_at, rowid;"
+        ))
+        .fetch_all(conn)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn records_the_writes_of_audited_models() {
+        let conn = memory().await;
+        assert!(AuditedUser::migrate(&conn).await);
+        let kw = field("name", "=", "joe").and(field("age", "=", 19));
+        assert!(with_actor("admin", AuditedUser::create(kw, &conn)).await);
+        assert!(AuditedUser::set(1, field("age", "=", 20), &conn).await);
+        let joe = AuditedUser::get(field("id", "=", 1), &conn).await.unwrap();
+        assert!(joe.delete_cascade(&Migrations::new(), &conn).await);
+
+        assert_eq!(
+            audit_log(&conn).await,
+            vec![
+                ("create".into(), Some("1".into()), Some("admin".into())),
+                ("update".into(), Some("1".into()), None),
+                ("delete".into(), Some("1".into()), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn records_save_or_update_in_the_audit_log() {
+        let conn = memory().await;
+        assert!(AuditedUser::migrate(&conn).await);
+        let mut joe = AuditedUser {
+            id: 4,
+            name: "joe".into(),
+            age: 19,
+        };
+        assert!(joe.save_or_update(&conn).await);
+        joe.age = 20;
+        assert!(joe.save_or_update(&conn).await);
+
+        assert_eq!(
+            AuditedUser::get(field("id", "=", 4), &conn).await,
+            Some(joe)
+        );
+        assert_eq!(
+            audit_log(&conn).await,
+            vec![
+                ("create".into(), Some("4".into()), None),
+                ("update".into(), Some("4".into()), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn records_the_manual_audit_entries() {
+        let conn = memory().await;
+        assert!(AuditedUser::migrate(&conn).await);
+        let joe = AuditedUser {
+            id: 7,
+            ..AuditedUser::default()
+        };
+        assert!(
+            joe.audit(AuditAction::Update, serde_json::json!({}), &conn)
+                .await
+        );
+        assert_eq!(
+            audit_log(&conn).await,
+            vec![("update".into(), Some("7".into()), None)]
+        );
+
+        // models that are not audited record nothing
+        assert!(User::migrate(&conn).await);
+        assert!(
+            User::new("joe", 1)
+                .audit(AuditAction::Create, serde_json::json!({}), &conn)
+                .await
+        );
+        assert_eq!(audit_log(&conn).await.len(), 1);
     }
 }
@@ -0,0 +1,658 @@
+//! Introspection of the live database and comparison with the schema of the models.
+//!
+//! The expected columns are read from the `CREATE TABLE` statement of a model (`Model::SCHEMA`)
+//! and the live ones from `PRAGMA table_xinfo` on sqlite, which also lists the generated
+//! columns, and `information_schema` on postgres and mysql. The differences are returned as
+//! `SchemaChange`s, which render the statements bringing the database up to date, or as
+//! `SchemaMismatch`es by `check`, which also reports the columns whose type differs.
+
+use std::fmt;
+
+use anyhow::Result;
+use sqlx::Row;
+
+use super::backend::Backend;
+use super::models::{Arg, Model};
+use super::{instrument, QuerySettings};
+use crate::{Connection, Error};
+
+/// The schema of a model, captured from its `Model` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelSchema {
+    /// The table name.
+    pub name: &'static str,
+    /// The `CREATE TABLE` statement.
+    pub schema: &'static str,
+    /// The sets of columns that must be unique together.
+    pub unique_together: &'static [&'static [&'static str]],
+    /// The environments the model is restricted to, empty when it applies everywhere.
+    pub env: &'static [&'static str],
+    /// The name of the database of the model in a `DatabaseRegistry`.
+    pub database: &'static str,
+    /// The partition key of the table on postgres, e.g. `range(at)`, empty when the table is
+    /// not partitioned.
+    pub partition_by: &'static str,
+    /// The columns filled from a sequence, with the name of the sequence.
+    pub sequences: &'static [(&'static str, &'static str)],
+}
+
+impl ModelSchema {
+    /// Captures the schema of a model.
+    pub fn of<M: Model + ?Sized>() -> Self {
+        Self {
+            name: M::NAME,
+            schema: M::SCHEMA,
+            unique_together: M::UNIQUE_TOGETHER,
+            env: M::ENV,
+            database: M::DATABASE,
+            partition_by: M::PARTITION_BY,
+            sequences: M::SEQUENCES,
+        }
+    }
+
+    /// Returns the columns declared in the `CREATE TABLE` statement.
+    pub fn columns(&self) -> Vec<ColumnDef> {
+        parse_columns(self.schema)
+    }
+
+    /// Returns the `CREATE TABLE` statement in the dialect of `backend`.
+    ///
+    /// On mysql, which cannot create an index only if it does not exist, the unique indexes are
+    /// declared in the table instead. On postgres, the partition key is added to the table.
+    pub fn create_table(&self, backend: Backend) -> String {
+        let sql = backend.create_table(self.schema);
+        if backend == Backend::Postgres && !self.partition_by.is_empty() {
+            let Some(end) = sql.rfind(')') else {
+                return sql;
+            };
+            return format!(
+                "{} partition by {}{}",
+                &sql[..=end],
+                self.partition_by,
+                &sql[end + 1..]
+            );
+        }
+        if backend != Backend::MySql || self.unique_together.is_empty() {
+            return sql;
+        }
+        let Some(end) = sql.rfind(')') else {
+            return sql;
+        };
+        let keys = self
+            .unique_together
+            .iter()
+            .map(|columns| {
+                format!(
+                    ", unique key {} ({})",
+                    unique_index_name(self.name, columns),
+                    columns.join(", ")
+                )
+            })
+            .collect::<String>();
+        format!("{}{keys}{}", &sql[..end], &sql[end..])
+    }
+
+    /// Returns the tables referenced by the foreign keys of the model, without duplicates.
+    ///
+    /// # Example
+    /// ```
+    /// // product (..., owner integer references user(id))
+    /// assert_eq!(ModelSchema::of::<Product>().references(), vec!["user"]);
+    /// ```
+    pub fn references(&self) -> Vec<String> {
+        let mut tables: Vec<String> = Vec::new();
+        for foreign_key in self.foreign_keys() {
+            if !tables.contains(&foreign_key.table) {
+                tables.push(foreign_key.table);
+            }
+        }
+        tables
+    }
+
+    /// Returns the foreign keys declared on the columns or as table constraints.
+    ///
+    /// Only the first column of a multi-column foreign key is returned.
+    ///
+    /// # Example
+    /// ```
+    /// // product (..., owner integer references user(id))
+    /// let foreign_key = &ModelSchema::of::<Product>().foreign_keys()[0];
+    /// assert_eq!((foreign_key.column.as_str(), foreign_key.table.as_str()), ("owner", "user"));
+    /// ```
+    pub fn foreign_keys(&self) -> Vec<ForeignKey> {
+        let (Some(start), Some(end)) = (self.schema.find('('), self.schema.rfind(')')) else {
+            return Vec::new();
+        };
+        if end <= start {
+            return Vec::new();
+        }
+        split_top_level(&self.schema[start + 1..end], ',')
+            .iter()
+            .filter_map(|definition| parse_foreign_key(definition))
+            .collect()
+    }
+
+    /// Returns the name of the partition holding the rows of a month, e.g. `event_2025_01`.
+    pub fn partition_name(&self, year: i32, month: u32) -> String {
+        format!("{}_{year:04}_{month:02}", self.name)
+    }
+
+    /// Returns the statements creating the partitions of `count` months, starting with the
+    /// given month, for a table with a `Model::PARTITION_BY` such as `"range(column)"`.
+    ///
+    /// The bounds are `YYYY-MM-01` literals, so the partition column can be a date, a
+    /// timestamp or the RFC 3339 text of `DateTime`.
+    ///
+    /// # Example
+    /// ```
+    /// // create table if not exists event_2025_01 partition of event
+    /// //     for values from ('2025-01-01') to ('2025-02-01');
+    /// let statements = ModelSchema::of::<Event>().monthly_partitions(2025, 1, 12);
+    /// ```
+    pub fn monthly_partitions(&self, year: i32, month: u32, count: u32) -> Vec<String> {
+        self.partitions(year, month, count)
+            .into_iter()
+            .map(|(_, sql)| sql)
+            .collect()
+    }
+
+    /// Returns the names of the monthly partitions with the statements creating them.
+    pub(crate) fn partitions(&self, year: i32, month: u32, count: u32) -> Vec<(String, String)> {
+        let next = |(year, month): (i32, u32)| match month {
+            12.. => (year + 1, 1),
+            _ => (year, month + 1),
+        };
+        let mut partitions = Vec::new();
+        let mut start = (year, month.clamp(1, 12));
+        for _ in 0..count {
+            let end = next(start);
+            let name = self.partition_name(start.0, start.1);
+            let sql = format!(
+                "create table if not exists {name} partition of {table} \
+                 for values from ('{:04}-{:02}-01') to ('{:04}-{:02}-01');",
+                start.0,
+                start.1,
+                end.0,
+                end.1,
+                table = self.name,
+            );
+            partitions.push((name, sql));
+            start = end;
+        }
+        partitions
+    }
+
+    /// Returns the statements creating the table, then its unique indexes and its sequences, in
+    /// the dialect of `backend`.
+    pub fn up(&self, backend: Backend) -> Vec<String> {
+        let mut statements = vec![self.create_table(backend)];
+        if backend != Backend::MySql {
+            statements.extend(self.unique_index_queries(backend));
+        }
+        for (_, sequence) in self.sequences {
+            statements.extend(super::sequence::create_statements(backend, sequence));
+        }
+        statements
+    }
+
+    /// Returns the statements creating the unique indexes of the model.
+    pub fn unique_index_queries(&self, backend: Backend) -> Vec<String> {
+        unique_index_queries(backend, self.name, self.unique_together)
+    }
+}
+
+/// A column declared in a `CREATE TABLE` statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnDef {
+    /// The column name, without quotes.
+    pub name: String,
+    /// The declared type, e.g. `varchar(255)`.
+    pub sql_type: String,
+    /// The full definition, e.g. `email varchar(255) not null`.
+    pub definition: String,
+}
+
+/// A foreign key declared in a `CREATE TABLE` statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignKey {
+    /// The referencing column.
+    pub column: String,
+    /// The referenced table.
+    pub table: String,
+    /// The referenced column, `id` when the statement leaves it out.
+    pub references: String,
+}
+
+/// A column of a live table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveColumn {
+    /// The column name.
+    pub name: String,
+    /// The type reported by the database.
+    pub data_type: String,
+    /// Whether the column accepts `NULL`.
+    pub nullable: bool,
+}
+
+/// A change needed to bring a table in line with its model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaChange {
+    /// The table does not exist.
+    CreateTable { table: String, sql: String },
+    /// A column of the model is missing from the table.
+    AddColumn { table: String, column: ColumnDef },
+    /// A unique index of the model is missing from the table.
+    CreateIndex {
+        table: String,
+        name: String,
+        sql: String,
+    },
+}
+
+impl fmt::Display for SchemaChange {
+    /// Renders the statement applying the change.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaChange::CreateTable { sql, .. } | SchemaChange::CreateIndex { sql, .. } => {
+                f.write_str(sql)
+            }
+            SchemaChange::AddColumn { table, column } => {
+                write!(f, "alter table {table} add column {};", column.definition)
+            }
+        }
+    }
+}
+
+/// Returns the changes needed to bring the table of a model in line with its schema.
+pub async fn diff(conn: &Connection, model: &ModelSchema) -> Result<Vec<SchemaChange>> {
+    let backend = Backend::of(conn);
+    let live = columns(conn, model.name).await?;
+    if live.is_empty() {
+        let mut changes = vec![SchemaChange::CreateTable {
+            table: model.name.to_string(),
+            sql: model.create_table(backend),
+        }];
+        // the unique indexes are part of the `create table` on mysql
+        if backend == Backend::MySql {
+            return Ok(changes);
+        }
+        for (name, sql) in unique_indexes(backend, model) {
+            changes.push(SchemaChange::CreateIndex {
+                table: model.name.to_string(),
+                name,
+                sql,
+            });
+        }
+        return Ok(changes);
+    }
+
+    let mut changes = Vec::new();
+    for column in parse_columns(&model.create_table(backend)) {
+        if !live
+            .iter()
+            .any(|c| c.name.eq_ignore_ascii_case(&column.name))
+        {
+            changes.push(SchemaChange::AddColumn {
+                table: model.name.to_string(),
+                column,
+            });
+        }
+    }
+    let indexes = indexes(conn, model.name).await?;
+    for (name, sql) in unique_indexes(backend, model) {
+        if !indexes
+            .iter()
+            .any(|index| index.eq_ignore_ascii_case(&name))
+        {
+            changes.push(SchemaChange::CreateIndex {
+                table: model.name.to_string(),
+                name,
+                sql,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// A difference between the schema of a model and its live table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaMismatch {
+    /// The table does not exist.
+    MissingTable { table: String },
+    /// A column of the model is missing from the table.
+    MissingColumn {
+        table: String,
+        column: String,
+        expected: String,
+    },
+    /// A column has a type incompatible with the declared one.
+    TypeMismatch {
+        table: String,
+        column: String,
+        expected: String,
+        found: String,
+    },
+    /// A unique index of the model is missing from the table.
+    MissingIndex { table: String, index: String },
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaMismatch::MissingTable { table } => write!(f, "{table}: missing table"),
+            SchemaMismatch::MissingColumn {
+                table,
+                column,
+                expected,
+            } => write!(f, "{table}.{column}: missing column of type {expected}"),
+            SchemaMismatch::TypeMismatch {
+                table,
+                column,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{table}.{column}: expected type {expected}, found {found}"
+            ),
+            SchemaMismatch::MissingIndex { table, index } => {
+                write!(f, "{table}: missing unique index {index}")
+            }
+        }
+    }
+}
+
+/// Compares the table of a model with its schema.
+///
+/// Column types are compared by family (integer, text, real, ...), since the backends report
+/// them differently from the way they are declared, e.g. `character varying` for `varchar(255)`.
+/// Types of an unknown family are not compared.
+pub async fn check(conn: &Connection, model: &ModelSchema) -> Result<Vec<SchemaMismatch>> {
+    let backend = Backend::of(conn);
+    let live = columns(conn, model.name).await?;
+    if live.is_empty() {
+        return Ok(vec![SchemaMismatch::MissingTable {
+            table: model.name.to_string(),
+        }]);
+    }
+
+    let mut mismatches = Vec::new();
+    for column in parse_columns(&model.create_table(backend)) {
+        match live
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(&column.name))
+        {
+            None => mismatches.push(SchemaMismatch::MissingColumn {
+                table: model.name.to_string(),
+                column: column.name,
+                expected: column.sql_type,
+            }),
+            Some(found) if !compatible_types(&column.sql_type, &found.data_type) => mismatches
+                .push(SchemaMismatch::TypeMismatch {
+                    table: model.name.to_string(),
+                    column: column.name,
+                    expected: column.sql_type,
+                    found: found.data_type.clone(),
+                }),
+            Some(_) => {}
+        }
+    }
+    let indexes = indexes(conn, model.name).await?;
+    for (name, _) in unique_indexes(backend, model) {
+        if !indexes
+            .iter()
+            .any(|index| index.eq_ignore_ascii_case(&name))
+        {
+            mismatches.push(SchemaMismatch::MissingIndex {
+                table: model.name.to_string(),
+                index: name,
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Returns the family of an SQL type, following the sqlite affinity rules.
+pub(crate) fn type_family(sql_type: &str) -> Option<&'static str> {
+    let sql_type = sql_type.to_lowercase();
+    let family = match sql_type.as_str() {
+        t if t.contains("interval") => return None,
+        t if t.contains("int") || t.contains("serial") => "integer",
+        t if t.contains("bool") => "boolean",
+        t if t.contains("char") || t.contains("clob") || t.contains("text") => "text",
+        t if t.contains("blob") || t.contains("bytea") || t.contains("binary") => "blob",
+        t if ["real", "floa", "doub", "decimal", "numeric"]
+            .iter()
+            .any(|family| t.contains(family)) =>
+        {
+            "real"
+        }
+        t if t.contains("date") || t.contains("time") => "datetime",
+        t if t.contains("json") => "json",
+        t if t.contains("uuid") => "uuid",
+        _ => return None,
+    };
+    Some(family)
+}
+
+fn compatible_types(declared: &str, live: &str) -> bool {
+    match (type_family(declared), type_family(live)) {
+        (Some(declared), Some(live)) => {
+            declared == live
+                // mysql reports booleans as tinyint, and sqlite stores uuids and json as text
+                || matches!(
+                    (declared, live),
+                    ("boolean", "integer") | ("uuid" | "json", "text")
+                )
+        }
+        _ => true,
+    }
+}
+
+/// Returns the columns of a live table, empty when the table does not exist.
+pub async fn columns(conn: &Connection, table: &str) -> Result<Vec<LiveColumn>> {
+    let backend = Backend::of(conn);
+    let placeholder = backend.placeholder(1);
+    let query = match backend {
+        Backend::Sqlite => format!(
+            "select name, type, \"notnull\" from pragma_table_xinfo({placeholder}) order by cid;"
+        ),
+        Backend::Postgres => format!(
+            "select column_name::text, data_type::text, is_nullable::text \
+             from information_schema.columns \
+             where table_name = {placeholder} and table_schema = current_schema() \
+             order by ordinal_position;"
+        ),
+        Backend::MySql => format!(
+            "select column_name, data_type, is_nullable \
+             from information_schema.columns \
+             where table_name = {placeholder} and table_schema = database() \
+             order by ordinal_position;"
+        ),
+    };
+    let rows = fetch(conn, &query, table).await?;
+    Ok(rows
+        .iter()
+        .map(|row| LiveColumn {
+            name: row.get(0),
+            data_type: row.get(1),
+            nullable: match backend {
+                Backend::Sqlite => row.get::<i64, _>(2) == 0,
+                Backend::Postgres | Backend::MySql => row.get::<String, _>(2) == "YES",
+            },
+        })
+        .collect())
+}
+
+/// Returns the index names of a live table.
+pub async fn indexes(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let placeholder = Backend::of(conn).placeholder(1);
+    let query = match Backend::of(conn) {
+        Backend::Sqlite => format!("select name from pragma_index_list({placeholder});"),
+        Backend::Postgres => format!(
+            "select indexname::text from pg_indexes \
+             where tablename = {placeholder} and schemaname = current_schema();"
+        ),
+        Backend::MySql => format!(
+            "select distinct index_name from information_schema.statistics \
+             where table_name = {placeholder} and table_schema = database();"
+        ),
+    };
+    let rows = fetch(conn, &query, table).await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+pub(crate) async fn fetch(
+    conn: &Connection,
+    query: &str,
+    table: &str,
+) -> Result<Vec<sqlx::any::AnyRow>> {
+    let args = vec![Arg::from(table)];
+    let mut stream = sqlx::query(query);
+    binds!(args.clone(), stream);
+    let rows = instrument(
+        &QuerySettings::of(conn),
+        "schema",
+        "introspect",
+        query,
+        stream.fetch_all(conn),
+    )
+    .await
+    .map_err(|err| Error::new(&QuerySettings::of(conn), query, args, err))?;
+    Ok(rows)
+}
+
+/// Returns the statements creating the indexes of `UNIQUE_TOGETHER` column sets.
+pub(crate) fn unique_index_queries(
+    backend: Backend,
+    table: &str,
+    unique_together: &[&[&str]],
+) -> Vec<String> {
+    unique_together
+        .iter()
+        .map(|columns| {
+            backend.create_unique_index(table, &unique_index_name(table, columns), columns)
+        })
+        .collect()
+}
+
+fn unique_index_name(table: &str, columns: &[&str]) -> String {
+    format!("{table}_{}_key", columns.join("_"))
+}
+
+fn unique_indexes(backend: Backend, model: &ModelSchema) -> Vec<(String, String)> {
+    model
+        .unique_together
+        .iter()
+        .map(|columns| unique_index_name(model.name, columns))
+        .zip(model.unique_index_queries(backend))
+        .collect()
+}
+
+/// Parses the column definitions of a `CREATE TABLE` statement, skipping table constraints.
+///
+/// # Example
+/// ```
+/// let columns = parse_columns("create table user (id integer primary key, name text not null);");
+/// assert_eq!(columns[1].definition, "name text not null");
+/// ```
+pub fn parse_columns(schema: &str) -> Vec<ColumnDef> {
+    let (Some(start), Some(end)) = (schema.find('('), schema.rfind(')')) else {
+        return Vec::new();
+    };
+    if end <= start {
+        return Vec::new();
+    }
+    split_top_level(&schema[start + 1..end], ',')
+        .into_iter()
+        .filter_map(|definition| {
+            let definition = definition.split_whitespace().collect::<Vec<_>>().join(" ");
+            let mut tokens = split_top_level(&definition, ' ').into_iter();
+            let name = tokens.next()?;
+            let keyword = name.to_lowercase();
+            if [
+                "primary",
+                "foreign",
+                "unique",
+                "check",
+                "constraint",
+                "key",
+                "index",
+            ]
+            .contains(&keyword.as_str())
+            {
+                return None;
+            }
+            Some(ColumnDef {
+                name: name.trim_matches(['"', '`', '[', ']']).to_string(),
+                sql_type: tokens.next().unwrap_or_default(),
+                definition,
+            })
+        })
+        .collect()
+}
+
+/// Parses the foreign key of a column definition (`owner integer references user(id)`) or of a
+/// table constraint (`foreign key (owner) references user(id)`).
+fn parse_foreign_key(definition: &str) -> Option<ForeignKey> {
+    let tokens = definition
+        .split(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | ','))
+        .filter(|token| !token.is_empty())
+        .map(|token| token.trim_matches(['"', '`', '[', ']']))
+        .collect::<Vec<_>>();
+    let at = tokens
+        .iter()
+        .position(|token| token.eq_ignore_ascii_case("references"))?;
+    let column = match tokens
+        .iter()
+        .position(|token| token.eq_ignore_ascii_case("foreign"))
+    {
+        // foreign key (column) references ...
+        Some(foreign) if foreign < at => tokens.get(foreign + 2)?,
+        _ => tokens.first()?,
+    };
+    let table = tokens.get(at + 1)?;
+    let references = match tokens.get(at + 2) {
+        Some(token)
+            if !["on", "match", "not", "deferrable", "initially"]
+                .iter()
+                .any(|keyword| token.eq_ignore_ascii_case(keyword)) =>
+        {
+            token
+        }
+        _ => "id",
+    };
+    Some(ForeignKey {
+        column: column.to_string(),
+        table: table.to_string(),
+        references: references.to_string(),
+    })
+}
+
+/// Splits on `separator` outside of parentheses and quotes.
+fn split_top_level(text: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    for c in text.chars() {
+        match (c, quote) {
+            ('\'' | '"' | '`', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('(', None) => depth += 1,
+            (')', None) => depth -= 1,
+            (c, None) if c == separator && depth == 0 => {
+                if !current.trim().is_empty() {
+                    parts.push(current.trim().to_string());
+                }
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
@@ -0,0 +1,334 @@
+//! Rendering of the builder statements with `sea-query`.
+//!
+//! The statements of `SelectBuilder`, `DeleteBuilder` and `UpdateBuilder` are built as a
+//! `sea-query` AST from the same `kwargs!` conditions, then rendered by the query builder of
+//! the backend, so the placeholders, operators, limits and row locks follow its dialect. It is
+//! only available with the `sea-query` feature.
+
+use std::fmt::Write;
+
+use sea_query::{
+    BinOper, Condition as Cond, Expr, Iden, LikeExpr, LockBehavior, LockType, MysqlQueryBuilder,
+    PostgresQueryBuilder, Query, QueryStatementBuilder, Quote, SimpleExpr, SqliteQueryBuilder,
+    Value,
+};
+
+use super::backend::Backend;
+use super::builder::Rows;
+use super::models::{prefix_pattern, similar_pattern, Arg, Condition, Model};
+
+/// A table or column name, written as the models declare it.
+///
+/// The names are left unquoted, as in the `create table` statements of the derive, so they
+/// resolve to the same tables and columns as the statements written by hand.
+struct Name(String);
+
+impl Iden for Name {
+    fn prepare(&self, s: &mut dyn Write, _quote: Quote) {
+        self.unquoted(s);
+    }
+
+    fn unquoted(&self, s: &mut dyn Write) {
+        s.write_str(&self.0).unwrap();
+    }
+}
+
+impl Name {
+    fn new(name: &str) -> Self {
+        Name(name.to_string())
+    }
+}
+
+/// Converts an argument to the value bound by `sea-query`.
+fn value(arg: &Arg) -> Value {
+    match arg {
+        Arg::Null => Value::String(None),
+        Arg::Bool(b) => (*b).into(),
+        Arg::Int(n) => (*n).into(),
+        Arg::Float(n) => (*n).into(),
+        Arg::Text(s) => s.clone().into(),
+        Arg::Bytes(bytes) => bytes.clone().into(),
+    }
+}
+
+/// Converts a value bound by `sea-query` back to an argument, `NULL` for the values of
+/// any type.
+fn arg(value: Value) -> Arg {
+    // the other variants belong to features of sea-query never built from an `Arg`
+    #[allow(unreachable_patterns)]
+    match value {
+        Value::Bool(b) => b.into(),
+        Value::TinyInt(n) => n.into(),
+        Value::SmallInt(n) => n.into(),
+        Value::Int(n) => n.into(),
+        Value::BigInt(n) => n.into(),
+        Value::TinyUnsigned(n) => n.into(),
+        Value::SmallUnsigned(n) => n.into(),
+        Value::Unsigned(n) => n.into(),
+        Value::BigUnsigned(n) => n.into(),
+        Value::Float(n) => n.into(),
+        Value::Double(n) => n.into(),
+        Value::String(s) => s.map(|s| *s).into(),
+        Value::Char(c) => c.into(),
+        Value::Bytes(bytes) => bytes.map(|bytes| *bytes).into(),
+        _ => Arg::Null,
+    }
+}
+
+/// Renders a statement with the query builder of the backend.
+fn build(backend: Backend, statement: &impl QueryStatementBuilder) -> (String, Vec<Arg>) {
+    let (query, values) = match backend {
+        Backend::Sqlite => statement.build_any(&SqliteQueryBuilder),
+        Backend::Postgres => statement.build_any(&PostgresQueryBuilder),
+        Backend::MySql => statement.build_any(&MysqlQueryBuilder),
+    };
+    (query, values.0.into_iter().map(arg).collect())
+}
+
+/// Returns the operator of `sea-query` for a comparison operator, `None` for the ones it does
+/// not know, which are rendered as written.
+fn bin_oper(operator: &str) -> Option<BinOper> {
+    Some(match operator.to_ascii_lowercase().as_str() {
+        "=" | "==" => BinOper::Equal,
+        "!=" | "<>" => BinOper::NotEqual,
+        "<" => BinOper::SmallerThan,
+        "<=" => BinOper::SmallerThanOrEqual,
+        ">" => BinOper::GreaterThan,
+        ">=" => BinOper::GreaterThanOrEqual,
+        "like" => BinOper::Like,
+        "not like" => BinOper::NotLike,
+        _ => return None,
+    })
+}
+
+/// Builds the comparison of a `FieldCondition`.
+///
+/// The field is kept as written, since it can be an expression, e.g. from `json_extract`.
+fn compare(backend: Backend, field: &str, operator: &str, arg: &Arg) -> SimpleExpr {
+    let left = Expr::expr(Expr::cust(field));
+    let operator = operator.trim();
+    match (operator, arg) {
+        // `= NULL` never matches in SQL, so `None` values are compared with `is null`
+        ("=" | "==", Arg::Null) => left.is_null(),
+        ("!=", Arg::Null) => left.is_not_null(),
+        // the `pg_trgm` similarity on postgres, a `like` anywhere in the column elsewhere
+        ("similar_to", _) => match (backend, arg) {
+            (Backend::Postgres, _) => left.binary(BinOper::Custom("%"), value(arg)),
+            (_, Arg::Text(text)) => left.like(LikeExpr::new(similar_pattern(text)).escape('!')),
+            _ => left.binary(BinOper::Like, value(arg)),
+        },
+        ("starts_with", Arg::Text(text)) => {
+            left.like(LikeExpr::new(prefix_pattern(text)).escape('!'))
+        }
+        ("starts_with", _) => left.binary(BinOper::Like, value(arg)),
+        _ => match bin_oper(operator) {
+            Some(operator) => left.binary(operator, value(arg)),
+            None => {
+                let template = match backend {
+                    Backend::Postgres => format!("$1 {operator} $2"),
+                    _ => format!("? {operator} ?"),
+                };
+                Expr::cust_with_exprs(template, [Expr::cust(field), SimpleExpr::Value(value(arg))])
+            }
+        },
+    }
+}
+
+/// Builds the condition of a list of `kwargs!` conditions, where `and` binds tighter than
+/// `or` as in SQL, and the conditions without an operator between them are and'ed.
+fn filter(backend: Backend, kw: &[Condition]) -> Cond {
+    let mut any = Cond::any();
+    let mut all = Cond::all();
+    for condition in kw {
+        match condition {
+            Condition::FieldCondition {
+                field,
+                value,
+                comparison_operator,
+            } => all = all.add(compare(backend, field, comparison_operator, value)),
+            Condition::ColumnCondition {
+                left,
+                right,
+                comparison_operator,
+            } => all = all.add(Expr::cust(format!("{left}{comparison_operator}{right}"))),
+            Condition::Group(conditions) => all = all.add(filter(backend, conditions)),
+            Condition::ByBackend {
+                sqlite,
+                postgres,
+                mysql,
+            } => {
+                let conditions = match backend {
+                    Backend::Sqlite => sqlite,
+                    Backend::Postgres => postgres,
+                    Backend::MySql => mysql,
+                };
+                all = all.add(filter(backend, conditions));
+            }
+            Condition::LogicalOperator { operator } if operator.trim() == "or" => {
+                any = any.add(std::mem::replace(&mut all, Cond::all()));
+            }
+            Condition::LogicalOperator { .. } => {}
+        }
+    }
+    if any.is_empty() {
+        all
+    } else {
+        any.add(all)
+    }
+}
+
+/// Builds the condition of the rows of `M` matching `kw`, restricted to `M::DEFAULT_SCOPE`
+/// when `scoped` is set, `None` if every row matches.
+fn where_clause<M: Model>(backend: Backend, kw: &[Condition], scoped: bool) -> Option<Cond> {
+    let scope = M::DEFAULT_SCOPE.trim();
+    let scope = (scoped && !scope.is_empty()).then(|| Expr::cust(scope));
+    match (scope, kw.is_empty()) {
+        (None, true) => None,
+        (None, false) => Some(filter(backend, kw)),
+        (Some(scope), true) => Some(Cond::all().add(scope)),
+        (Some(scope), false) => Some(Cond::all().add(scope).add(filter(backend, kw))),
+    }
+}
+
+/// Builds the condition of the rows of a write, and the limit to put on the statement.
+///
+/// Backends without `LIMIT` on writes get an id subquery instead:
+/// `where pk in (select pk from table where ... limit n)`.
+fn write_filter<M: Model>(backend: Backend, rows: Rows) -> (Option<Cond>, Option<u64>) {
+    match rows {
+        Rows::Keys(keys) => {
+            let keys = Expr::col(Name::new(M::PK)).is_in(keys.iter().map(value));
+            (Some(Cond::all().add(keys)), None)
+        }
+        Rows::Matching(kw, None) => (where_clause::<M>(backend, kw, false), None),
+        Rows::Matching(kw, Some(limit)) if backend.capabilities().write_limit => {
+            (where_clause::<M>(backend, kw, false), Some(limit as u64))
+        }
+        Rows::Matching(kw, Some(limit)) => {
+            let mut ids = Query::select();
+            ids.column(Name::new(M::PK))
+                .from(Name::new(M::NAME))
+                .limit(limit as u64);
+            if let Some(condition) = where_clause::<M>(backend, kw, false) {
+                ids.cond_where(condition);
+            }
+            let ids = Expr::col(Name::new(M::PK)).in_subquery(ids);
+            (Some(Cond::all().add(ids)), None)
+        }
+    }
+}
+
+/// Renders the `select` of `columns` of the rows of `M` matching `kw`, without the final `;`,
+/// restricted to `M::DEFAULT_SCOPE` when `scoped` is set.
+///
+/// The row locks are left out on the backends without them.
+pub(crate) fn select_statement<M: Model>(
+    backend: Backend,
+    columns: &str,
+    kw: &[Condition],
+    scoped: bool,
+    limit: Option<usize>,
+    lock: Option<&'static str>,
+    skip_locked: bool,
+) -> (String, Vec<Arg>) {
+    let capabilities = backend.capabilities();
+    let mut select = Query::select();
+    select.expr(Expr::cust(columns)).from(Name::new(M::NAME));
+    if let Some(condition) = where_clause::<M>(backend, kw, scoped) {
+        select.cond_where(condition);
+    }
+    if let Some(limit) = limit {
+        select.limit(limit as u64);
+    }
+    if let (Some(lock), true) = (lock, capabilities.for_update) {
+        let lock = match lock {
+            "for share" => LockType::Share,
+            _ => LockType::Update,
+        };
+        if skip_locked && capabilities.skip_locked {
+            select.lock_with_behavior(lock, LockBehavior::SkipLocked);
+        } else {
+            select.lock(lock);
+        }
+    }
+    build(backend, &select)
+}
+
+/// Renders the `delete` of the rows of `M`, without the final `;`.
+pub(crate) fn delete_statement<M: Model>(backend: Backend, rows: Rows) -> (String, Vec<Arg>) {
+    let mut delete = Query::delete();
+    delete.from_table(Name::new(M::NAME));
+    let (condition, limit) = write_filter::<M>(backend, rows);
+    if let Some(condition) = condition {
+        delete.cond_where(condition);
+    }
+    if let Some(limit) = limit {
+        delete.limit(limit);
+    }
+    build(backend, &delete)
+}
+
+/// Renders the `update` of the rows of `M` to `values`, without the final `;`.
+pub(crate) fn update_statement<M: Model>(
+    backend: Backend,
+    values: &[Condition],
+    rows: Rows,
+) -> (String, Vec<Arg>) {
+    let mut update = Query::update();
+    update
+        .table(Name::new(M::NAME))
+        .values(values.iter().filter_map(|condition| match condition {
+            Condition::FieldCondition {
+                field, value: v, ..
+            } => Some((Name::new(field), SimpleExpr::Value(value(v)))),
+            _ => None,
+        }));
+    let (condition, limit) = write_filter::<M>(backend, rows);
+    if let Some(condition) = condition {
+        update.cond_where(condition);
+    }
+    if let Some(limit) = limit {
+        update.limit(limit);
+    }
+    build(backend, &update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::{select_columns_query, Or};
+    use crate::db::testing::User;
+
+    fn field(field: &str, comparison_operator: &str, value: impl Into<Arg>) -> Vec<Condition> {
+        vec![Condition::field(field, comparison_operator, value)]
+    }
+
+    fn select(kw: &[Condition]) -> String {
+        select_statement::<User>(Backend::Sqlite, "id", kw, false, None, None, false).0
+    }
+
+    #[test]
+    fn escapes_the_starts_with_pattern() {
+        assert_eq!(
+            select(&field("addr", "starts_with", "10_")),
+            "SELECT id FROM user WHERE (addr) LIKE ? ESCAPE '!'"
+        );
+    }
+
+    #[test]
+    fn ands_the_adjacent_conditions_like_the_default_rendering() {
+        let kw = [field("age", "=", 10), field("name", "=", "z")].concat();
+        assert_eq!(
+            select(&kw),
+            "SELECT id FROM user WHERE (age) = ? AND (name) = ?"
+        );
+        let (default, _) = select_columns_query::<User>(Backend::Sqlite, "id", &kw, false);
+        assert_eq!(default, "select id from user where age=?1 and name=?2");
+
+        let kw = kw.or(field("age", "=", 19));
+        assert_eq!(
+            select(&kw),
+            "SELECT id FROM user WHERE ((age) = ? AND (name) = ?) OR (age) = ?"
+        );
+    }
+}
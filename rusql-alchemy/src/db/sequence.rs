@@ -0,0 +1,133 @@
+//! Sequences, pulled by `Database::next_sequence_value` and by `Model::create` for the
+//! `Model::SEQUENCES` columns.
+//!
+//! Postgres has native sequences. Sqlite and mysql have none, a sequence is a row of the
+//! `_rusql_sequences` table there, incremented in a single statement so concurrent callers
+//! never get the same value:
+//!
+//! - `name`, the name of the sequence
+//! - `value`, the last value returned, `0` before the first one
+
+use anyhow::Result;
+use sqlx::Row;
+
+use super::backend::Backend;
+use super::models::Arg;
+use super::{instrument, QuerySettings};
+use crate::{Connection, Error};
+
+/// The table of the sequences on sqlite and mysql.
+pub const SEQUENCE_TABLE: &str = "_rusql_sequences";
+
+/// Returns the statements creating the sequence `name` if it does not exist, starting at 1.
+pub(crate) fn create_statements(backend: Backend, name: &str) -> Vec<String> {
+    let literal = name.replace('\'', "''");
+    match backend {
+        Backend::Postgres => vec![format!("create sequence if not exists {name};")],
+        Backend::Sqlite | Backend::MySql => {
+            let insert = if backend == Backend::MySql {
+                "insert ignore into"
+            } else {
+                "insert or ignore into"
+            };
+            vec![
+                format!(
+                    "create table if not exists {SEQUENCE_TABLE} (
+                        name varchar(255) primary key,
+                        value bigint not null
+                    );"
+                ),
+                format!("{insert} {SEQUENCE_TABLE} (name, value) values ('{literal}', 0);"),
+            ]
+        }
+    }
+}
+
+/// Creates the sequence `name` if it does not exist.
+pub(crate) async fn create(conn: &Connection, name: &str) -> Result<()> {
+    for query in create_statements(Backend::of(conn), name) {
+        instrument(
+            &QuerySettings::of(conn),
+            SEQUENCE_TABLE,
+            "create_sequence",
+            &query,
+            sqlx::query(&query).execute(conn),
+        )
+        .await
+        .map_err(|err| Error::new(&QuerySettings::of(conn), &query, Vec::new(), err))?;
+    }
+    Ok(())
+}
+
+/// Returns the next value of the sequence `name`.
+///
+/// # Errors
+/// Returns an error if the sequence was not created or the statement fails.
+pub(crate) async fn next_value(conn: &Connection, name: &str) -> Result<i64> {
+    let args = vec![Arg::from(name)];
+    match Backend::of(conn) {
+        Backend::Postgres => {
+            let query = "select nextval($1);";
+            let row = instrument(
+                &QuerySettings::of(conn),
+                SEQUENCE_TABLE,
+                "next_sequence_value",
+                query,
+                sqlx::query(query).bind(name).fetch_one(conn),
+            )
+            .await
+            .map_err(|err| Error::new(&QuerySettings::of(conn), query, args, err))?;
+            Ok(row.try_get(0)?)
+        }
+        Backend::Sqlite => {
+            let query = format!(
+                "update {SEQUENCE_TABLE} set value = value + 1 where name = ?1 returning value;"
+            );
+            let row = instrument(
+                &QuerySettings::of(conn),
+                SEQUENCE_TABLE,
+                "next_sequence_value",
+                &query,
+                sqlx::query(&query).bind(name).fetch_optional(conn),
+            )
+            .await
+            .map_err(|err| Error::new(&QuerySettings::of(conn), &query, args, err))?;
+            match row {
+                Some(row) => Ok(row.try_get(0)?),
+                None => anyhow::bail!("sequence `{name}` does not exist"),
+            }
+        }
+        Backend::MySql => {
+            // `last_insert_id(expr)` remembers the incremented value for the connection, so both
+            // statements must run on the same one
+            let settings = QuerySettings::of(conn);
+            let mut conn = conn.acquire().await?;
+            let query = format!(
+                "update {SEQUENCE_TABLE} set value = last_insert_id(value + 1) where name = ?;"
+            );
+            let result = instrument(
+                &settings,
+                SEQUENCE_TABLE,
+                "next_sequence_value",
+                &query,
+                sqlx::query(&query).bind(name).execute(&mut *conn),
+            )
+            .await
+            .map_err(|err| Error::new(&settings, &query, args, err))?;
+            if result.rows_affected() == 0 {
+                anyhow::bail!("sequence `{name}` does not exist");
+            }
+            let query = "select cast(last_insert_id() as signed);";
+            let row = instrument(
+                &settings,
+                SEQUENCE_TABLE,
+                "next_sequence_value",
+                query,
+                sqlx::query(query).fetch_one(&mut *conn),
+            )
+            .await
+            .map_err(|err| Error::new(&settings, query, Vec::new(), err))?;
+            Ok(row.try_get(0)?)
+        }
+    }
+}
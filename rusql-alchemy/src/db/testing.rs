@@ -0,0 +1,120 @@
+//! Models written by hand the way the derive writes them, to test the `Model` methods against
+//! an in-memory sqlite database.
+
+use serde::{Deserialize, Serialize};
+use sqlx::any::AnyPoolOptions;
+use sqlx::FromRow;
+
+use super::encryption::Cipher;
+use super::models::{insert_values, Condition, Model};
+use crate::Connection;
+
+/// Returns a pool on a new in-memory sqlite database, kept on a single connection since every
+/// in-memory connection has its own database.
+pub(crate) async fn memory() -> Connection {
+    sqlx::any::install_default_drivers();
+    AnyPoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
+}
+
+/// Returns the conditions setting every column of an instance but its primary key.
+fn update_values<M: Model + Serialize>(instance: &M) -> Vec<Condition> {
+    let Some((columns, values)) = insert_values(instance) else {
+        return Vec::new();
+    };
+    columns
+        .into_iter()
+        .zip(values)
+        .filter(|(column, _)| column != M::PK)
+        .map(|(column, value)| Condition::field(column, "=", value))
+        .collect()
+}
+
+macro_rules! test_model {
+    ($name:ident, $table:literal $(, $constant:ident: $ty:ty = $value:expr)*) => {
+        #[derive(Debug, Clone, Default, PartialEq, FromRow, Serialize, Deserialize)]
+        pub(crate) struct $name {
+            pub id: i64,
+            pub name: String,
+            pub age: i64,
+        }
+
+        #[async_trait::async_trait]
+        impl Model for $name {
+            const SCHEMA: &'static str = concat!(
+                "create table if not exists ",
+                $table,
+                " (id integer primary key autoincrement, name varchar(50) not null, \
+                 age integer not null);"
+            );
+            const NAME: &'static str = $table;
+            const PK: &'static str = "id";
+            $(const $constant: $ty = $value;)*
+
+            async fn save(&self, conn: &Connection) -> bool {
+                let Some((columns, values)) = insert_values(self) else {
+                    return false;
+                };
+                let kw = columns
+                    .into_iter()
+                    .zip(values)
+                    .map(|(column, value)| Condition::field(column, "=", value))
+                    .collect();
+                Self::create(kw, conn).await
+            }
+
+            async fn update(&self, conn: &Connection) -> bool {
+                Self::set(self.id, update_values(self), conn).await
+            }
+
+            async fn delete(&self, conn: &Connection) -> bool {
+                Self::delete_where(vec![Condition::field("id", "=", self.id)])
+                    .execute(conn)
+                    .await
+                    > 0
+            }
+        }
+    };
+}
+
+test_model!(User, "user");
+test_model!(AuditedUser, "audited_user", AUDITED: bool = true);
+test_model!(
+    EncryptedUser,
+    "encrypted_user",
+    ENCRYPTED: &'static [&'static str] = &["name"]
+);
+
+/// A cipher storing the values reversed behind an `enc:` prefix, so the tests can read them.
+pub(crate) struct TestCipher;
+
+impl Cipher for TestCipher {
+    fn encrypt(&self, plaintext: &str) -> anyhow::Result<String> {
+        Ok(format!(
+            "enc:{}",
+            plaintext.chars().rev().collect::<String>()
+        ))
+    }
+
+    fn decrypt(&self, ciphertext: &str) -> anyhow::Result<String> {
+        let reversed = ciphertext
+            .strip_prefix("enc:")
+            .ok_or_else(|| anyhow::anyhow!("the value is not encrypted"))?;
+        Ok(reversed.chars().rev().collect())
+    }
+}
+
+impl User {
+    pub(crate) fn new(name: &str, age: i64) -> Self {
+        Self {
+            name: name.to_string(),
+            age,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::db::decode::DecodeError;
+use crate::db::models::Arg;
+use crate::db::{QuerySettings, StatementTimeout};
+
+/// The error returned when a statement fails.
+///
+/// `Database::execute`, `Database::fetch` and the other methods returning an `anyhow::Result`
+/// return it inside their `anyhow::Error`, so it can be recovered with `downcast_ref`.
+///
+/// # Example
+/// ```rust
+/// if let Err(err) = db.execute("update user set role = ?", args!("admin")).await {
+///     match err.downcast_ref::<Error>() {
+///         Some(Error::Timeout { timeout, .. }) => eprintln!("gave up after {timeout:?}"),
+///         Some(Error::Query(err)) => eprintln!("{} failed with {:?}", err.sql, err.params),
+///         None => {}
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub enum Error {
+    /// The statement failed on the database.
+    Query(QueryError),
+    /// The statement was abandoned after the statement timeout, see
+    /// `Database::set_statement_timeout`.
+    Timeout {
+        /// The statement as sent to the database.
+        sql: String,
+        /// The timeout it ran into.
+        timeout: Duration,
+    },
+}
+
+impl Error {
+    /// Whether the statement failed on a conflict with another transaction and can succeed
+    /// when retried, see `Database::transaction_with_retry`.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Query(err) if err.is_retryable())
+    }
+
+    /// The row that could not be decoded into the model, when decoding failed.
+    pub fn decode_error(&self) -> Option<&DecodeError> {
+        match self {
+            Self::Query(err) => err.decode_error(),
+            Self::Timeout { .. } => None,
+        }
+    }
+
+    /// Wraps the error of a statement run on a pool with `settings`, whose parameters are
+    /// redacted when the pool redacts them, see `DatabaseConfig::redact_params`.
+    pub(crate) fn new(
+        settings: &QuerySettings,
+        sql: &str,
+        params: Vec<Arg>,
+        source: sqlx::Error,
+    ) -> Self {
+        if let sqlx::Error::Io(err) = &source {
+            let timeout = err
+                .get_ref()
+                .and_then(|err| err.downcast_ref::<StatementTimeout>());
+            if let Some(StatementTimeout(timeout)) = timeout {
+                return Self::Timeout {
+                    sql: sql.to_string(),
+                    timeout: *timeout,
+                };
+            }
+        }
+        Self::Query(QueryError::new(
+            sql,
+            params,
+            settings.redact_params(),
+            source,
+        ))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Query(err) => err.fmt(f),
+            Self::Timeout { sql, timeout } => {
+                write!(f, "statement timed out after {timeout:?}\n  query: {sql}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Query(err) => err.source(),
+            Self::Timeout { .. } => None,
+        }
+    }
+}
+
+/// A statement that failed on the database, with the SQL text and the bound parameters that
+/// were sent to it, see `Error::Query`.
+#[derive(Debug)]
+pub struct QueryError {
+    /// The statement as sent to the database.
+    pub sql: String,
+    /// The bound parameters, empty when they are redacted.
+    pub params: Vec<Arg>,
+    /// Whether the parameters were redacted, see `DatabaseConfig::redact_params`.
+    pub redacted: bool,
+    /// The error reported by the driver.
+    pub source: sqlx::Error,
+}
+
+impl QueryError {
+    /// Whether the statement failed on a conflict with another transaction and can succeed
+    /// when retried, see `Database::transaction_with_retry`.
+    pub fn is_retryable(&self) -> bool {
+        is_retryable(&self.source)
+    }
+
+    /// The row that could not be decoded into the model, when decoding failed.
+    pub fn decode_error(&self) -> Option<&DecodeError> {
+        match &self.source {
+            sqlx::Error::Decode(err) => err.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn new(sql: &str, params: Vec<Arg>, redacted: bool, source: sqlx::Error) -> Self {
+        Self {
+            sql: sql.to_string(),
+            params: if redacted { Vec::new() } else { params },
+            redacted,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n  query: {}", self.source, self.sql)?;
+        if self.redacted {
+            write!(f, "\n  params: <redacted>")
+        } else {
+            let params = self
+                .params
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(f, "\n  params: [{params}]")
+        }
+    }
+}
+
+impl std::error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Whether an error is a serialization failure or a deadlock (postgres and mysql), or a busy
+/// or locked database (sqlite).
+pub(crate) fn is_retryable(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(err) => matches!(
+            err.code().as_deref(),
+            Some("40001" | "40P01" | "5" | "6" | "261" | "262" | "517" | "773")
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DatabaseConfig;
+
+    #[test]
+    fn redacts_the_params_when_the_pool_does() {
+        let args = || vec![Arg::from("s3cret")];
+        let settings = QuerySettings::from_config(&DatabaseConfig::new().redact_params(true));
+        let err = Error::new(&settings, "select ?1;", args(), sqlx::Error::RowNotFound);
+        assert!(!err.to_string().contains("s3cret"), "{err}");
+        assert!(matches!(
+            err,
+            Error::Query(QueryError { redacted: true, .. })
+        ));
+
+        let err = Error::new(
+            &QuerySettings::default(),
+            "select ?1;",
+            args(),
+            sqlx::Error::RowNotFound,
+        );
+        assert!(err.to_string().contains("params: ['s3cret']"), "{err}");
+    }
+}
@@ -0,0 +1,81 @@
+//! Events fired after the writes of the models, so caches and search indexes can follow the
+//! changes without wrapping every call site.
+//!
+//! The events are fired once the write succeeded, after the commit when it runs in a
+//! transaction, by `create`, `create_returning_pk`, `save_and_refresh`, `save_or_update`, `set`,
+//! `update_returning` and `delete_cascade`. The derived `save`, `update` and `delete` do not fire
+//! events unless `emit` is called after them. The writes of `update_where`, `delete_where`,
+//! `bulk_upsert`, `WriteBatch`, `import_csv` and `copy_in` do not fire events either.
+//!
+//! # Example
+//! ```rust
+//! rusql_alchemy::events::subscribe::<User_>(|event| {
+//!     if let Event::Deleted { pk } = event {
+//!         cache.invalidate(pk);
+//!     }
+//! });
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+
+use crate::db::models::{Arg, Model};
+
+/// A write of a model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A row was inserted, with the given values. The primary key is `None` when it was
+    /// generated by the database and the driver does not report it.
+    Created {
+        pk: Option<Arg>,
+        values: serde_json::Value,
+    },
+    /// The row with the primary key `pk` was updated with the given values.
+    Updated { pk: Arg, values: serde_json::Value },
+    /// The row with the primary key `pk` was deleted.
+    Deleted { pk: Arg },
+}
+
+/// A function receiving the events of a model.
+pub type Listener = Arc<dyn Fn(&Event) + Send + Sync>;
+
+lazy_static! {
+    static ref LISTENERS: RwLock<HashMap<&'static str, Vec<Listener>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Calls `listener` after every write of the model `M`.
+///
+/// The listeners run on the task of the write, in the order they were subscribed, and should
+/// hand long work over to another task.
+pub fn subscribe<M: Model + ?Sized>(listener: impl Fn(&Event) + Send + Sync + 'static) {
+    LISTENERS
+        .write()
+        .unwrap()
+        .entry(M::NAME)
+        .or_default()
+        .push(Arc::new(listener));
+}
+
+/// Removes every listener of the model `M`.
+pub fn unsubscribe_all<M: Model + ?Sized>() {
+    LISTENERS.write().unwrap().remove(M::NAME);
+}
+
+/// Fires the event built by `event` to the listeners of `M`, the event is only built when the
+/// model has listeners.
+///
+/// The `Model` methods fire their events, custom writes can call it to notify the listeners too.
+pub fn emit<M: Model + ?Sized>(event: impl FnOnce() -> Event) {
+    let listeners = match LISTENERS.read().unwrap().get(M::NAME) {
+        Some(listeners) if !listeners.is_empty() => listeners.clone(),
+        _ => return,
+    };
+    // the lock is released so the listeners can subscribe
+    let event = event();
+    for listener in listeners {
+        listener(&event);
+    }
+}
@@ -11,10 +11,57 @@ pub mod prelude;
 /// This module contains the custom types used in the crate.
 pub mod types;
 
+/// This module contains the `rusql` command line managing migrations.
+pub mod cli;
+
+/// This module contains the events fired after the writes of the models.
+pub mod events;
+
+/// This module contains the job queue stored in the `_rusql_jobs` table.
+#[cfg(feature = "queue")]
+pub mod queue;
+
+/// This module contains the `tower-sessions` store backed by the `_rusql_sessions` table.
+#[cfg(feature = "sessions")]
+pub mod sessions;
+
+/// This module contains the configuration used to open a `Database`.
+mod config;
+
+pub use config::{DatabaseConfig, RetryPolicy, SqlitePragmas};
+
+/// This module contains the registry of named databases.
+mod registry;
+
+pub use registry::DatabaseRegistry;
+
+/// This module contains the transactions opened by `Database::transaction`.
+mod transaction;
+
+pub use transaction::{IsolationLevel, Transaction, TransactionFuture};
+
+/// This module contains the postgres `LISTEN`/`NOTIFY` listener.
+#[cfg(feature = "postgres")]
+mod listen;
+
+#[cfg(feature = "postgres")]
+pub use listen::{Listener, Notification};
+
+/// This module contains the errors returned by the crate.
+mod error;
+
+pub use error::{Error, QueryError};
+
 /// The placeholder for the database query.
+#[allow(deprecated)]
 pub use db::models::PLACEHOLDER;
 pub use utils::*;
 
+#[doc(hidden)]
+pub use serde_json;
+#[doc(hidden)]
+pub use sqlx;
+
 use anyhow::Result;
 
 mod utils;
@@ -22,21 +69,69 @@ mod utils;
 /// Alias for the database connection pool.
 pub type Connection = sqlx::Pool<sqlx::Any>;
 
-use sqlx::any::{install_default_drivers, AnyPoolOptions};
+use db::{
+    backend::Capabilities,
+    instrument,
+    migration::Migrations,
+    models::Arg,
+    schema::{self, SchemaMismatch},
+    QuerySettings,
+};
+use sqlx::any::{install_default_drivers, AnyRow};
+use sqlx::FromRow;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Whether the error of a transaction, or any error it wraps, can succeed when retried.
+fn is_retryable(err: &anyhow::Error) -> bool {
+    err.chain().any(|err| {
+        err.downcast_ref::<Error>().is_some_and(Error::is_retryable)
+            || err
+                .downcast_ref::<sqlx::Error>()
+                .is_some_and(error::is_retryable)
+    })
+}
 
-async fn establish_connection(url: String) -> Result<Connection> {
+async fn establish_connection(
+    url: String,
+    config: &DatabaseConfig,
+    settings: &Arc<QuerySettings>,
+) -> Result<Connection> {
     install_default_drivers();
-    let conn = AnyPoolOptions::new()
-        .max_connections(5)
-        .connect(&url)
-        .await?;
-    Ok(conn)
+    let mut attempt = 1;
+    loop {
+        match config.pool_options(&url, settings).connect(&url).await {
+            Ok(conn) => return Ok(conn),
+            Err(err) => match config.retry {
+                // a bad url will not get any better
+                Some(policy)
+                    if attempt < policy.max_attempts
+                        && !matches!(err, sqlx::Error::Configuration(_)) =>
+                {
+                    let delay = policy.backoff(attempt);
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(attempt, error = %err, "cannot connect to the database, retrying in {delay:?}");
+                    #[cfg(not(feature = "tracing"))]
+                    eprintln!("cannot connect to the database ({err}), retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => return Err(err.into()),
+            },
+        }
+    }
 }
 
 /// Represents a database.
 pub struct Database {
-    /// The connection pool for the database.
+    /// The connection pool for the database, the primary when there are read replicas.
     pub conn: Connection,
+    replicas: Vec<Connection>,
+    next_replica: AtomicUsize,
+    settings: Arc<QuerySettings>,
+    environments: Vec<String>,
 }
 
 impl Database {
@@ -56,9 +151,680 @@ impl Database {
     /// }
     /// ```
     pub async fn new() -> Result<Self> {
-        dotenv::dotenv().ok();
-        let database_url = std::env::var("DATABASE_URL")?;
-        let conn = establish_connection(database_url).await?;
-        Ok(Self { conn })
+        Self::with_config(DatabaseConfig::default()).await
+    }
+
+    /// Creates a new instance of `Database` with the given configuration.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `Database` instance.
+    ///
+    /// # Example
+    /// ```rust
+    /// use rusql_alchemy::{Database, DatabaseConfig};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let config = DatabaseConfig::new().application_name("billing-service");
+    ///     let db = Database::with_config(config).await;
+    /// }
+    /// ```
+    pub async fn with_config(config: DatabaseConfig) -> Result<Self> {
+        let database_url = config.connection_url()?;
+        let settings = QuerySettings::from_config(&config);
+        let conn = establish_connection(database_url, &config, &settings).await?;
+        let mut replicas = Vec::new();
+        for url in config.replica_urls() {
+            replicas.push(establish_connection(url, &config, &settings).await?);
+        }
+        let db = Self::from_parts(conn, config, settings);
+        Ok(replicas.into_iter().fold(db, Self::with_replica))
+    }
+
+    /// Wraps an existing pool, so the application and the models share its connections.
+    ///
+    /// The pool must use the `Any` driver; `sqlx::any::install_default_drivers` has to be
+    /// called before it is created.
+    ///
+    /// # Example
+    /// ```rust
+    /// sqlx::any::install_default_drivers();
+    /// let pool = AnyPoolOptions::new()
+    ///     .max_connections(50)
+    ///     .connect(&std::env::var("DATABASE_URL")?)
+    ///     .await?;
+    /// let db = Database::from_pool(pool.clone());
+    /// ```
+    pub fn from_pool(pool: Connection) -> Self {
+        Self::from_pool_with_config(pool, DatabaseConfig::default())
+    }
+
+    /// Wraps an existing pool with the options of `config` that do not concern the connection,
+    /// such as `redact_params` and the enabled environments.
+    ///
+    /// The settings of the database, e.g. `log_slow_queries` and `tag_queries`, apply to the
+    /// statements sent to the pool and its clones.
+    ///
+    /// The statement timeout is only enforced on the client side, the server-side timeout needs
+    /// the connection hooks of the pools opened by `with_config`.
+    pub fn from_pool_with_config(pool: Connection, config: DatabaseConfig) -> Self {
+        let settings = QuerySettings::from_config(&config);
+        Self::from_parts(pool, config, settings)
+    }
+
+    fn from_parts(pool: Connection, config: DatabaseConfig, settings: Arc<QuerySettings>) -> Self {
+        QuerySettings::attach(&pool, settings.clone());
+        Self {
+            conn: pool,
+            replicas: Vec::new(),
+            next_replica: AtomicUsize::new(0),
+            settings,
+            environments: config.environments,
+        }
+    }
+
+    /// Adds a read replica pool, see `reader`.
+    pub fn with_replica(mut self, pool: Connection) -> Self {
+        QuerySettings::attach(&pool, self.settings.clone());
+        self.replicas.push(pool);
+        self
+    }
+
+    /// Returns the pool to read from: the read replicas in turn, or the primary when there are
+    /// none.
+    ///
+    /// Replicas may lag behind the primary, read from `writer` to see a write right away.
+    ///
+    /// # Example
+    /// ```rust
+    /// let db = Database::builder()
+    ///     .replica("postgres://replica-1/app")
+    ///     .replica("postgres://replica-2/app")
+    ///     .connect()
+    ///     .await?;
+    ///
+    /// let users = User::filter(kwargs!(age >= 18), db.reader()).await;
+    /// user.update(db.writer()).await;
+    /// let user = User::get(kwargs!(id == user.id), db.writer()).await;
+    /// ```
+    pub fn reader(&self) -> &Connection {
+        if self.replicas.is_empty() {
+            return &self.conn;
+        }
+        let next = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        &self.replicas[next % self.replicas.len()]
+    }
+
+    /// Returns the primary pool, for the writes and the reads that must see them.
+    pub fn writer(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Checks that the database and its read replicas answer, e.g. for a readiness probe.
+    ///
+    /// # Example
+    /// ```rust
+    /// let ready = db.ping().await.is_ok();
+    /// ```
+    pub async fn ping(&self) -> Result<()> {
+        let query = "select 1;";
+        for conn in std::iter::once(&self.conn).chain(&self.replicas) {
+            instrument(
+                &self.settings,
+                "raw",
+                "ping",
+                query,
+                sqlx::query(query).execute(conn),
+            )
+            .await
+            .map_err(|err| Error::new(&self.settings, query, Vec::new(), err))?;
+        }
+        Ok(())
+    }
+
+    /// Closes the pool: new acquisitions fail, and this waits until the connections in use are
+    /// returned and closed, for a clean shutdown.
+    ///
+    /// # Example
+    /// ```rust
+    /// tokio::signal::ctrl_c().await?;
+    /// db.close().await;
+    /// ```
+    pub async fn close(&self) {
+        self.conn.close().await;
+        for replica in &self.replicas {
+            replica.close().await;
+        }
+    }
+
+    /// Returns a configuration to open a `Database` with, see `DatabaseConfig`.
+    ///
+    /// # Example
+    /// ```rust
+    /// let db = Database::builder()
+    ///     .max_connections(20)
+    ///     .min_connections(2)
+    ///     .acquire_timeout(Duration::from_secs(5))
+    ///     .idle_timeout(Duration::from_secs(600))
+    ///     .test_before_acquire(true)
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn builder() -> DatabaseConfig {
+        DatabaseConfig::new()
+    }
+
+    /// Executes a raw statement.
+    ///
+    /// The `?` placeholders are translated for the connected database, `??` being a literal
+    /// `?` on postgres, and the arguments are bound the same way as the `Model` methods do.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of affected rows. On failure the error is an `Error` holding the
+    /// statement and its arguments.
+    ///
+    /// # Example
+    /// ```rust
+    /// let updated = db
+    ///     .execute("update user set role = ? where age >= ?", args!("adult", 18))
+    ///     .await?;
+    /// ```
+    pub async fn execute(&self, query: &str, args: Vec<Arg>) -> Result<u64> {
+        let query = translate_placeholders(query, db::backend::Backend::of(&self.conn));
+        let mut stream = sqlx::query(&query);
+        binds!(args.clone(), stream);
+        match instrument(
+            &self.settings,
+            "raw",
+            "execute",
+            &query,
+            stream.execute(&self.conn),
+        )
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(err) => Err(Error::new(&self.settings, &query, args, err).into()),
+        }
+    }
+
+    /// Executes several `;`-separated statements without arguments in one round trip.
+    ///
+    /// # Returns
+    ///
+    /// Returns the total number of rows affected by the statements.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.execute_batch(
+    ///     "delete from product where owner = 2;
+    ///      delete from user where id = 2;",
+    /// )
+    /// .await?;
+    /// ```
+    pub async fn execute_batch(&self, sql: &str) -> Result<u64> {
+        match instrument(
+            &self.settings,
+            "raw",
+            "execute_batch",
+            sql,
+            sqlx::raw_sql(sql).execute(&self.conn),
+        )
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(err) => Err(Error::new(&self.settings, sql, Vec::new(), err).into()),
+        }
+    }
+
+    /// Listens to a postgres channel on a dedicated connection to the primary.
+    ///
+    /// # Errors
+    /// Returns an error if the database is not postgres or the connection fails.
+    ///
+    /// # Example
+    /// ```rust
+    /// let mut listener = db.listen("cache").await?;
+    /// while let Ok(notification) = listener.recv().await {
+    ///     println!("{}: {}", notification.channel, notification.payload);
+    /// }
+    /// ```
+    #[cfg(feature = "postgres")]
+    pub async fn listen(&self, channel: &str) -> Result<Listener> {
+        let url = &self.conn.connect_options().database_url;
+        if db::backend::Backend::of(&self.conn) != db::backend::Backend::Postgres {
+            anyhow::bail!("LISTEN is only supported by postgres");
+        }
+        Listener::connect(url.as_str(), channel).await
+    }
+
+    /// Sends a notification on a postgres channel to every listener, see `Database::listen`.
+    /// Inside a transaction, `select pg_notify(?, ?)` delivers it on commit instead.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.notify("cache", "user:42").await?;
+    /// ```
+    #[cfg(feature = "postgres")]
+    pub async fn notify(&self, channel: &str, payload: &str) -> Result<()> {
+        if db::backend::Backend::of(&self.conn) != db::backend::Backend::Postgres {
+            anyhow::bail!("NOTIFY is only supported by postgres");
+        }
+        self.execute(
+            "select pg_notify(?, ?)",
+            vec![Arg::from(channel), Arg::from(payload)],
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Runs `f` in a transaction on the primary, committed when it returns `Ok` and rolled back
+    /// when it returns an error.
+    ///
+    /// # Returns
+    ///
+    /// Returns the value returned by `f`.
+    ///
+    /// # Example
+    /// ```rust
+    /// let moved = db
+    ///     .transaction(|tx| {
+    ///         Box::pin(async move {
+    ///             tx.execute("update account set balance = balance - ? where id = ?", args!(10, 1))
+    ///                 .await?;
+    ///             tx.execute("update account set balance = balance + ? where id = ?", args!(10, 2))
+    ///                 .await
+    ///         })
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: for<'t> FnOnce(&'t mut Transaction) -> TransactionFuture<'t, T>,
+    {
+        self.run_transaction(None, f).await
+    }
+
+    /// Runs `f` in a transaction with the given isolation level, see `Database::transaction`.
+    ///
+    /// Postgres and mysql set the level for this transaction only, sqlite transactions are
+    /// always serializable.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.transaction_with_isolation(IsolationLevel::Serializable, |tx| {
+    ///     Box::pin(async move {
+    ///         tx.execute("update item set stock = stock - 1 where id = ?", args!(7)).await
+    ///     })
+    /// })
+    /// .await?;
+    /// ```
+    pub async fn transaction_with_isolation<T, F>(&self, level: IsolationLevel, f: F) -> Result<T>
+    where
+        F: for<'t> FnOnce(&'t mut Transaction) -> TransactionFuture<'t, T>,
+    {
+        self.run_transaction(Some(level), f).await
+    }
+
+    async fn run_transaction<T, F>(&self, isolation: Option<IsolationLevel>, f: F) -> Result<T>
+    where
+        F: for<'t> FnOnce(&'t mut Transaction) -> TransactionFuture<'t, T>,
+    {
+        let mut tx = Transaction::begin(&self.conn, isolation).await?;
+        match f(&mut tx).await {
+            Ok(value) => {
+                tx.commit().await?;
+                Ok(value)
+            }
+            Err(err) => {
+                tx.rollback().await?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Runs `f` in a transaction like `Database::transaction`, running it again in a new
+    /// transaction when it fails on a conflict with another transaction: a serialization
+    /// failure or a deadlock on postgres and mysql, a busy or locked database on sqlite.
+    ///
+    /// The delay between the attempts follows `policy`, the other errors are returned at once.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.transaction_with_retry(RetryPolicy::default(), |tx| {
+    ///     Box::pin(async move {
+    ///         tx.execute("set transaction isolation level serializable", vec![]).await?;
+    ///         tx.execute("update account set balance = balance - ? where id = ?", args!(10, 1))
+    ///             .await
+    ///     })
+    /// })
+    /// .await?;
+    /// ```
+    pub async fn transaction_with_retry<T, F>(&self, policy: RetryPolicy, f: F) -> Result<T>
+    where
+        F: for<'t> Fn(&'t mut Transaction) -> TransactionFuture<'t, T>,
+    {
+        let mut attempt = 1;
+        loop {
+            match self.transaction(&f).await {
+                Err(err) if attempt < policy.max_attempts && is_retryable(&err) => {
+                    let delay = policy.backoff(attempt);
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(attempt, error = %err, "transaction conflict, retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs a raw query and decodes every row into `T`.
+    ///
+    /// The `?` placeholders are translated for the connected database, `??` being a literal
+    /// `?` on postgres, and the arguments are bound the same way as the `Model` methods do. On
+    /// failure the error is an `Error` holding the statement and its arguments.
+    ///
+    /// # Example
+    /// ```rust
+    /// let users: Vec<User> = db
+    ///     .fetch("select * from user where age >= ?", args!(18))
+    ///     .await?;
+    /// ```
+    pub async fn fetch<T>(&self, query: &str, args: Vec<Arg>) -> Result<Vec<T>>
+    where
+        T: Send + Unpin + for<'r> FromRow<'r, AnyRow>,
+    {
+        let query = translate_placeholders(query, db::backend::Backend::of(&self.conn));
+        let mut stream = sqlx::query_as::<_, T>(&query);
+        binds!(args.clone(), stream);
+        instrument(
+            &self.settings,
+            "raw",
+            "fetch",
+            &query,
+            stream.fetch_all(&self.conn),
+        )
+        .await
+        .map_err(|err| Error::new(&self.settings, &query, args, err).into())
+    }
+
+    /// Applies the migrations that are not recorded in the `_rusql_migrations` table yet, in
+    /// version order, then brings the tables of the registered models in line with their schema.
+    ///
+    /// The migrations and models restricted to environments that are not enabled with
+    /// `DatabaseConfig::environment` are skipped.
+    ///
+    /// # Returns
+    ///
+    /// Returns the versions that were applied.
+    ///
+    /// # Example
+    /// ```rust
+    /// let migrations = Migrations::new()
+    ///     .with(Migration::model::<User>(1))
+    ///     .with(Migration::model::<Product>(2));
+    ///
+    /// let applied = db.migrate(&migrations).await?;
+    /// ```
+    pub async fn migrate(&self, migrations: &Migrations) -> Result<Vec<i64>> {
+        self.enabled(migrations).run(&self.conn).await
+    }
+
+    /// Returns the statements `migrate` would execute, without executing them.
+    ///
+    /// # Example
+    /// ```rust
+    /// for sql in db.plan(&migrations).await? {
+    ///     println!("{sql}");
+    /// }
+    /// ```
+    pub async fn plan(&self, migrations: &Migrations) -> Result<Vec<String>> {
+        self.enabled(migrations).plan(&self.conn).await
+    }
+
+    /// Compares the tables of the models registered with `Migrations::model` with the live
+    /// database, without changing anything.
+    ///
+    /// # Returns
+    ///
+    /// Returns the missing tables, columns and unique indexes, and the columns whose type
+    /// differs from the model, empty when the database is up to date.
+    ///
+    /// # Example
+    /// ```rust
+    /// let mismatches = db.check_schema(&migrations).await?;
+    /// if !mismatches.is_empty() {
+    ///     for mismatch in &mismatches {
+    ///         eprintln!("{mismatch}");
+    ///     }
+    ///     anyhow::bail!("the database schema does not match the models");
+    /// }
+    /// ```
+    pub async fn check_schema(&self, migrations: &Migrations) -> Result<Vec<SchemaMismatch>> {
+        let mut mismatches = Vec::new();
+        for model in self.enabled(migrations).models() {
+            mismatches.extend(schema::check(&self.conn, model).await?);
+        }
+        Ok(mismatches)
+    }
+
+    /// Writes the script creating the whole schema to `path`: the `up` statements of the
+    /// migrations in version order, then the tables and unique indexes of the registered models,
+    /// so the schema can be reviewed and applied out of band.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.dump_schema(&migrations, "schema.sql")?;
+    /// ```
+    pub fn dump_schema(&self, migrations: &Migrations, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(
+            path,
+            self.enabled(migrations)
+                .script(db::backend::Backend::of(&self.conn))?,
+        )?;
+        Ok(())
+    }
+
+    /// Inserts the rows of a fixture file, or of every fixture file of a directory, in one
+    /// transaction. See the `fixtures` module for the file format.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of inserted rows.
+    ///
+    /// # Example
+    /// ```rust
+    /// // fixtures/users.json: { "user": [{ "name": "joe", "age": 19 }] }
+    /// db.load_fixtures("fixtures").await?;
+    /// ```
+    pub async fn load_fixtures(&self, path: impl AsRef<Path>) -> Result<u64> {
+        db::fixtures::load(&self.conn, path).await
+    }
+
+    /// Returns the key-value store of the database, e.g. for feature flags and settings that do
+    /// not deserve a model. Its table is created by `KvStore::migrate`.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.kv().set("maintenance", &false).await?;
+    /// let maintenance = db.kv().get::<bool>("maintenance").await?.unwrap_or(false);
+    /// ```
+    pub fn kv(&self) -> db::kv::KvStore<'_> {
+        db::kv::KvStore::new(&self.conn)
+    }
+
+    /// Creates the sequence `name` if it does not exist, starting at 1.
+    ///
+    /// The sequences of the `Model::SEQUENCES` of a model are created by its migration.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.create_sequence("ticket_number_seq").await?;
+    /// ```
+    pub async fn create_sequence(&self, name: &str) -> Result<()> {
+        db::sequence::create(&self.conn, name).await
+    }
+
+    /// Returns the next value of the sequence `name`, e.g. for human-facing numbers.
+    ///
+    /// The values are never returned twice, but a value pulled by a rolled back transaction
+    /// is not reused, so the numbers can have gaps.
+    ///
+    /// # Errors
+    /// Returns an error if the sequence does not exist.
+    ///
+    /// # Example
+    /// ```rust
+    /// let number = db.next_sequence_value("ticket_number_seq").await?;
+    /// println!("TICKET-{number:06}");
+    /// ```
+    pub async fn next_sequence_value(&self, name: &str) -> Result<i64> {
+        db::sequence::next_value(&self.conn, name).await
+    }
+
+    /// Reverts the last `n` applied migrations, newest first, with the down statements recorded
+    /// when they were applied.
+    ///
+    /// # Returns
+    ///
+    /// Returns the versions that were reverted.
+    ///
+    /// # Example
+    /// ```rust
+    /// // undo the migration of a bad deploy
+    /// db.rollback(1).await?;
+    /// ```
+    pub async fn rollback(&self, n: usize) -> Result<Vec<i64>> {
+        Migrations::rollback(&self.conn, n).await
+    }
+
+    /// Drops the schema and builds it again: the tables of the registered models are dropped
+    /// and the recorded migrations are reverted with their down statements, newest first, then
+    /// every migration is applied and the tables of the models are created.
+    ///
+    /// This deletes the data of the migrated tables, `migrate` never does.
+    ///
+    /// # Example
+    /// ```rust
+    /// // start every test run from an empty database
+    /// db.reset_schema(&migrations).await?;
+    /// ```
+    pub async fn reset_schema(&self, migrations: &Migrations) -> Result<Vec<i64>> {
+        self.enabled(migrations).reset(&self.conn).await
+    }
+
+    /// Returns the migrations and models that apply in the environments enabled with
+    /// `DatabaseConfig::environment`.
+    fn enabled(&self, migrations: &Migrations) -> Migrations {
+        migrations.for_environments(&self.environments)
+    }
+
+    /// Reports every statement that takes at least `threshold` to the slow-query callback.
+    ///
+    /// Without a callback registered with `on_slow_query`, slow statements are logged as a
+    /// `tracing` warning with the `tracing` feature, or on stderr otherwise. The setting applies
+    /// to the pools of the database, so it also applies to the `Model` methods given one of
+    /// them, and not to the other databases.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.log_slow_queries(Duration::from_millis(100));
+    /// ```
+    pub fn log_slow_queries(&self, threshold: Duration) -> &Self {
+        self.settings.set_slow_query_log(threshold, None);
+        self
+    }
+
+    /// Abandons every statement running longer than `timeout`, or never when it is `None`.
+    ///
+    /// The failed statements return `Error::Timeout`. The timeout applies to the statements
+    /// sent to the pools of this database, including those of the `Model` methods given
+    /// `db.conn`; `db::with_timeout` overrides it for the statements of a future. The pools
+    /// opened by `with_config` also set it on their postgres and mysql connections, when they
+    /// are next acquired.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_statement_timeout(Some(Duration::from_secs(5)));
+    ///
+    /// if let Err(err) = db.execute("update user set role = ?", args!("admin")).await {
+    ///     if let Some(Error::Timeout { .. }) = err.downcast_ref::<Error>() {
+    ///         eprintln!("the update took too long");
+    ///     }
+    /// }
+    /// ```
+    pub fn set_statement_timeout(&self, timeout: Option<Duration>) -> &Self {
+        self.settings.set_statement_timeout(timeout);
+        self
+    }
+
+    /// Prefixes the statements generated by the crate with `/* tag */`, or with nothing when it
+    /// is `None`, so they can be traced in `pg_stat_statements` and the slow-query logs.
+    ///
+    /// The tag applies to the statements sent to the pools of this database, including those of
+    /// the `Model` methods given `db.conn`. The raw statements of `execute` and `fetch` are sent
+    /// as written.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_query_tag(Some("billing-service"));
+    /// // /* billing-service */ select * from invoice where paid = ?;
+    /// let unpaid = Invoice::filter(kwargs!(paid == false), &db.conn).await;
+    /// ```
+    pub fn set_query_tag(&self, tag: Option<&str>) -> &Self {
+        self.settings.set_query_tag(tag.map(str::to_string));
+        self
+    }
+
+    /// Sets the cipher encrypting the `Model::ENCRYPTED` columns, see the `encryption` module.
+    ///
+    /// Like the query tag, the cipher belongs to this database and its pools. The encrypted
+    /// columns cannot be written nor read through them until a cipher is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.set_cipher(AesGcmCipher::new(key));
+    /// User::create(kwargs!(email = "joe@example.com", api_token = token), &db.conn).await;
+    /// ```
+    pub fn set_cipher(&self, cipher: impl db::encryption::Cipher + 'static) -> &Self {
+        self.settings.set_cipher(Some(std::sync::Arc::new(cipher)));
+        self
+    }
+
+    /// Registers the callback receiving the SQL and the elapsed time of the statements slower
+    /// than the `log_slow_queries` threshold.
+    ///
+    /// # Example
+    /// ```rust
+    /// db.log_slow_queries(Duration::from_millis(100))
+    ///     .on_slow_query(|sql, elapsed| metrics.record_slow_query(sql, elapsed));
+    /// ```
+    pub fn on_slow_query<F>(&self, callback: F) -> &Self
+    where
+        F: Fn(&str, Duration) + Send + Sync + 'static,
+    {
+        self.settings.set_slow_query_callback(Arc::new(callback));
+        self
+    }
+
+    /// Returns the SQL features supported by the connected backend.
+    ///
+    /// # Example
+    /// ```rust
+    /// use rusql_alchemy::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let db = Database::new().await.unwrap();
+    ///     println!("{}", db.capabilities());
+    ///     if db.capabilities().returning {
+    ///         // ...
+    ///     }
+    /// }
+    /// ```
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::of(&self.conn)
     }
 }
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use sqlx::postgres::PgListener;
+
+/// A notification sent with `NOTIFY` or `Database::notify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// The channel the notification was sent on.
+    pub channel: String,
+    /// The payload, empty when none was given.
+    pub payload: String,
+    /// The id of the server process that sent it.
+    pub process_id: u32,
+}
+
+/// The notifications of the channels listened to, returned by `Database::listen`.
+///
+/// The listener holds its own connection, outside of the pool, and reconnects when it is
+/// lost; the notifications sent in the meantime are missed.
+pub struct Listener {
+    listener: PgListener,
+}
+
+impl Listener {
+    pub(crate) async fn connect(url: &str, channel: &str) -> Result<Self> {
+        let mut listener = PgListener::connect(url).await?;
+        listener.listen(channel).await?;
+        Ok(Self { listener })
+    }
+
+    /// Listens to another channel.
+    pub async fn listen(&mut self, channel: &str) -> Result<()> {
+        Ok(self.listener.listen(channel).await?)
+    }
+
+    /// Stops listening to a channel.
+    pub async fn unlisten(&mut self, channel: &str) -> Result<()> {
+        Ok(self.listener.unlisten(channel).await?)
+    }
+
+    /// Waits for the next notification.
+    ///
+    /// # Example
+    /// ```rust
+    /// let mut listener = db.listen("cache").await?;
+    /// loop {
+    ///     let notification = listener.recv().await?;
+    ///     cache.invalidate(&notification.payload);
+    /// }
+    /// ```
+    pub async fn recv(&mut self) -> Result<Notification> {
+        let notification = self.listener.recv().await?;
+        Ok(Notification {
+            channel: notification.channel().to_string(),
+            payload: notification.payload().to_string(),
+            process_id: notification.process_id(),
+        })
+    }
+}
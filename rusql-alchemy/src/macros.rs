@@ -17,8 +17,19 @@
 /// );
 /// ```
 ///
+/// Columns of two tables can be compared with the same operators, e.g. `kwargs!(User.id == Profile.user_id)`.
+/// Such comparisons produce a `Condition::ColumnCondition`, so the right side is never bound as a value.
+///
+/// `Option` values are bound as `NULL` when they are `None`, and `==`/`!=` comparisons with `None`
+/// are rendered as `is null`/`is not null`, e.g. `kwargs!(email == maybe_email)`.
+///
+/// `similar_to` matches the values close to the given text, for typo-tolerant searches, e.g.
+/// `kwargs!(name similar_to "jonh")`. It uses the `%` similarity of the `pg_trgm` extension on
+/// postgres (`create extension pg_trgm;`), and a `like '%jonh%'` on the other databases.
+///
 /// # Variants
 ///
+/// - `$table:ident.$field:ident == $other_table:ident.$other_field:ident` (and the other operators)
 /// - `$field:ident = $value:expr`
 /// - `$field:ident == $value:expr`
 /// - `$field:ident != $value:expr`
@@ -26,8 +37,43 @@
 /// - `$field:ident <= $value:expr`
 /// - `$field:ident > $value:expr`
 /// - `$field:ident >= $value:expr`
+/// - `$field:ident similar_to $value:expr`
 #[macro_export]
 macro_rules! kwargs {
+    // Support for column-to-column comparisons between tables
+    (@column $table:ident . $field:ident, $op:literal, $other_table:ident . $other_field:ident) => {
+        vec![
+            Condition::ColumnCondition {
+                left: ColumnRef {
+                    table: stringify!($table).to_string(),
+                    column: stringify!($field).to_string(),
+                },
+                right: ColumnRef {
+                    table: stringify!($other_table).to_string(),
+                    column: stringify!($other_field).to_string(),
+                },
+                comparison_operator: $op.to_string(),
+            }
+        ]
+    };
+    ($table:ident . $field:ident == $other_table:ident . $other_field:ident) => {
+        kwargs!(@column $table.$field, "=", $other_table.$other_field)
+    };
+    ($table:ident . $field:ident != $other_table:ident . $other_field:ident) => {
+        kwargs!(@column $table.$field, "!=", $other_table.$other_field)
+    };
+    ($table:ident . $field:ident < $other_table:ident . $other_field:ident) => {
+        kwargs!(@column $table.$field, "<", $other_table.$other_field)
+    };
+    ($table:ident . $field:ident <= $other_table:ident . $other_field:ident) => {
+        kwargs!(@column $table.$field, "<=", $other_table.$other_field)
+    };
+    ($table:ident . $field:ident > $other_table:ident . $other_field:ident) => {
+        kwargs!(@column $table.$field, ">", $other_table.$other_field)
+    };
+    ($table:ident . $field:ident >= $other_table:ident . $other_field:ident) => {
+        kwargs!(@column $table.$field, ">=", $other_table.$other_field)
+    };
     // Support for direct field-value pairs with custom comparison operators
     ($($field:ident = $value:expr),* $(,)?) => {
         {
@@ -35,8 +81,7 @@ macro_rules! kwargs {
             $(
                 args.push(Condition::FieldCondition {
                     field: stringify!($field).to_string(),
-                    value: rusql_alchemy::to_string($value.clone()),
-                    value_type: rusql_alchemy::get_type_name($value.clone()).into(),
+                    value: rusql_alchemy::db::models::Arg::from($value.clone()),
                     comparison_operator: "=".to_string(),
                 });
             )*
@@ -48,8 +93,7 @@ macro_rules! kwargs {
             vec![
                 Condition::FieldCondition {
                     field: stringify!($field).to_string(),
-                    value: rusql_alchemy::to_string($value.clone()),
-                    value_type: rusql_alchemy::get_type_name($value.clone()).into(),
+                    value: rusql_alchemy::db::models::Arg::from($value.clone()),
                     comparison_operator: "=".to_string(),
                 }
             ]
@@ -60,8 +104,7 @@ macro_rules! kwargs {
             vec![
                 Condition::FieldCondition {
                     field: stringify!($field).to_string(),
-                    value: rusql_alchemy::to_string($value.clone()),
-                    value_type: rusql_alchemy::get_type_name($value.clone()).into(),
+                    value: rusql_alchemy::db::models::Arg::from($value.clone()),
                     comparison_operator: "!=".to_string(),
                 }
             ]
@@ -72,8 +115,7 @@ macro_rules! kwargs {
             vec![
                 Condition::FieldCondition {
                     field: stringify!($field).to_string(),
-                    value: rusql_alchemy::to_string($value.clone()),
-                    value_type: rusql_alchemy::get_type_name($value.clone()).into(),
+                    value: rusql_alchemy::db::models::Arg::from($value.clone()),
                     comparison_operator: "<".to_string(),
                 }
             ]
@@ -84,8 +126,7 @@ macro_rules! kwargs {
             vec![
                 Condition::FieldCondition {
                     field: stringify!($field).to_string(),
-                    value: rusql_alchemy::to_string($value.clone()),
-                    value_type: rusql_alchemy::get_type_name($value.clone()).into(),
+                    value: rusql_alchemy::db::models::Arg::from($value.clone()),
                     comparison_operator: "<=".to_string(),
                 }
             ]
@@ -96,8 +137,7 @@ macro_rules! kwargs {
             vec![
                 Condition::FieldCondition {
                     field: stringify!($field).to_string(),
-                    value: rusql_alchemy::to_string($value.clone()),
-                    value_type: rusql_alchemy::get_type_name($value.clone()).into(),
+                    value: rusql_alchemy::db::models::Arg::from($value.clone()),
                     comparison_operator: ">".to_string(),
                 }
             ]
@@ -108,50 +148,122 @@ macro_rules! kwargs {
             vec![
                 Condition::FieldCondition {
                     field: stringify!($field).to_string(),
-                    value: rusql_alchemy::to_string($value.clone()),
-                    value_type: rusql_alchemy::get_type_name($value.clone()).into(),
+                    value: rusql_alchemy::db::models::Arg::from($value.clone()),
                     comparison_operator: ">=".to_string(),
                 }
             ]
         }
     };
+    ($field:ident similar_to $value:expr) => {
+        {
+            vec![
+                Condition::FieldCondition {
+                    field: stringify!($field).to_string(),
+                    value: rusql_alchemy::db::models::Arg::from($value.clone()),
+                    comparison_operator: "similar_to".to_string(),
+                }
+            ]
+        }
+    };
 }
 
-/// A macro to bind arguments to a stream based on their type.
+/// A macro to create a vector of `Arg` from values, for raw queries run with `Database::execute`
+/// and `Database::fetch`.
 ///
-/// This macro iterates over a list of `(value, type)` pairs and binds each value to the stream
-/// according to its type. Supported types are `i32`, `bool`, and `f64`. All other types are bound as strings.
+/// # Example
+///
+/// ```
+/// let args = args!("joe", 19);
+/// db.execute("update user set name = ? where age = ?", args).await?;
+/// ```
+#[macro_export]
+macro_rules! args {
+    ($($value:expr),* $(,)?) => {
+        vec![
+            $(rusql_alchemy::db::models::Arg::from($value.clone()),)*
+        ]
+    };
+}
+
+/// A macro to bind arguments to a stream.
+///
+/// This macro iterates over a list of `Arg` and binds each value to the stream with its own type.
 ///
 /// # Arguments
 ///
-/// * `$args:expr` - A list of `(value, type)` pairs.
+/// * `$args:expr` - A list of `Arg`.
 /// * `$stream:expr` - The stream to which the values will be bound.
 ///
 /// # Example
 ///
 /// ```
-/// let args = vec![
-///     ("42".to_string(), "i32".to_string()),
-///     ("3.14".to_string(), "f64".to_string()),
-///     ("true".to_string(), "bool".to_string()),
-/// ];
+/// let args = vec![Arg::Int(42), Arg::Float(3.14), Arg::Bool(true)];
 /// let stream = some_stream();
 /// binds!(args, stream);
 /// ```
 macro_rules! binds {
     ($args: expr, $stream:expr) => {
-        for (v, t) in $args {
-            let v = v.replace('"', "");
-            match t.as_str() {
-                "i32" | "bool" => {
-                    $stream = $stream.bind(v.parse::<i32>().unwrap());
-                }
-                "f64" => {
-                    $stream = $stream.bind(v.parse::<f64>().unwrap());
-                }
-                _ => {
-                    $stream = $stream.bind(v);
-                }
+        for arg in $args {
+            $stream = match arg {
+                // the Any driver needs a type for nulls, an integer null is accepted by
+                // sqlite and mysql columns of any type and cast on assignment by postgres
+                $crate::db::models::Arg::Null => $stream.bind(None::<i32>),
+                $crate::db::models::Arg::Bool(v) => $stream.bind(v),
+                $crate::db::models::Arg::Int(v) => $stream.bind(v),
+                $crate::db::models::Arg::Float(v) => $stream.bind(v),
+                $crate::db::models::Arg::Text(v) => $stream.bind(v),
+                $crate::db::models::Arg::Bytes(v) => $stream.bind(v),
+            };
+        }
+    };
+}
+
+/// A macro to implement the sqlx `Any` encoding, the serde traits and the `kwargs!` conversion
+/// of a type stored as text.
+///
+/// The value is encoded with its `Display` implementation and decoded with its `FromStr`
+/// implementation.
+#[allow(unused_macros)]
+macro_rules! impl_text_type {
+    ($ty:ty) => {
+        impl From<$ty> for crate::db::models::Arg {
+            fn from(value: $ty) -> Self {
+                crate::db::models::Arg::Text(value.to_string())
+            }
+        }
+
+        impl serde::Serialize for $ty {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let text = String::deserialize(deserializer)?;
+                text.parse().map_err(serde::de::Error::custom)
+            }
+        }
+
+        impl sqlx::Type<sqlx::Any> for $ty {
+            fn type_info() -> sqlx::any::AnyTypeInfo {
+                <String as sqlx::Type<sqlx::Any>>::type_info()
+            }
+        }
+
+        impl<'q> sqlx::Encode<'q, sqlx::Any> for $ty {
+            fn encode_by_ref(
+                &self,
+                buf: &mut <sqlx::Any as sqlx::Database>::ArgumentBuffer<'q>,
+            ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+                <String as sqlx::Encode<'q, sqlx::Any>>::encode(self.to_string(), buf)
+            }
+        }
+
+        impl<'r> sqlx::Decode<'r, sqlx::Any> for $ty {
+            fn decode(value: sqlx::any::AnyValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+                let text = <String as sqlx::Decode<'r, sqlx::Any>>::decode(value)?;
+                Ok(text.parse::<$ty>()?)
             }
         }
     };
@@ -180,3 +292,106 @@ macro_rules! migrate {
         $( $struct::migrate($conn).await; )*
     };
 }
+
+/// A macro to declare a Rust enum usable as a model field.
+///
+/// The variants are stored as `TEXT` holding the variant name. The macro derives `Debug`,
+/// `Clone`, `Copy`, `PartialEq` and `Eq`, implements `SqlEnum`, `Display`, `FromStr` and
+/// `Default` (the first variant), and the conversions needed by `kwargs!` and row decoding.
+///
+/// # Example
+///
+/// ```
+/// sql_enum! {
+///     pub enum Role {
+///         User,
+///         Admin,
+///     }
+/// }
+///
+/// let admins = User::filter(kwargs!(role == Role::Admin), &conn).await;
+/// ```
+#[macro_export]
+macro_rules! sql_enum {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $first:ident $(, $variant:ident)* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        $vis enum $name {
+            $first,
+            $($variant,)*
+        }
+
+        impl $crate::types::SqlEnum for $name {
+            const VARIANTS: &'static [&'static str] = &[stringify!($first), $(stringify!($variant),)*];
+
+            fn as_str(&self) -> &'static str {
+                match self {
+                    Self::$first => stringify!($first),
+                    $(Self::$variant => stringify!($variant),)*
+                }
+            }
+
+            fn from_variant(name: &str) -> Option<Self> {
+                match name {
+                    stringify!($first) => Some(Self::$first),
+                    $(stringify!($variant) => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::$first
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str($crate::types::SqlEnum::as_str(self))
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                <Self as $crate::types::SqlEnum>::from_variant(s)
+                    .ok_or_else(|| format!("invalid {} variant: {s}", stringify!($name)))
+            }
+        }
+
+        impl From<$name> for $crate::db::models::Arg {
+            fn from(value: $name) -> Self {
+                $crate::db::models::Arg::Text($crate::types::SqlEnum::as_str(&value).to_string())
+            }
+        }
+
+        impl $crate::sqlx::Type<$crate::sqlx::Any> for $name {
+            fn type_info() -> $crate::sqlx::any::AnyTypeInfo {
+                <String as $crate::sqlx::Type<$crate::sqlx::Any>>::type_info()
+            }
+        }
+
+        impl<'q> $crate::sqlx::Encode<'q, $crate::sqlx::Any> for $name {
+            fn encode_by_ref(
+                &self,
+                buf: &mut <$crate::sqlx::Any as $crate::sqlx::Database>::ArgumentBuffer<'q>,
+            ) -> Result<$crate::sqlx::encode::IsNull, $crate::sqlx::error::BoxDynError> {
+                <String as $crate::sqlx::Encode<'q, $crate::sqlx::Any>>::encode(
+                    $crate::types::SqlEnum::as_str(self).to_string(),
+                    buf,
+                )
+            }
+        }
+
+        impl<'r> $crate::sqlx::Decode<'r, $crate::sqlx::Any> for $name {
+            fn decode(
+                value: $crate::sqlx::any::AnyValueRef<'r>,
+            ) -> Result<Self, $crate::sqlx::error::BoxDynError> {
+                let name = <String as $crate::sqlx::Decode<'r, $crate::sqlx::Any>>::decode(value)?;
+                Ok(name.parse::<Self>()?)
+            }
+        }
+    };
+}
@@ -1,10 +1,32 @@
 #[cfg(feature = "postgres")]
 pub use super::types::Serial;
 
+#[cfg(feature = "encryption")]
+pub use super::db::encryption::AesGcmCipher;
+
 pub use super::types::*;
 pub use super::Connection;
 pub use super::Database;
-pub use super::{db::models::*, kwargs, migrate};
+pub use super::DatabaseConfig;
+pub use super::DatabaseRegistry;
+pub use super::Error;
+pub use super::IsolationLevel;
+pub use super::QueryError;
+pub use super::RetryPolicy;
+pub use super::SqlitePragmas;
+pub use super::Transaction;
+pub use super::{
+    args,
+    db::audit::AuditAction,
+    db::batch::WriteBatch,
+    db::builder::*,
+    db::decode::{ColumnOrdinals, DecodeError},
+    db::encryption::Cipher,
+    db::factory::Factory,
+    db::migration::{Migration, Migrations},
+    db::models::*,
+    kwargs, migrate, sql_enum,
+};
 pub use async_trait::async_trait;
 pub use rusql_alchemy_macro::Model;
 pub use sqlx::FromRow;
@@ -0,0 +1,421 @@
+//! A small job queue stored in the `_rusql_jobs` table.
+//!
+//! `enqueue` adds a job with a JSON payload to a named queue, and `dequeue` claims the oldest
+//! job that is due, so several workers can share a queue without running a job twice. The
+//! claim is a single `update ... returning` on postgres, where the job is selected with
+//! `FOR UPDATE SKIP LOCKED`, and on sqlite, which serializes the writes; on mysql, which has
+//! no `returning`, the job is selected with `FOR UPDATE SKIP LOCKED` then claimed in a
+//! transaction.
+//!
+//! A claimed job is removed with `Job::complete` or scheduled again with `Job::retry`. The
+//! jobs of a worker that died stay claimed until `requeue_stale` releases them, after which
+//! `complete` and `retry` fail for the worker that claimed them first.
+//!
+//! # Example
+//! ```rust
+//! Migrations::new().model::<Job>();
+//!
+//! enqueue("emails", &Welcome { user_id: 42 }, &conn).await?;
+//!
+//! while let Some(job) = dequeue("emails", &conn).await? {
+//!     let welcome: Welcome = job.payload_as()?;
+//!     match send_welcome(welcome).await {
+//!         Ok(()) => job.complete(&conn).await?,
+//!         Err(_) if job.attempts < 5 => job.retry(Duration::from_secs(60), &conn).await?,
+//!         Err(_) => job.complete(&conn).await?,
+//!     }
+//! }
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::db::backend::Backend;
+use crate::db::models::{Arg, Condition, Model};
+use crate::db::sequence;
+use crate::db::{instrument, QuerySettings};
+use crate::{Connection, Error};
+
+/// The table of the jobs.
+pub const JOB_TABLE: &str = "_rusql_jobs";
+
+/// The sequence of the job ids.
+pub const JOB_SEQUENCE: &str = "_rusql_job_seq";
+
+/// A job of a queue, registered with `Migrations::model::<Job>()` to create its table.
+#[derive(Debug, Clone, Default, PartialEq, FromRow, Serialize, Deserialize)]
+pub struct Job {
+    /// The id of the job, in the order the jobs were enqueued.
+    pub id: i64,
+    /// The name of the queue.
+    pub queue: String,
+    /// The payload, as JSON.
+    pub payload: String,
+    /// The number of times the job was claimed, including the current one.
+    pub attempts: i32,
+    /// The unix timestamp from which the job can be claimed, in seconds.
+    pub run_at: i64,
+    /// The unix timestamp at which the job was claimed, `None` while it waits.
+    pub locked_at: Option<i64>,
+    /// The unix timestamp at which the job was enqueued.
+    pub created_at: i64,
+}
+
+#[async_trait]
+impl Model for Job {
+    const SCHEMA: &'static str = "create table if not exists _rusql_jobs (
+        id bigint primary key,
+        queue varchar(255) not null,
+        payload text not null,
+        attempts integer not null,
+        run_at bigint not null,
+        locked_at bigint,
+        created_at bigint not null
+    );";
+    const NAME: &'static str = JOB_TABLE;
+    const PK: &'static str = "id";
+    const SEQUENCES: &'static [(&'static str, &'static str)] = &[("id", JOB_SEQUENCE)];
+
+    async fn save(&self, conn: &Connection) -> bool {
+        Self::create(self.values(), conn).await
+    }
+
+    async fn update(&self, conn: &Connection) -> bool {
+        Self::set(self.id, self.values(), conn).await
+    }
+
+    async fn delete(&self, conn: &Connection) -> bool {
+        Self::delete_where(vec![Condition::field("id", "=", self.id)])
+            .execute(conn)
+            .await
+            > 0
+    }
+}
+
+impl Job {
+    /// Returns the columns written by `save` and `update`, every one except the id.
+    fn values(&self) -> Vec<Condition> {
+        vec![
+            Condition::field("queue", "=", self.queue.clone()),
+            Condition::field("payload", "=", self.payload.clone()),
+            Condition::field("attempts", "=", self.attempts),
+            Condition::field("run_at", "=", self.run_at),
+            Condition::field("locked_at", "=", self.locked_at),
+            Condition::field("created_at", "=", self.created_at),
+        ]
+    }
+
+    /// Deserializes the payload.
+    ///
+    /// # Errors
+    /// Returns an error if the payload does not match `T`.
+    pub fn payload_as<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_str(&self.payload)?)
+    }
+
+    /// Removes the job once it is done.
+    ///
+    /// # Errors
+    /// Returns an error if the statement fails, or if the job is no longer claimed by this
+    /// worker, e.g. once `requeue_stale` released it to another one.
+    pub async fn complete(&self, conn: &Connection) -> Result<()> {
+        let query = format!(
+            "delete from {JOB_TABLE} where id = {} and locked_at = {};",
+            placeholder(conn, 1),
+            placeholder(conn, 2)
+        );
+        let args = vec![Arg::from(self.id), Arg::from(self.locked_at)];
+        self.claimed(execute(conn, "complete", &query, args).await?)
+    }
+
+    /// Releases the job so it can be claimed again after `delay`, e.g. after a failure.
+    ///
+    /// # Errors
+    /// Returns an error if the statement fails, or if the job is no longer claimed by this
+    /// worker.
+    pub async fn retry(&self, delay: Duration, conn: &Connection) -> Result<()> {
+        let query = format!(
+            "update {JOB_TABLE} set locked_at = null, run_at = {} where id = {} and locked_at = {};",
+            placeholder(conn, 1),
+            placeholder(conn, 2),
+            placeholder(conn, 3)
+        );
+        let run_at = now() + delay.as_secs() as i64;
+        let args = vec![
+            Arg::from(run_at),
+            Arg::from(self.id),
+            Arg::from(self.locked_at),
+        ];
+        self.claimed(execute(conn, "retry", &query, args).await?)
+    }
+
+    /// Fails when a statement on the claimed job matched no row.
+    fn claimed(&self, rows_affected: u64) -> Result<()> {
+        if rows_affected == 0 {
+            anyhow::bail!("the job {} is no longer claimed by this worker", self.id);
+        }
+        Ok(())
+    }
+}
+
+/// Adds a job to `queue`, due at once.
+///
+/// # Returns
+/// The id of the job.
+///
+/// # Errors
+/// Returns an error if the payload cannot be serialized or the insert fails.
+///
+/// # Example
+/// ```rust
+/// enqueue("emails", &Welcome { user_id: 42 }, &conn).await?;
+/// ```
+pub async fn enqueue<T: Serialize + ?Sized>(
+    queue: &str,
+    payload: &T,
+    conn: &Connection,
+) -> Result<i64> {
+    let payload = serde_json::to_string(payload)?;
+    let id = sequence::next_value(conn, JOB_SEQUENCE).await?;
+    let now = now();
+    let placeholders = (1..=6)
+        .map(|index| placeholder(conn, index))
+        .collect::<Vec<_>>();
+    let query = format!(
+        "insert into {JOB_TABLE} (id, queue, payload, attempts, run_at, created_at) values ({});",
+        placeholders.join(", ")
+    );
+    let args = vec![
+        Arg::from(id),
+        Arg::from(queue),
+        Arg::from(payload),
+        Arg::from(0),
+        Arg::from(now),
+        Arg::from(now),
+    ];
+    execute(conn, "enqueue", &query, args).await?;
+    Ok(id)
+}
+
+/// Claims the oldest due job of `queue`, or returns `None` when there is none.
+///
+/// The job is locked until it is completed or retried, the other workers skip it.
+///
+/// # Errors
+/// Returns an error if a statement fails.
+pub async fn dequeue(queue: &str, conn: &Connection) -> Result<Option<Job>> {
+    let backend = Backend::of(conn);
+    let capabilities = backend.capabilities();
+    let lock = if capabilities.skip_locked {
+        " for update skip locked"
+    } else {
+        ""
+    };
+    let due = |columns: &str| {
+        format!(
+            "select {columns} from {JOB_TABLE} \
+             where queue = {} and locked_at is null and run_at <= {} \
+             order by run_at, id limit 1{lock}",
+            backend.placeholder(1),
+            backend.placeholder(2)
+        )
+    };
+    let now = now();
+    let args = vec![Arg::from(queue), Arg::from(now)];
+
+    if capabilities.returning {
+        let query = format!(
+            "update {JOB_TABLE} set locked_at = {now}, attempts = attempts + 1 \
+             where id = ({due}) returning *;",
+            now = backend.placeholder(2),
+            due = due("id")
+        );
+        let mut stream = sqlx::query(&query);
+        binds!(args.clone(), stream);
+        let row = instrument(
+            &QuerySettings::of(conn),
+            JOB_TABLE,
+            "dequeue",
+            &query,
+            stream.fetch_optional(conn),
+        )
+        .await
+        .map_err(|err| Error::new(&QuerySettings::of(conn), &query, args, err))?;
+        return Ok(row.as_ref().map(Job::from_row).transpose()?);
+    }
+
+    let mut tx = conn.begin().await?;
+    let query = format!("{};", due("*"));
+    let mut stream = sqlx::query(&query);
+    binds!(args.clone(), stream);
+    let row = instrument(
+        &QuerySettings::of(conn),
+        JOB_TABLE,
+        "dequeue",
+        &query,
+        stream.fetch_optional(&mut *tx),
+    )
+    .await
+    .map_err(|err| Error::new(&QuerySettings::of(conn), &query, args, err))?;
+    let Some(mut job) = row.as_ref().map(Job::from_row).transpose()? else {
+        return Ok(None);
+    };
+    let query = format!(
+        "update {JOB_TABLE} set locked_at = {}, attempts = attempts + 1 where id = {};",
+        backend.placeholder(1),
+        backend.placeholder(2)
+    );
+    let args = vec![Arg::from(now), Arg::from(job.id)];
+    let mut stream = sqlx::query(&query);
+    binds!(args.clone(), stream);
+    instrument(
+        &QuerySettings::of(conn),
+        JOB_TABLE,
+        "dequeue",
+        &query,
+        stream.execute(&mut *tx),
+    )
+    .await
+    .map_err(|err| Error::new(&QuerySettings::of(conn), &query, args, err))?;
+    tx.commit().await?;
+    job.locked_at = Some(now);
+    job.attempts += 1;
+    Ok(Some(job))
+}
+
+/// Releases the jobs of `queue` claimed more than `timeout` ago, e.g. by a worker that died,
+/// so they can be claimed again.
+///
+/// # Returns
+/// The number of released jobs.
+pub async fn requeue_stale(queue: &str, timeout: Duration, conn: &Connection) -> Result<u64> {
+    let query = format!(
+        "update {JOB_TABLE} set locked_at = null where queue = {} and locked_at < {};",
+        placeholder(conn, 1),
+        placeholder(conn, 2)
+    );
+    let args = vec![
+        Arg::from(queue),
+        Arg::from(now() - timeout.as_secs() as i64),
+    ];
+    let mut stream = sqlx::query(&query);
+    binds!(args.clone(), stream);
+    let result = instrument(
+        &QuerySettings::of(conn),
+        JOB_TABLE,
+        "requeue_stale",
+        &query,
+        stream.execute(conn),
+    )
+    .await
+    .map_err(|err| Error::new(&QuerySettings::of(conn), &query, args, err))?;
+    Ok(result.rows_affected())
+}
+
+/// Runs a statement of the queue, returning the number of rows it affected.
+async fn execute(conn: &Connection, op: &'static str, query: &str, args: Vec<Arg>) -> Result<u64> {
+    let mut stream = sqlx::query(query);
+    binds!(args.clone(), stream);
+    let result = instrument(
+        &QuerySettings::of(conn),
+        JOB_TABLE,
+        op,
+        query,
+        stream.execute(conn),
+    )
+    .await
+    .map_err(|err| Error::new(&QuerySettings::of(conn), query, args, err))?;
+    Ok(result.rows_affected())
+}
+
+/// Returns the placeholder of the parameter at `index` for the backend of `conn`.
+fn placeholder(conn: &Connection, index: usize) -> String {
+    Backend::of(conn).placeholder(index)
+}
+
+/// Returns the current unix timestamp, in seconds.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migration::Migrations;
+    use crate::db::testing::memory;
+
+    async fn queue() -> Connection {
+        let conn = memory().await;
+        Migrations::new().model::<Job>().run(&conn).await.unwrap();
+        enqueue("emails", &42, &conn).await.unwrap();
+        conn
+    }
+
+    #[tokio::test]
+    async fn claims_a_job_once() {
+        let conn = queue().await;
+        let job = dequeue("emails", &conn).await.unwrap().unwrap();
+        assert_eq!((job.payload_as::<i64>().unwrap(), job.attempts), (42, 1));
+        assert!(job.locked_at.is_some());
+        assert!(dequeue("emails", &conn).await.unwrap().is_none());
+        assert!(dequeue("sms", &conn).await.unwrap().is_none());
+
+        job.complete(&conn).await.unwrap();
+        assert!(Job::all(&conn).await.is_empty());
+        assert!(job.complete(&conn).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reschedules_a_retried_job() {
+        let conn = queue().await;
+        let job = dequeue("emails", &conn).await.unwrap().unwrap();
+        job.retry(Duration::from_secs(60), &conn).await.unwrap();
+        assert!(dequeue("emails", &conn).await.unwrap().is_none());
+        let waiting = &Job::all(&conn).await[0];
+        assert!(waiting.run_at >= now() + 59 && waiting.locked_at.is_none());
+
+        job.retry(Duration::ZERO, &conn).await.unwrap_err();
+        sqlx::query("update _rusql_jobs set run_at = 0;")
+            .execute(&conn)
+            .await
+            .unwrap();
+        let job = dequeue("emails", &conn).await.unwrap().unwrap();
+        assert_eq!(job.attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn releases_the_stale_jobs() {
+        let conn = queue().await;
+        let mut stale = dequeue("emails", &conn).await.unwrap().unwrap();
+        assert_eq!(
+            requeue_stale("emails", Duration::from_secs(60), &conn)
+                .await
+                .unwrap(),
+            0
+        );
+        stale.locked_at = Some(now() - 120);
+        sqlx::query("update _rusql_jobs set locked_at = $1;")
+            .bind(stale.locked_at)
+            .execute(&conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            requeue_stale("emails", Duration::from_secs(60), &conn)
+                .await
+                .unwrap(),
+            1
+        );
+
+        let job = dequeue("emails", &conn).await.unwrap().unwrap();
+        assert_eq!(job.attempts, 2);
+        assert!(stale.complete(&conn).await.is_err());
+        assert!(stale.retry(Duration::ZERO, &conn).await.is_err());
+        job.complete(&conn).await.unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Result};
+
+use crate::db::migration::Migrations;
+use crate::db::models::{Model, DEFAULT_DATABASE};
+use crate::{Connection, Database};
+
+/// A set of named databases, for applications whose models live in several databases.
+///
+/// A model is routed to the database named by its `Model::DATABASE`, e.g.
+/// `const DATABASE: &'static str = "analytics";`, and to the `default` database otherwise.
+///
+/// The databases can run on different backends: the queries are written in the dialect of the
+/// connection they are sent to, see `Backend::of`.
+///
+/// # Example
+/// ```rust
+/// let registry = DatabaseRegistry::new()
+///     .with("default", Database::builder().url("sqlite://app.db").connect().await?)
+///     .with("analytics", Database::builder().url("postgres://localhost/analytics").connect().await?);
+///
+/// registry.migrate(&migrations).await?;
+/// let events = Event::all(registry.conn::<Event>()?).await;
+/// ```
+#[derive(Default)]
+pub struct DatabaseRegistry {
+    databases: Vec<(String, Database)>,
+}
+
+impl DatabaseRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a database under a name, replacing the database registered under it before.
+    pub fn with(mut self, name: impl Into<String>, database: Database) -> Self {
+        let name = name.into();
+        self.databases.retain(|(registered, _)| *registered != name);
+        self.databases.push((name, database));
+        self
+    }
+
+    /// Returns the database registered under a name.
+    pub fn get(&self, name: &str) -> Option<&Database> {
+        self.databases
+            .iter()
+            .find(|(registered, _)| registered == name)
+            .map(|(_, database)| database)
+    }
+
+    /// Returns the `default` database.
+    pub fn default_database(&self) -> Option<&Database> {
+        self.get(DEFAULT_DATABASE)
+    }
+
+    /// Returns the database of a model.
+    ///
+    /// # Errors
+    /// Fails when no database is registered under the name the model declares.
+    pub fn database<M: Model>(&self) -> Result<&Database> {
+        self.get(M::DATABASE).ok_or_else(|| {
+            anyhow!(
+                "no database named {} is registered for the model {}",
+                M::DATABASE,
+                M::NAME
+            )
+        })
+    }
+
+    /// Returns the connection pool of the database of a model, whose backend gives the dialect
+    /// of the queries of the model.
+    pub fn conn<M: Model>(&self) -> Result<&Connection> {
+        Ok(&self.database::<M>()?.conn)
+    }
+
+    /// Applies to every registered database its migrations and models, see `Database::migrate`.
+    ///
+    /// # Returns
+    /// The versions applied to each database, in registration order.
+    ///
+    /// # Errors
+    /// Fails when a migration or a model names a database that is not registered.
+    pub async fn migrate(&self, migrations: &Migrations) -> Result<Vec<(String, Vec<i64>)>> {
+        let unknown = migrations
+            .iter()
+            .map(|migration| migration.database.as_str())
+            .chain(migrations.models().map(|model| model.database))
+            .find(|name| self.get(name).is_none());
+        if let Some(name) = unknown {
+            return Err(anyhow!("no database named {name} is registered"));
+        }
+        let mut applied = Vec::new();
+        for (name, database) in &self.databases {
+            let versions = database.migrate(&migrations.for_database(name)).await?;
+            applied.push((name.clone(), versions));
+        }
+        Ok(applied)
+    }
+}
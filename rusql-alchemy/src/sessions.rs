@@ -0,0 +1,238 @@
+//! A `tower-sessions` store keeping the sessions in the `_rusql_sessions` table.
+//!
+//! The `Session` model is registered with `Migrations::model::<Session>()` to create the
+//! table, and `DatabaseSessionStore` is handed to the `SessionManagerLayer` of the web app. The
+//! data of a session is stored as JSON, with its expiry date as a unix timestamp; the expired
+//! sessions are never loaded and are removed by `ExpiredDeletion::delete_expired`.
+//!
+//! # Example
+//! ```rust
+//! let migrations = Migrations::new().model::<Session>();
+//! db.migrate(&migrations).await?;
+//!
+//! let store = DatabaseSessionStore::new(db.conn.clone());
+//! let app = Router::new()
+//!     .route("/", get(handler))
+//!     .layer(SessionManagerLayer::new(store));
+//! ```
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Row};
+use time::OffsetDateTime;
+use tower_sessions_core::session::{Id, Record};
+use tower_sessions_core::session_store::{self, ExpiredDeletion, SessionStore};
+
+use crate::db::backend::Backend;
+use crate::db::models::{Arg, Condition, Model};
+use crate::db::{instrument, QuerySettings};
+use crate::Connection;
+
+/// The table of the sessions.
+pub const SESSION_TABLE: &str = "_rusql_sessions";
+
+/// A session stored by `DatabaseSessionStore`.
+#[derive(Debug, Clone, Default, PartialEq, FromRow, Serialize, Deserialize)]
+pub struct Session {
+    /// The id of the session, as sent in the cookie.
+    pub id: String,
+    /// The data of the session, as JSON.
+    pub data: String,
+    /// The unix timestamp at which the session expires, in seconds.
+    pub expiry_date: i64,
+}
+
+#[async_trait]
+impl Model for Session {
+    const SCHEMA: &'static str = "create table if not exists _rusql_sessions (
+        id varchar(64) primary key,
+        data text not null,
+        expiry_date bigint not null
+    );";
+    const NAME: &'static str = SESSION_TABLE;
+    const PK: &'static str = "id";
+
+    async fn save(&self, conn: &Connection) -> bool {
+        let mut kw = vec![Condition::field("id", "=", self.id.clone())];
+        kw.extend(self.values());
+        Self::create(kw, conn).await
+    }
+
+    async fn update(&self, conn: &Connection) -> bool {
+        Self::set(self.id.clone(), self.values(), conn).await
+    }
+
+    async fn delete(&self, conn: &Connection) -> bool {
+        Self::delete_where(vec![Condition::field("id", "=", self.id.clone())])
+            .execute(conn)
+            .await
+            > 0
+    }
+}
+
+impl Session {
+    /// Returns the columns written by `update`, every one except the id.
+    fn values(&self) -> Vec<Condition> {
+        vec![
+            Condition::field("data", "=", self.data.clone()),
+            Condition::field("expiry_date", "=", self.expiry_date),
+        ]
+    }
+}
+
+/// A `SessionStore` keeping the sessions in the `_rusql_sessions` table.
+#[derive(Debug, Clone)]
+pub struct DatabaseSessionStore {
+    conn: Connection,
+}
+
+impl DatabaseSessionStore {
+    /// Creates a store on the connection pool.
+    pub fn new(conn: Connection) -> Self {
+        Self { conn }
+    }
+
+    /// Runs a statement of the store.
+    async fn execute(
+        &self,
+        op: &'static str,
+        query: &str,
+        args: Vec<Arg>,
+    ) -> session_store::Result<u64> {
+        let mut stream = sqlx::query(query);
+        binds!(args, stream);
+        let result = instrument(
+            &QuerySettings::of(&self.conn),
+            SESSION_TABLE,
+            op,
+            query,
+            stream.execute(&self.conn),
+        )
+        .await
+        .map_err(backend_error)?;
+        Ok(result.rows_affected())
+    }
+
+    /// Writes a record, replacing the session with the same id when `replace` is set and
+    /// leaving it untouched otherwise.
+    ///
+    /// # Returns
+    /// The number of written rows, `0` when the id was taken and `replace` is not set.
+    async fn write(&self, record: &Record, replace: bool) -> session_store::Result<u64> {
+        let backend = Backend::of(&self.conn);
+        let values = format!(
+            "({}, {}, {})",
+            backend.placeholder(1),
+            backend.placeholder(2),
+            backend.placeholder(3)
+        );
+        let query = match (backend, replace) {
+            (Backend::MySql, false) => {
+                format!(
+                    "insert ignore into {SESSION_TABLE} (id, data, expiry_date) values {values};"
+                )
+            }
+            (Backend::MySql, true) => format!(
+                "insert into {SESSION_TABLE} (id, data, expiry_date) values {values} \
+                 on duplicate key update data = values(data), expiry_date = values(expiry_date);"
+            ),
+            (_, false) => format!(
+                "insert into {SESSION_TABLE} (id, data, expiry_date) values {values} \
+                 on conflict (id) do nothing;"
+            ),
+            (_, true) => format!(
+                "insert into {SESSION_TABLE} (id, data, expiry_date) values {values} \
+                 on conflict (id) do update set data = excluded.data, \
+                 expiry_date = excluded.expiry_date;"
+            ),
+        };
+        let data = serde_json::to_string(&record.data)
+            .map_err(|err| session_store::Error::Encode(err.to_string()))?;
+        let args = vec![
+            Arg::from(record.id.to_string()),
+            Arg::from(data),
+            Arg::from(record.expiry_date.unix_timestamp()),
+        ];
+        let op = if replace { "save" } else { "create" };
+        self.execute(op, &query, args).await
+    }
+}
+
+#[async_trait]
+impl SessionStore for DatabaseSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        // a new id is drawn until it does not collide with an existing session
+        while self.write(record, false).await? == 0 {
+            record.id = Id::default();
+        }
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.write(record, true).await.map(|_| ())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let backend = Backend::of(&self.conn);
+        let query = format!(
+            "select data, expiry_date from {SESSION_TABLE} where id = {} and expiry_date > {};",
+            backend.placeholder(1),
+            backend.placeholder(2)
+        );
+        let args = vec![
+            Arg::from(session_id.to_string()),
+            Arg::from(OffsetDateTime::now_utc().unix_timestamp()),
+        ];
+        let mut stream = sqlx::query(&query);
+        binds!(args, stream);
+        let row = instrument(
+            &QuerySettings::of(&self.conn),
+            SESSION_TABLE,
+            "load",
+            &query,
+            stream.fetch_optional(&self.conn),
+        )
+        .await
+        .map_err(backend_error)?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let data: String = row.try_get(0).map_err(backend_error)?;
+        let expiry_date: i64 = row.try_get(1).map_err(backend_error)?;
+        Ok(Some(Record {
+            id: *session_id,
+            data: serde_json::from_str(&data)
+                .map_err(|err| session_store::Error::Decode(err.to_string()))?,
+            expiry_date: OffsetDateTime::from_unix_timestamp(expiry_date)
+                .map_err(|err| session_store::Error::Decode(err.to_string()))?,
+        }))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        let query = format!(
+            "delete from {SESSION_TABLE} where id = {};",
+            Backend::of(&self.conn).placeholder(1)
+        );
+        self.execute("delete", &query, vec![Arg::from(session_id.to_string())])
+            .await
+            .map(|_| ())
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for DatabaseSessionStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        let query = format!(
+            "delete from {SESSION_TABLE} where expiry_date <= {};",
+            Backend::of(&self.conn).placeholder(1)
+        );
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        self.execute("delete_expired", &query, vec![Arg::from(now)])
+            .await
+            .map(|_| ())
+    }
+}
+
+fn backend_error(err: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(err.to_string())
+}
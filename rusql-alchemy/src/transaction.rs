@@ -0,0 +1,156 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use sqlx::any::AnyRow;
+use sqlx::{Any, AnyConnection, Executor, FromRow};
+
+use crate::db::backend::Backend;
+use crate::db::models::Arg;
+use crate::db::{instrument, QuerySettings};
+use crate::utils::translate_placeholders;
+use crate::{Connection, Error};
+
+/// The future returned by the closure of `Database::transaction`.
+pub type TransactionFuture<'t, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 't>>;
+
+/// The isolation level of a transaction, see `Database::transaction_with_isolation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolationLevel {
+    ReadUncommitted,
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl IsolationLevel {
+    fn as_sql(self) -> &'static str {
+        match self {
+            IsolationLevel::ReadUncommitted => "read uncommitted",
+            IsolationLevel::ReadCommitted => "read committed",
+            IsolationLevel::RepeatableRead => "repeatable read",
+            IsolationLevel::Serializable => "serializable",
+        }
+    }
+}
+
+/// A transaction opened by `Database::transaction`.
+///
+/// The statements run with `execute` and `fetch` are committed together when the closure
+/// returns `Ok`, and rolled back when it returns an error.
+pub struct Transaction {
+    tx: sqlx::Transaction<'static, Any>,
+    backend: Backend,
+    settings: Arc<QuerySettings>,
+}
+
+impl Transaction {
+    /// Begins a transaction on a connection of the pool, with the given isolation level or
+    /// the default of the database.
+    ///
+    /// Sqlite transactions are always serializable, the level is ignored.
+    pub(crate) async fn begin(
+        conn: &Connection,
+        isolation: Option<IsolationLevel>,
+    ) -> Result<Self> {
+        let backend = Backend::of(conn);
+        let tx = match (isolation, backend) {
+            (Some(level), Backend::Postgres) => {
+                let mut tx = conn.begin().await?;
+                let sql = format!("set transaction isolation level {};", level.as_sql());
+                tx.execute(sql.as_str()).await?;
+                tx
+            }
+            // mysql sets the level of the next transaction of the session, so the transaction
+            // is started on that session
+            (Some(level), Backend::MySql) => {
+                let mut session = conn.acquire().await?;
+                let sql = format!("set transaction isolation level {};", level.as_sql());
+                session.execute(sql.as_str()).await?;
+                sqlx::Transaction::begin(session, None).await?
+            }
+            _ => conn.begin().await?,
+        };
+        Ok(Self {
+            tx,
+            backend,
+            settings: QuerySettings::of(conn),
+        })
+    }
+
+    /// Executes a raw statement in the transaction, see `Database::execute`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of rows affected by the statement.
+    pub async fn execute(&mut self, query: &str, args: Vec<Arg>) -> Result<u64> {
+        let query = translate_placeholders(query, self.backend);
+        let mut stream = sqlx::query(&query);
+        binds!(args.clone(), stream);
+        match instrument(
+            &self.settings,
+            "raw",
+            "execute",
+            &query,
+            stream.execute(&mut *self.tx),
+        )
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(err) => Err(Error::new(&self.settings, &query, args, err).into()),
+        }
+    }
+
+    /// Executes several `;`-separated statements without arguments in the transaction, see
+    /// `Database::execute_batch`.
+    pub async fn execute_batch(&mut self, sql: &str) -> Result<u64> {
+        let statement = sqlx::raw_sql(sql).execute(&mut *self.tx);
+        match instrument(&self.settings, "raw", "execute_batch", sql, statement).await {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(err) => Err(Error::new(&self.settings, sql, Vec::new(), err).into()),
+        }
+    }
+
+    /// Runs a raw query in the transaction and decodes every row into `T`, see
+    /// `Database::fetch`.
+    pub async fn fetch<T>(&mut self, query: &str, args: Vec<Arg>) -> Result<Vec<T>>
+    where
+        T: Send + Unpin + for<'r> FromRow<'r, AnyRow>,
+    {
+        let query = translate_placeholders(query, self.backend);
+        let mut stream = sqlx::query_as::<_, T>(&query);
+        binds!(args.clone(), stream);
+        instrument(
+            &self.settings,
+            "raw",
+            "fetch",
+            &query,
+            stream.fetch_all(&mut *self.tx),
+        )
+        .await
+        .map_err(|err| Error::new(&self.settings, &query, args, err).into())
+    }
+
+    /// Returns the connection of the transaction, to run `sqlx` queries in it.
+    pub fn conn(&mut self) -> &mut AnyConnection {
+        &mut self.tx
+    }
+
+    /// Returns the database of the transaction.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub(crate) fn settings(&self) -> Arc<QuerySettings> {
+        self.settings.clone()
+    }
+
+    pub(crate) async fn commit(self) -> Result<()> {
+        Ok(self.tx.commit().await?)
+    }
+
+    pub(crate) async fn rollback(self) -> Result<()> {
+        Ok(self.tx.rollback().await?)
+    }
+}
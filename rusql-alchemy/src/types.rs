@@ -1,22 +1,360 @@
+use crate::db::models::{Arg, Condition};
+
 #[cfg(feature = "postgres")]
 pub type Serial = i32;
 
 pub type Integer = i32;
 pub type Text = String;
 pub type Float = f64;
-pub type Date = String;
-pub type DateTime = String;
-pub type Boolean = i32;
+/// A calendar date, stored as `YYYY-MM-DD` text.
+///
+/// The `Default` value is today, so `..Default::default()` behaves like `default = "now"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date(pub chrono::NaiveDate);
+
+/// A UTC timestamp, stored as fixed-width RFC 3339 text (`2025-01-02T03:04:05.000000Z`) so
+/// that text comparisons in SQL follow chronological order.
+///
+/// The `Default` value is the current time, so `..Default::default()` behaves like
+/// `default = "now"`.
+///
+/// Both types are text on every backend: the sqlx `Any` driver only carries booleans, numbers,
+/// text and bytes, and cannot read the rows holding a native postgres or mysql date column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime(pub chrono::DateTime<chrono::Utc>);
+
+/// A time of day, stored as fixed-width `HH:MM:SS.ffffff` text so that text comparisons in SQL
+/// follow chronological order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time(pub chrono::NaiveTime);
+
+/// A length of time, stored as a `BIGINT` number of microseconds so that it can be compared and
+/// summed in SQL.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Interval(pub chrono::TimeDelta);
+
+impl Date {
+    /// Returns the current date in UTC.
+    pub fn today() -> Self {
+        Self(chrono::Utc::now().date_naive())
+    }
+}
+
+impl DateTime {
+    /// Returns the current time.
+    pub fn now() -> Self {
+        Self(chrono::Utc::now())
+    }
+
+    /// Returns the date of the timestamp, in UTC.
+    pub fn date(&self) -> Date {
+        Date(self.0.date_naive())
+    }
+}
+
+impl Default for Date {
+    fn default() -> Self {
+        Self::today()
+    }
+}
+
+impl Default for DateTime {
+    fn default() -> Self {
+        Self::now()
+    }
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.format("%Y-%m-%d"))
+    }
+}
+
+impl std::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.format("%Y-%m-%dT%H:%M:%S%.6fZ"))
+    }
+}
+
+impl std::str::FromStr for Date {
+    type Err = chrono::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").map(Self)
+    }
+}
+
+impl std::str::FromStr for DateTime {
+    type Err = chrono::ParseError;
+
+    /// Parses RFC 3339 text, or the `YYYY-MM-DD HH:MM:SS` UTC text written by the sqlite
+    /// `CURRENT_TIMESTAMP` default.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match chrono::DateTime::parse_from_rfc3339(s) {
+            Ok(at) => Ok(Self(at.to_utc())),
+            Err(err) => chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")
+                .map(|at| Self(at.and_utc()))
+                .map_err(|_| err),
+        }
+    }
+}
+
+impl_text_type!(Date);
+
+impl_text_type!(DateTime);
+
+impl From<chrono::NaiveDate> for Date {
+    fn from(value: chrono::NaiveDate) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Date> for chrono::NaiveDate {
+    fn from(value: Date) -> Self {
+        value.0
+    }
+}
+
+impl From<chrono::DateTime<chrono::Utc>> for DateTime {
+    fn from(value: chrono::DateTime<chrono::Utc>) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DateTime> for chrono::DateTime<chrono::Utc> {
+    fn from(value: DateTime) -> Self {
+        value.0
+    }
+}
+
+impl Time {
+    /// Returns the current time of day in UTC.
+    pub fn now() -> Self {
+        Self(chrono::Utc::now().time())
+    }
+}
+
+impl Interval {
+    /// Returns the interval as a number of microseconds, the value stored in the database.
+    pub fn as_micros(&self) -> i64 {
+        self.0.num_microseconds().unwrap_or(i64::MAX)
+    }
+
+    /// Returns an interval of `micros` microseconds.
+    pub fn from_micros(micros: i64) -> Self {
+        Self(chrono::TimeDelta::microseconds(micros))
+    }
+}
+
+impl From<chrono::TimeDelta> for Interval {
+    fn from(value: chrono::TimeDelta) -> Self {
+        Self(value)
+    }
+}
+
+impl From<std::time::Duration> for Interval {
+    fn from(value: std::time::Duration) -> Self {
+        Self(chrono::TimeDelta::from_std(value).unwrap_or(chrono::TimeDelta::MAX))
+    }
+}
+
+impl std::fmt::Display for Time {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.format("%H:%M:%S%.6f"))
+    }
+}
+
+impl std::str::FromStr for Time {
+    type Err = chrono::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        chrono::NaiveTime::parse_from_str(s, "%H:%M:%S%.f").map(Self)
+    }
+}
+
+impl_text_type!(Time);
+
+impl From<Interval> for Arg {
+    fn from(value: Interval) -> Self {
+        Arg::Int(value.as_micros())
+    }
+}
+
+impl serde::Serialize for Interval {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.as_micros())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Interval {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        i64::deserialize(deserializer).map(Self::from_micros)
+    }
+}
+
+impl sqlx::Type<sqlx::Any> for Interval {
+    fn type_info() -> sqlx::any::AnyTypeInfo {
+        <i64 as sqlx::Type<sqlx::Any>>::type_info()
+    }
+
+    fn compatible(ty: &sqlx::any::AnyTypeInfo) -> bool {
+        <i64 as sqlx::Type<sqlx::Any>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, sqlx::Any> for Interval {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <sqlx::Any as sqlx::Database>::ArgumentBuffer<'q>,
+    ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+        <i64 as sqlx::Encode<'q, sqlx::Any>>::encode(self.as_micros(), buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, sqlx::Any> for Interval {
+    fn decode(value: sqlx::any::AnyValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+        <i64 as sqlx::Decode<'r, sqlx::Any>>::decode(value).map(Self::from_micros)
+    }
+}
+
+/// A boolean column: `BOOLEAN` on postgres and mysql, `INTEGER` holding `0`/`1` on sqlite.
+///
+/// It is bound as a real boolean and decodes both boolean and integer columns.
+///
+/// `Boolean` used to be an alias of `i32`. To ease the upgrade it still converts from and to
+/// `i32`, compares with integers (`0` is false, any other value true) and deserializes from
+/// `0`/`1`, so `Boolean::from(1)` and `active == 1` keep working; prefer `true`/`false` in new
+/// code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Boolean(pub bool);
+
+impl From<bool> for Boolean {
+    fn from(value: bool) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Boolean> for bool {
+    fn from(value: Boolean) -> Self {
+        value.0
+    }
+}
+
+impl PartialEq<bool> for Boolean {
+    fn eq(&self, other: &bool) -> bool {
+        self.0 == *other
+    }
+}
+
+/// `0` is false, any other value true, as when `Boolean` was an `i32`.
+impl From<i32> for Boolean {
+    fn from(value: i32) -> Self {
+        Self(value != 0)
+    }
+}
+
+impl From<Boolean> for i32 {
+    fn from(value: Boolean) -> Self {
+        value.0 as i32
+    }
+}
+
+impl PartialEq<i32> for Boolean {
+    fn eq(&self, other: &i32) -> bool {
+        self.0 == (*other != 0)
+    }
+}
+
+impl std::ops::Not for Boolean {
+    type Output = Boolean;
+
+    fn not(self) -> Self::Output {
+        Self(!self.0)
+    }
+}
+
+impl From<Boolean> for Arg {
+    fn from(value: Boolean) -> Self {
+        Arg::Bool(value.0)
+    }
+}
+
+impl serde::Serialize for Boolean {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bool(self.0)
+    }
+}
+
+/// Deserializes from a boolean, or from an integer as written when `Boolean` was an `i32`.
+impl<'de> serde::Deserialize<'de> for Boolean {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BooleanVisitor;
+
+        impl serde::de::Visitor<'_> for BooleanVisitor {
+            type Value = Boolean;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a boolean or an integer")
+            }
+
+            fn visit_bool<E: serde::de::Error>(self, value: bool) -> Result<Boolean, E> {
+                Ok(Boolean(value))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Boolean, E> {
+                Ok(Boolean(value != 0))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Boolean, E> {
+                Ok(Boolean(value != 0))
+            }
+        }
+
+        deserializer.deserialize_any(BooleanVisitor)
+    }
+}
+
+impl sqlx::Type<sqlx::Any> for Boolean {
+    fn type_info() -> sqlx::any::AnyTypeInfo {
+        <bool as sqlx::Type<sqlx::Any>>::type_info()
+    }
+
+    fn compatible(ty: &sqlx::any::AnyTypeInfo) -> bool {
+        <bool as sqlx::Type<sqlx::Any>>::compatible(ty)
+            || <i64 as sqlx::Type<sqlx::Any>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, sqlx::Any> for Boolean {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <sqlx::Any as sqlx::Database>::ArgumentBuffer<'q>,
+    ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+        <bool as sqlx::Encode<'q, sqlx::Any>>::encode(self.0, buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, sqlx::Any> for Boolean {
+    fn decode(value: sqlx::any::AnyValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+        // sqlite stores booleans in integer columns
+        match <bool as sqlx::Decode<'r, sqlx::Any>>::decode(value.clone()) {
+            Ok(value) => Ok(Self(value)),
+            Err(_) => Ok(Self(
+                <i64 as sqlx::Decode<'r, sqlx::Any>>::decode(value)? != 0,
+            )),
+        }
+    }
+}
 
 pub trait True {
-    fn r#true() -> i32 {
-        1
+    fn r#true() -> Boolean {
+        Boolean(true)
     }
 }
 
 pub trait False {
-    fn r#false() -> i32 {
-        0
+    fn r#false() -> Boolean {
+        Boolean(false)
     }
 }
 
@@ -30,6 +368,523 @@ impl False for Boolean {}
 
 impl IsTrue for Boolean {
     fn is_true(&self) -> bool {
-        *self == 1
+        self.0
+    }
+}
+
+/// A Rust enum stored in a column as the name of its variant, implemented by `sql_enum!`.
+pub trait SqlEnum: Sized {
+    /// The names of the variants, in declaration order.
+    const VARIANTS: &'static [&'static str];
+
+    /// Returns the name of the variant, as stored in the database.
+    fn as_str(&self) -> &'static str;
+
+    /// Returns the variant with the given name.
+    fn from_variant(name: &str) -> Option<Self>;
+
+    /// Returns the `CHECK` constraint restricting `column` to the variant names.
+    ///
+    /// # Example
+    /// ```
+    /// assert_eq!(Role::check("role"), "check (role in ('Admin', 'User'))");
+    /// ```
+    fn check(column: &str) -> String {
+        let variants = Self::VARIANTS
+            .iter()
+            .map(|variant| format!("'{variant}'"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("check ({column} in ({variants}))")
+    }
+}
+
+/// A UUID, stored as `char(36)` text so it can go through the `Any` driver on every backend.
+///
+/// The columns listed in `Model::UUID_DEFAULTS` get a random v4 UUID when the row is created
+/// without one.
+///
+/// # Example
+/// ```rust
+/// impl Model for Session {
+///     const UUID_DEFAULTS: &'static [&'static str] = &["id"];
+///     // SCHEMA, NAME, PK, save, update and delete, see `Model`
+/// }
+/// ```
+#[cfg(feature = "uuid")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uuid(pub uuid::Uuid);
+
+#[cfg(feature = "uuid")]
+impl Uuid {
+    /// Generates a random (v4) UUID.
+    pub fn new_v4() -> Self {
+        Self(uuid::Uuid::new_v4())
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl std::fmt::Display for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.hyphenated().fmt(f)
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl std::str::FromStr for Uuid {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        uuid::Uuid::parse_str(s).map(Self)
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl_text_type!(Uuid);
+
+/// A password, stored as its argon2id hash in the PHC string format
+/// (`$argon2id$v=19$m=19456,t=2,p=1$...`).
+///
+/// Converting a plain password with `Password::from` or `.into()` hashes it with a random salt,
+/// so assigning the field or binding it in `kwargs!` never stores the plain text. The rows read
+/// back hold the hash, checked with `verify`. Since every hash has its own salt, a password
+/// cannot be filtered on: get the row by another column, then verify the password.
+///
+/// # Example
+/// ```rust
+/// User::create(kwargs!(name = "joe", password = Password::from("p455w0rd")), &conn).await;
+///
+/// let user = User::get(kwargs!(name == "joe"), &conn).await;
+/// let logged_in = user.is_some_and(|user| user.password.verify("p455w0rd"));
+/// ```
+#[cfg(feature = "password")]
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Password(String);
+
+#[cfg(feature = "password")]
+impl Password {
+    /// Hashes a plain password with argon2id and a random salt.
+    pub fn hash(plain: &str) -> Self {
+        use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = argon2::Argon2::default()
+            .hash_password(plain.as_bytes(), &salt)
+            .expect("the default argon2 parameters are valid");
+        Self(hash.to_string())
+    }
+
+    /// Returns `true` if `plain` is the password, `false` otherwise or when it is unset.
+    pub fn verify(&self, plain: &str) -> bool {
+        use argon2::password_hash::{PasswordHash, PasswordVerifier};
+
+        PasswordHash::new(&self.0).is_ok_and(|hash| {
+            argon2::Argon2::default()
+                .verify_password(plain.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
+    /// Returns the stored hash, empty when the password is unset.
+    pub fn as_hash(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(feature = "password")]
+impl From<&str> for Password {
+    fn from(plain: &str) -> Self {
+        Self::hash(plain)
+    }
+}
+
+#[cfg(feature = "password")]
+impl From<String> for Password {
+    fn from(plain: String) -> Self {
+        Self::hash(&plain)
+    }
+}
+
+/// Leaves the hash out, so logging a model does not expose it.
+#[cfg(feature = "password")]
+impl std::fmt::Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password(***)")
+    }
+}
+
+#[cfg(feature = "password")]
+impl std::fmt::Display for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Parses a stored hash, the plain passwords are converted with `Password::from`.
+#[cfg(feature = "password")]
+impl std::str::FromStr for Password {
+    type Err = argon2::password_hash::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(Self::default());
+        }
+        argon2::password_hash::PasswordHash::new(s)?;
+        Ok(Self(s.to_string()))
+    }
+}
+
+#[cfg(feature = "password")]
+impl_text_type!(Password);
+
+/// An IPv4 or IPv6 address, stored as text so it can go through the `Any` driver on every
+/// backend.
+///
+/// Addresses are written in their canonical form, so equality works with `kwargs!` and
+/// network prefixes can be matched with `inet_prefix`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Inet(pub std::net::IpAddr);
+
+impl Default for Inet {
+    fn default() -> Self {
+        Self(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED))
+    }
+}
+
+impl From<std::net::IpAddr> for Inet {
+    fn from(value: std::net::IpAddr) -> Self {
+        Self(value)
+    }
+}
+
+impl std::fmt::Display for Inet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::str::FromStr for Inet {
+    type Err = std::net::AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+impl_text_type!(Inet);
+
+/// A MAC address, stored as lowercase `aa:bb:cc:dd:ee:ff` text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl std::fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+impl std::str::FromStr for MacAddr {
+    type Err = String;
+
+    /// Parses six hexadecimal octets separated by `:` or `-`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid MAC address: {s}");
+        let mut octets = [0; 6];
+        let mut parts = s.split([':', '-']);
+        for octet in octets.iter_mut() {
+            let part = parts.next().ok_or_else(invalid)?;
+            if part.len() != 2 {
+                return Err(invalid());
+            }
+            *octet = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        match parts.next() {
+            Some(_) => Err(invalid()),
+            None => Ok(Self(octets)),
+        }
+    }
+}
+
+impl_text_type!(MacAddr);
+
+/// Creates a condition matching the `Inet` addresses of a column that start with a text prefix,
+/// e.g. `"10.0."` for the `10.0.0.0/16` network. The `%` and `_` of the prefix are matched
+/// literally.
+///
+/// # Example
+/// ```
+/// let internal = AuditLog::filter(inet_prefix("remote_addr", "10.0."), &conn).await;
+/// ```
+pub fn inet_prefix(column: &str, prefix: &str) -> Vec<Condition> {
+    vec![Condition::field(column.to_string(), "starts_with", prefix)]
+}
+
+/// Structured data stored as JSON text.
+///
+/// The value is serialized with serde on insert and update and deserialized on fetch. It is
+/// stored as text on every backend, since the `Any` driver cannot decode postgres `jsonb`.
+///
+/// # Example
+/// ```
+/// #[derive(FromRow, Clone, Debug, Default, Model)]
+/// struct Event {
+///     #[model(primary_key = true, auto = true)]
+///     id: Integer,
+///     payload: Json<Payload>,
+/// }
+///
+/// let events = Event::filter(json_extract("payload", "kind", "=", "click"), &conn).await;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T> std::ops::Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> std::ops::DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T: serde::Serialize> From<Json<T>> for Arg {
+    fn from(value: Json<T>) -> Self {
+        Arg::Text(serde_json::to_string(&value.0).unwrap_or_default())
+    }
+}
+
+impl<T: serde::Serialize> serde::Serialize for Json<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Json<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Json)
+    }
+}
+
+impl<T> sqlx::Type<sqlx::Any> for Json<T> {
+    fn type_info() -> sqlx::any::AnyTypeInfo {
+        <String as sqlx::Type<sqlx::Any>>::type_info()
+    }
+}
+
+impl<'q, T: serde::Serialize> sqlx::Encode<'q, sqlx::Any> for Json<T> {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <sqlx::Any as sqlx::Database>::ArgumentBuffer<'q>,
+    ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+        <String as sqlx::Encode<'q, sqlx::Any>>::encode(serde_json::to_string(&self.0)?, buf)
+    }
+}
+
+impl<'r, T: serde::de::DeserializeOwned> sqlx::Decode<'r, sqlx::Any> for Json<T> {
+    fn decode(value: sqlx::any::AnyValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+        let text = <String as sqlx::Decode<'r, sqlx::Any>>::decode(value)?;
+        Ok(Json(serde_json::from_str(&text)?))
+    }
+}
+
+/// Creates a condition on a value inside a JSON column, e.g. `payload.user.name`.
+///
+/// The path is a dot-separated list of keys. It is read with `json_extract` on sqlite and
+/// mysql, and with the `#>>` operator on postgres, where the extracted value is text.
+///
+/// # Example
+/// ```
+/// let events = Event::filter(json_extract("payload", "user.name", "=", "joe"), &conn).await;
+/// ```
+pub fn json_extract(
+    column: &str,
+    path: &str,
+    comparison_operator: &str,
+    value: impl Into<Arg>,
+) -> Vec<Condition> {
+    let path = path.replace('\'', "''");
+    let value = value.into();
+    let extracted = vec![Condition::field(
+        format!("json_extract({column}, '$.{path}')"),
+        comparison_operator,
+        value.clone(),
+    )];
+    vec![Condition::ByBackend {
+        sqlite: extracted.clone(),
+        postgres: vec![Condition::field(
+            format!("({column}::jsonb #>> '{{{}}}')", path.replace('.', ",")),
+            comparison_operator,
+            value,
+        )],
+        mysql: extracted,
+    }]
+}
+
+/// The mean radius of the earth, in meters.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// A geographic point, stored as `POINT(lon lat)` WKT text so it can go through the `Any` driver
+/// on every backend.
+///
+/// The text is read by PostGIS and the spatial functions of mysql, see `within_radius`.
+///
+/// # Example
+/// ```
+/// let shop = Shop {
+///     location: Point::new(48.8584, 2.2945),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Point {
+    /// The latitude, in degrees.
+    pub lat: f64,
+    /// The longitude, in degrees.
+    pub lon: f64,
+}
+
+impl Point {
+    /// Creates a point from its latitude and longitude, in degrees.
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+
+    /// Returns the great-circle distance to `other` in meters, with the haversine formula.
+    pub fn distance(&self, other: &Point) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let half_lat = (lat2 - lat1) / 2.0;
+        let half_lon = (other.lon - self.lon).to_radians() / 2.0;
+        let a = half_lat.sin().powi(2) + lat1.cos() * lat2.cos() * half_lon.sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+}
+
+impl std::fmt::Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "POINT({} {})", self.lon, self.lat)
+    }
+}
+
+impl std::str::FromStr for Point {
+    type Err = String;
+
+    /// Parses `POINT(lon lat)`, with an optional `SRID=4326;` prefix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid point: {s}");
+        let wkt = s.rsplit(';').next().unwrap_or(s).trim();
+        let coordinates = wkt
+            .get(..5)
+            .filter(|tag| tag.eq_ignore_ascii_case("point"))
+            .and_then(|_| wkt[5..].trim().strip_prefix('('))
+            .and_then(|rest| rest.strip_suffix(')'))
+            .ok_or_else(invalid)?;
+        let mut parts = coordinates.split_whitespace().map(str::parse::<f64>);
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(lon)), Some(Ok(lat)), None) => Ok(Self { lat, lon }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl_text_type!(Point);
+
+/// A geometry, e.g. a line or a polygon, stored as WKT text such as
+/// `POLYGON((2.29 48.85, 2.30 48.85, 2.30 48.86, 2.29 48.85))`.
+///
+/// The text is kept as written, it can be passed to the PostGIS and mysql spatial functions in
+/// raw statements.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Geometry(pub String);
+
+impl std::fmt::Display for Geometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::str::FromStr for Geometry {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_string()))
     }
-}
\ No newline at end of file
+}
+
+impl_text_type!(Geometry);
+
+/// Creates a condition matching the `Point` columns within `meters` of a location.
+///
+/// Postgres uses `ST_DWithin` on geographies, which needs the PostGIS extension, and mysql
+/// `ST_Distance_Sphere`. The sqlite library bundled with sqlx has no trigonometric functions,
+/// so the distance is approximated on the plane tangent at the location: the error stays below
+/// 1% for radii up to a few hundred kilometers, away from the poles and the antimeridian.
+/// `Point::distance` gives the exact distance of the returned rows.
+///
+/// # Example
+/// ```
+/// let nearby = Shop::filter(within_radius("location", 48.8584, 2.2945, 500.0), &conn).await;
+/// ```
+pub fn within_radius(column: &str, lat: f64, lon: f64, meters: f64) -> Vec<Condition> {
+    let center = Point::new(lat, lon);
+    let meters_per_degree = EARTH_RADIUS.to_radians();
+    let lon_column = format!("cast(substr({column}, 7, instr({column}, ' ') - 7) as real)");
+    let lat_column = format!("cast(substr({column}, instr({column}, ' ') + 1) as real)");
+    let dx = format!(
+        "(({lon_column} - {lon}) * {})",
+        meters_per_degree * lat.to_radians().cos()
+    );
+    let dy = format!("(({lat_column} - {lat}) * {meters_per_degree})");
+    vec![Condition::ByBackend {
+        sqlite: vec![Condition::field(
+            format!("({dx} * {dx} + {dy} * {dy})"),
+            " <= ",
+            meters * meters,
+        )],
+        postgres: vec![Condition::field(
+            format!("ST_DWithin(ST_GeogFromText({column}), ST_GeogFromText('{center}'), {meters})"),
+            "=",
+            true,
+        )],
+        mysql: vec![Condition::field(
+            format!("ST_Distance_Sphere(ST_GeomFromText({column}), ST_GeomFromText('{center}'))"),
+            " <= ",
+            meters,
+        )],
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::backend::Backend;
+    use crate::db::models::Query;
+
+    #[test]
+    fn writes_fixed_width_timestamps() {
+        let at: DateTime = "2025-01-02T05:04:05.5+02:00".parse().unwrap();
+        assert_eq!(at.to_string(), "2025-01-02T03:04:05.500000Z");
+        assert_eq!(at.date().to_string(), "2025-01-02");
+        assert_eq!(at.to_string().parse::<DateTime>().unwrap(), at);
+    }
+
+    #[test]
+    fn reads_the_sqlite_current_timestamp() {
+        let at: DateTime = "2025-01-02 03:04:05".parse().unwrap();
+        assert_eq!(at.to_string(), "2025-01-02T03:04:05.000000Z");
+        assert!("2025-02-30".parse::<Date>().is_err());
+        assert!("2025-01-02 25:00:00".parse::<DateTime>().is_err());
+    }
+
+    #[test]
+    fn matches_the_inet_prefix_literally() {
+        let (sql, args) = inet_prefix("addr", "10_0.").to_select_query(Backend::Sqlite);
+        assert_eq!(sql, "addr like ?1 escape '!'");
+        assert_eq!(args, vec![Arg::from("10!_0.%")]);
+    }
+}
@@ -1,5 +1,8 @@
 use std::{any::type_name, io::Error};
 
+use crate::db::backend::Backend;
+use crate::db::models::placeholder_prefix;
+
 /// Returns the name of the type `T` as a string.
 ///
 /// # Arguments
@@ -20,7 +23,7 @@ pub fn get_type_name<T: Sized>(_: T) -> &'static str {
     type_name::<T>()
 }
 
-/// Retrieves a placeholder for SQL queries based on the `DATABASE_URL` environment variable.
+/// Retrieves the placeholder prefix of the database of the `DATABASE_URL` environment variable.
 ///
 /// # Returns
 ///
@@ -31,27 +34,17 @@ pub fn get_type_name<T: Sized>(_: T) -> &'static str {
 /// # Errors
 ///
 /// Returns an `std::io::Error` if the `DATABASE_URL` is not found or if the database type is unsupported.
-///
-/// # Example
-///
-/// ```
-/// std::env::set_var("DATABASE_URL", "sqlite://database.db");
-/// let placeholder = get_placeholder().unwrap();
-/// assert_eq!(placeholder, "?");
-/// ```
+#[deprecated(note = "use `Backend::placeholder` with the backend of the connection")]
 pub fn get_placeholder() -> std::io::Result<&'static str> {
     let database_url = std::env::var("DATABASE_URL").map_err(|_| {
         std::io::Error::new(std::io::ErrorKind::NotFound, "DATABASE_URL is not found")
     })?;
-    if database_url.starts_with("sqlite") || database_url.starts_with("mysql") {
-        Ok("?")
-    } else if database_url.starts_with("postgres") {
-        Ok("$")
-    } else {
-        Err(Error::new(
+    match Backend::from_url(&database_url) {
+        Some(backend) => Ok(placeholder_prefix(backend)),
+        None => Err(Error::new(
             std::io::ErrorKind::InvalidData,
             "Unsupported database type",
-        ))
+        )),
     }
 }
 
@@ -84,3 +77,163 @@ pub fn to_string(value: impl Into<serde_json::Value>) -> String {
     }
     .to_string()
 }
+
+/// Replaces the `?` placeholders of a raw query with the placeholders of `backend`, see
+/// `Backend::placeholder`.
+///
+/// Question marks inside quoted strings and identifiers and inside comments are left untouched.
+/// On postgres, `??` is a literal `?` for the `?` jsonb operator, and the `?|` and `?&` jsonb
+/// operators are left untouched. The other databases have no `?` operator, so every other `?`
+/// is a placeholder there.
+///
+/// # Example
+///
+/// ```
+/// let query = translate_placeholders(
+///     "select * from user where name = ? and role = 'a?' and tags ?? 'admin'",
+///     Backend::Postgres,
+/// );
+/// assert_eq!(query, "select * from user where name = $1 and role = 'a?' and tags ? 'admin'");
+/// ```
+pub(crate) fn translate_placeholders(query: &str, backend: Backend) -> String {
+    let mut translated = String::with_capacity(query.len());
+    let mut quote = None;
+    let mut chars = query.chars().peekable();
+    let mut index = 0;
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {}
+            ('\'' | '"' | '`', None) => quote = Some(c),
+            ('-', None) if chars.peek() == Some(&'-') => {
+                translated.push(c);
+                for c in chars.by_ref() {
+                    translated.push(c);
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            ('/', None) if chars.peek() == Some(&'*') => {
+                translated.push(c);
+                translated.extend(chars.next());
+                let mut previous = None;
+                for c in chars.by_ref() {
+                    translated.push(c);
+                    if previous == Some('*') && c == '/' {
+                        break;
+                    }
+                    previous = Some(c);
+                }
+                continue;
+            }
+            ('?', None) if backend == Backend::Postgres && chars.peek() == Some(&'?') => {
+                chars.next();
+            }
+            ('?', None) if backend == Backend::Postgres && jsonb_operator(chars.clone()) => {
+                translated.push(c);
+                translated.extend(chars.next());
+                continue;
+            }
+            ('?', None) => {
+                index += 1;
+                translated.push_str(&backend.placeholder(index));
+                continue;
+            }
+            _ => {}
+        }
+        translated.push(c);
+    }
+    translated
+}
+
+/// Whether a `?` followed by `rest` is the `?|` or `?&` jsonb operator, `?||` being a
+/// placeholder followed by the concatenation operator.
+fn jsonb_operator(mut rest: impl Iterator<Item = char>) -> bool {
+    match rest.next() {
+        Some('&') => true,
+        Some('|') => rest.next() != Some('|'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_placeholders_for_each_backend() {
+        let query = "select * from user where name = ? and age >= ?";
+        assert_eq!(
+            translate_placeholders(query, Backend::Sqlite),
+            "select * from user where name = ?1 and age >= ?2"
+        );
+        assert_eq!(
+            translate_placeholders(query, Backend::Postgres),
+            "select * from user where name = $1 and age >= $2"
+        );
+        assert_eq!(translate_placeholders(query, Backend::MySql), query);
+    }
+
+    #[test]
+    fn leaves_quoted_question_marks() {
+        assert_eq!(
+            translate_placeholders(
+                "select * from \"a?\" where b = 'c?' and `d?` = ?",
+                Backend::Postgres
+            ),
+            "select * from \"a?\" where b = 'c?' and `d?` = $1"
+        );
+        assert_eq!(
+            translate_placeholders("select 'it''s ?' where a = ?", Backend::Postgres),
+            "select 'it''s ?' where a = $1"
+        );
+    }
+
+    #[test]
+    fn leaves_comments() {
+        assert_eq!(
+            translate_placeholders(
+                "select * from user -- why?\nwhere a = ? /* and b = ? */ and c = ?",
+                Backend::Postgres
+            ),
+            "select * from user -- why?\nwhere a = $1 /* and b = ? */ and c = $2"
+        );
+        assert_eq!(
+            translate_placeholders("select 1 -- trailing?", Backend::Sqlite),
+            "select 1 -- trailing?"
+        );
+    }
+
+    #[test]
+    fn keeps_the_jsonb_operators_of_postgres() {
+        assert_eq!(
+            translate_placeholders(
+                "select * from doc where tags ?? ? and tags ?| ? and tags ?& ?",
+                Backend::Postgres
+            ),
+            "select * from doc where tags ? $1 and tags ?| $2 and tags ?& $3"
+        );
+        assert_eq!(
+            translate_placeholders("select ?||'x'", Backend::Postgres),
+            "select $1||'x'"
+        );
+        assert_eq!(
+            translate_placeholders("select ?|1", Backend::Sqlite),
+            "select ?1|1"
+        );
+    }
+
+    #[test]
+    fn reads_a_double_question_mark_as_two_placeholders_outside_of_postgres() {
+        assert_eq!(
+            translate_placeholders("select ??", Backend::Sqlite),
+            "select ?1?2"
+        );
+        assert_eq!(
+            translate_placeholders("select ??", Backend::MySql),
+            "select ??"
+        );
+    }
+}